@@ -0,0 +1,78 @@
+;; Functions with several results, and blocks that take parameters, from
+;; the multi-value proposal.
+
+(module
+  (type $pair (func (result i32 i64)))
+
+  (func (export "multi") (result i32 i64 f32)
+    (i32.const 1) (i64.const 2) (f32.const 3))
+
+  (func $swap (param i32 i64) (result i64 i32)
+    (local.get 1) (local.get 0))
+  (func (export "swap") (param i32 i64) (result i64 i32)
+    (call $swap (local.get 0) (local.get 1)))
+  (func $dup (param i32) (result i32 i32)
+    (local.get 0) (local.get 0))
+  (func (export "square") (param i32) (result i32)
+    (i32.mul (call $dup (local.get 0))))
+
+  (func (export "block-type") (result i32 i64)
+    (block (type $pair) (i32.const 7) (i64.const 8)))
+  (func (export "block-params") (param i32) (result i32)
+    (local.get 0) (i32.const 2)
+    (block (param i32 i32) (result i32) (i32.mul)))
+  (func (export "block-br") (result i32 i32)
+    (block (result i32 i32)
+      (i32.const 1) (i32.const 2) (br 0) (i32.const 3) (i32.const 4)))
+
+  ;; The sum of 1..n, passing the counter from one iteration to the next.
+  (func (export "sum") (param i32) (result i32) (local i32)
+    (local.get 0)
+    (loop (param i32) (result i32)
+      (local.tee 0)
+      (local.get 1) (i32.add) (local.set 1)
+      (local.get 0) (i32.const 1) (i32.sub)
+      (local.tee 0) (local.get 0) (br_if 0)
+      (drop) (local.get 1)))
+
+  (func (export "if-params") (param i32) (result i32)
+    (i32.const 10) (i32.const 3)
+    (if (param i32 i32) (result i32) (local.get 0)
+      (then (i32.add))
+      (else (i32.sub))))
+)
+
+(assert_return (invoke "multi") (i32.const 1) (i64.const 2) (f32.const 3))
+(assert_return (invoke "swap" (i32.const 1) (i64.const 2)) (i64.const 2) (i32.const 1))
+(assert_return (invoke "square" (i32.const 9)) (i32.const 81))
+(assert_return (invoke "block-type") (i32.const 7) (i64.const 8))
+(assert_return (invoke "block-params" (i32.const 21)) (i32.const 42))
+(assert_return (invoke "block-br") (i32.const 1) (i32.const 2))
+(assert_return (invoke "sum" (i32.const 1)) (i32.const 1))
+(assert_return (invoke "sum" (i32.const 100)) (i32.const 5050))
+(assert_return (invoke "if-params" (i32.const 1)) (i32.const 13))
+(assert_return (invoke "if-params" (i32.const 0)) (i32.const 7))
+
+;; (func (export "f") (result i32 i32) (block (type 0) (i32.const 1) (i32.const 2)))
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\01\06\01\60\00\02\7f\7f"
+  "\03\02\01\00"
+  "\07\05\01\01f\00\00"
+  "\0a\0b\01\09\00\02\00\41\01\41\02\0b\0b"
+)
+
+(assert_return (invoke "f") (i32.const 1) (i32.const 2))
+
+(assert_invalid
+  (module (func (result i32 i32) (i32.const 1)))
+  "type mismatch")
+(assert_invalid
+  (module (func (block (param i32) (drop))))
+  "type mismatch")
+(assert_invalid
+  (module (func (result i32) (block (result i32 i32) (i32.const 1) (i32.const 2))))
+  "type mismatch")
+(assert_invalid
+  (module (func (i32.const 0) (loop (param i32) (i64.const 1) (br 0))))
+  "type mismatch")
@@ -69,25 +69,50 @@ pub fn read_vari7(rd: &mut untrusted::Reader) -> Result<i8, CodecError> {
 }
 
 pub fn read_varu32(rd: &mut untrusted::Reader) -> Result<u32, CodecError> {
-    Ok(read_varu(rd, 5)? as u32)
+    let v = read_varu(rd, 5)?;
+    if v > u32::MAX as u64 {
+        return Err(CodecError::BadVarInt);
+    }
+    Ok(v as u32)
 }
 
-pub fn read_vari32(rd: &mut untrusted::Reader) -> Result<i32, CodecError> {
-    let v = read_varu32(rd)?;
-    if v & 0x4000_0000 != 0 {
-        Ok(((v & 0x3fff_ffff) as i32) - 0x4000_0000)
-    } else {
-        Ok(v as i32)
+/// Read a signed LEB128 integer of at most `bits` bits.
+fn read_vari(rd: &mut untrusted::Reader, bits: u32) -> Result<i64, CodecError> {
+    let max_len = bits.div_ceil(7);
+    let mut r = 0i128;
+    let mut shift = 0;
+
+    for _ in 0..max_len {
+        let b = read_u8(rd)?;
+        r |= ((b & 0x7f) as i128) << shift;
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            if b & 0x40 != 0 {
+                r |= -1i128 << shift;
+            }
+
+            let limit = 1i128 << (bits - 1);
+            if r < -limit || r >= limit {
+                return Err(CodecError::BadVarInt);
+            }
+            return Ok(r as i64);
+        }
     }
+    Err(CodecError::BadVarInt)
+}
+
+pub fn read_vari32(rd: &mut untrusted::Reader) -> Result<i32, CodecError> {
+    Ok(read_vari(rd, 32)? as i32)
+}
+
+/// Block types are signed 33-bit, so they can hold any `u32` type index.
+pub fn read_vari33(rd: &mut untrusted::Reader) -> Result<i64, CodecError> {
+    read_vari(rd, 33)
 }
 
 pub fn read_vari64(rd: &mut untrusted::Reader) -> Result<i64, CodecError> {
-    let v = read_varu(rd, 10)?;
-    if v & 0x4000_0000_0000_0000 != 0 {
-        Ok(((v & 0x3fff_ffff_ffff_ffff) as i64) - 0x4000_0000_0000_0000)
-    } else {
-        Ok(v as i64)
-    }
+    read_vari(rd, 64)
 }
 
 pub fn read_utf8<'a>(rd: &mut untrusted::Reader<'a>, len: usize) -> Result<&'a str, CodecError> {
@@ -97,9 +122,147 @@ pub fn read_utf8<'a>(rd: &mut untrusted::Reader<'a>, len: usize) -> Result<&'a s
                   .map_err(|_| CodecError::BadUTF8))
 }
 
+/// Read with `read`, also returning how many bytes it consumed.
+pub fn measured<'a, F, R>(rd: &mut untrusted::Reader<'a>, read: F) -> Result<(R, usize), CodecError>
+    where F: FnOnce(&mut untrusted::Reader<'a>) -> Result<R, CodecError>
+{
+    let start = rd.mark();
+    let value = read(rd)?;
+    let len = rd.get_input_between_marks(start, rd.mark())
+        .map_err(|_| CodecError::Truncated)?
+        .len();
+    Ok((value, len))
+}
+
+pub fn write_u8(out: &mut Vec<u8>, v: u8) {
+    out.push(v);
+}
+
+pub fn write_u32(out: &mut Vec<u8>, v: u32) {
+    let mut buf = [0u8; 4];
+    LittleEndian::write_u32(&mut buf, v);
+    out.extend_from_slice(&buf);
+}
+
+pub fn write_u64(out: &mut Vec<u8>, v: u64) {
+    let mut buf = [0u8; 8];
+    LittleEndian::write_u64(&mut buf, v);
+    out.extend_from_slice(&buf);
+}
+
+/// Write `v` as an unsigned LEB128 integer, in as few bytes as possible.
+pub fn write_varu(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+pub fn write_varu32(out: &mut Vec<u8>, v: u32) {
+    write_varu(out, v as u64)
+}
+
+/// Write `v` as a signed LEB128 integer, in as few bytes as possible.
+pub fn write_vari(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+pub fn write_vari7(out: &mut Vec<u8>, v: i8) {
+    write_vari(out, v as i64)
+}
+
+pub fn write_vari32(out: &mut Vec<u8>, v: i32) {
+    write_vari(out, v as i64)
+}
+
+pub fn write_vari64(out: &mut Vec<u8>, v: i64) {
+    write_vari(out, v)
+}
+
+/// Write a length-prefixed string.
+pub fn write_utf8(out: &mut Vec<u8>, s: &str) {
+    write_bytes(out, s.as_bytes())
+}
+
+/// Write length-prefixed bytes.
+pub fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varu32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
 #[test]
 fn test_read_varu7() {
     let b = [0x7f];
     assert_eq!(read_varu7(&mut untrusted::Reader::new(untrusted::Input::from(&b))),
                Ok(0x7f));
 }
+
+#[test]
+fn test_read_vari() {
+    fn vari32(b: &[u8]) -> Result<i32, CodecError> {
+        read_vari32(&mut untrusted::Reader::new(untrusted::Input::from(b)))
+    }
+    fn vari64(b: &[u8]) -> Result<i64, CodecError> {
+        read_vari64(&mut untrusted::Reader::new(untrusted::Input::from(b)))
+    }
+
+    assert_eq!(vari32(&[0x7f]), Ok(-1));
+    assert_eq!(vari32(&[0x3f]), Ok(63));
+    assert_eq!(vari32(&[0xc0, 0x00]), Ok(64));
+    assert_eq!(vari32(&[0x80, 0x7f]), Ok(-128));
+    assert_eq!(vari32(&[0x80, 0x80, 0x80, 0x80, 0x78]), Ok(i32::MIN));
+    assert_eq!(vari32(&[0xff, 0xff, 0xff, 0xff, 0x07]), Ok(i32::MAX));
+    assert_eq!(vari32(&[0xff, 0xff, 0xff, 0xff, 0x0f]), Err(CodecError::BadVarInt));
+    assert_eq!(vari32(&[0x80, 0x80, 0x80, 0x80, 0x70]), Err(CodecError::BadVarInt));
+    assert_eq!(vari32(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]), Err(CodecError::BadVarInt));
+
+    assert_eq!(vari64(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]),
+               Ok(i64::MIN));
+    assert_eq!(vari64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]),
+               Ok(i64::MAX));
+    assert_eq!(vari64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]),
+               Err(CodecError::BadVarInt));
+    assert_eq!(vari64(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40]),
+               Ok(-1 << 62));
+}
+
+#[test]
+fn test_write_var() {
+    fn roundtrip_i64(v: i64) -> Vec<u8> {
+        let mut out = vec![];
+        write_vari64(&mut out, v);
+        assert_eq!(read_vari64(&mut untrusted::Reader::new(untrusted::Input::from(&out))),
+                   Ok(v));
+        out
+    }
+
+    assert_eq!(roundtrip_i64(0), vec![0x00]);
+    assert_eq!(roundtrip_i64(-1), vec![0x7f]);
+    assert_eq!(roundtrip_i64(63), vec![0x3f]);
+    assert_eq!(roundtrip_i64(64), vec![0xc0, 0x00]);
+    assert_eq!(roundtrip_i64(-64), vec![0x40]);
+    assert_eq!(roundtrip_i64(-65), vec![0xbf, 0x7f]);
+    assert_eq!(roundtrip_i64(i64::MIN).len(), 10);
+    assert_eq!(roundtrip_i64(i64::MAX).len(), 10);
+
+    let mut out = vec![];
+    write_varu32(&mut out, 624485);
+    assert_eq!(out, vec![0xe5, 0x8e, 0x26]);
+    out.clear();
+    write_varu32(&mut out, u32::MAX);
+    assert_eq!(read_varu32(&mut untrusted::Reader::new(untrusted::Input::from(&out))),
+               Ok(u32::MAX));
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, PartialEq)]
 pub enum CodecError {
     BadMagic,
@@ -12,6 +14,8 @@ pub enum CodecError {
     BadInitExpr,
     BadFunctionEnd,
     BadOpArgs,
+    /// A non-custom section is repeated or out of order.
+    BadSectionOrder,
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,6 +23,210 @@ pub enum RuntimeError {
     MemoryFault,
     TypeFault,
     StackUnderflow,
+    /// The operand stack or call depth limit was exceeded.
+    StackOverflow,
     DivideByZero,
+    /// The result of a signed division or float truncation is not
+    /// representable.
+    IntegerOverflow,
+    /// A NaN was truncated to an integer.
+    InvalidConversionToInteger,
+    Unreachable,
+    Host(String),
+    BadFunctionIndex,
+    BadLocalIndex,
+    BadGlobalIndex,
+    BadControl,
+    /// `call_indirect` was given an index past the end of the table.
+    UndefinedElement,
+    /// `call_indirect` was given a table slot holding no function.
+    UninitializedElement,
+    /// The function `call_indirect` found has the wrong signature.
+    IndirectCallTypeMismatch,
+    /// The fuel budget ran out before execution finished.
+    OutOfFuel,
     Unimpl
 }
+
+/// A frame that was active when execution trapped.
+#[derive(Debug, PartialEq)]
+pub struct TrapFrame {
+    /// The function's index, counting imports.
+    pub func: u32,
+    /// The function's name from the module's "name" section.
+    pub name: Option<String>,
+    /// Byte offset within the code section of the instruction that
+    /// trapped, or of the call in progress for outer frames.  `None` if
+    /// the module was not decoded from binary.
+    pub offset: Option<u32>,
+}
+
+/// A runtime error, with the frames that were active when it happened,
+/// innermost first.
+#[derive(Debug, PartialEq)]
+pub struct Trap {
+    pub error: RuntimeError,
+    pub backtrace: Vec<TrapFrame>,
+}
+
+impl From<RuntimeError> for Trap {
+    fn from(error: RuntimeError) -> Trap {
+        Trap { error, backtrace: vec![] }
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trap: {:?}", self.error)?;
+        for (i, frame) in self.backtrace.iter().enumerate() {
+            write!(f, "\n  {}: ", i)?;
+            match frame.name {
+                Some(ref name) => write!(f, "{} (func[{}])", name, frame.func)?,
+                None => write!(f, "func[{}]", frame.func)?,
+            }
+            if let Some(offset) = frame.offset {
+                write!(f, " @ {:#x}", offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InstantiationError {
+    /// No definition was supplied for the import `module`.`field`.
+    UnknownImport(String, String),
+    /// The definition supplied for `module`.`field` is of the wrong
+    /// kind or signature.
+    IncompatibleImport(String, String),
+    BadIndex,
+    TypeMismatch,
+    BadInitExpr,
+    ElementSegmentOutOfBounds,
+    DataSegmentOutOfBounds,
+    /// The store's `ResourceLimiter` refused to allocate a memory or
+    /// table.
+    ResourceLimitExceeded,
+    Invalid(ValidationError),
+    Start(Trap),
+}
+
+/// Why a function body failed to type-check.
+#[derive(Debug, PartialEq)]
+pub enum CodeError {
+    TypeMismatch,
+    Unbalanced,
+    BadLabel,
+    BadLocalIndex,
+    BadGlobalIndex,
+    BadFunctionIndex,
+    BadTypeIndex,
+    BadAlignment,
+    BadImmediate,
+    NoTable,
+    NoMemory,
+    TooManyLocals,
+    ImmutableGlobal,
+    /// An op the validator has no typing rule for.
+    UnknownOp,
+}
+
+/// A module that decoded but is not well-typed.
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    /// Op `pos` of function `func` is invalid.  `pos` is the number of
+    /// ops in the body for errors found at the end of the function.
+    Code { func: u32, pos: usize, error: CodeError },
+    BadTypeIndex,
+    BadFunctionIndex,
+    BadTableIndex,
+    BadMemoryIndex,
+    BadGlobalIndex,
+    BadLimits,
+    MultipleTables,
+    MultipleMemories,
+    FunctionCodeMismatch,
+    DuplicateExport,
+    BadStartFunction,
+    BadInitExpr,
+}
+
+/// A text module is not a well-formed s-expression.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+}
+
+/// Why a text module could not be parsed.
+#[derive(Debug, PartialEq)]
+pub enum WatError {
+    Syntax(SyntaxError),
+    /// A form is missing or out of place; says what was expected.
+    Malformed(&'static str),
+    UnknownOperator(String),
+    /// A `$name` that is not defined in its index space.
+    UnknownId(String),
+    DuplicateId(String),
+    BadLiteral(String),
+    /// All imports must come before any function, table, memory or
+    /// global definition.
+    ImportAfterDefinition,
+    /// A type use names a type that differs from its inline signature.
+    TypeMismatch,
+}
+
+/// Any error produced while loading, instantiating or running a module.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Codec(CodecError),
+    Instantiation(InstantiationError),
+    Runtime(RuntimeError),
+    /// Execution trapped.
+    Trap(Trap),
+    Wat(WatError),
+    NoSuchExport,
+    /// The host tried to set an immutable global.
+    ImmutableGlobal,
+}
+
+impl From<CodecError> for Error {
+    fn from(e: CodecError) -> Error {
+        Error::Codec(e)
+    }
+}
+
+impl From<InstantiationError> for Error {
+    fn from(e: InstantiationError) -> Error {
+        Error::Instantiation(e)
+    }
+}
+
+impl From<ValidationError> for InstantiationError {
+    fn from(e: ValidationError) -> InstantiationError {
+        InstantiationError::Invalid(e)
+    }
+}
+
+impl From<WatError> for Error {
+    fn from(e: WatError) -> Error {
+        Error::Wat(e)
+    }
+}
+
+impl From<SyntaxError> for WatError {
+    fn from(e: SyntaxError) -> WatError {
+        WatError::Syntax(e)
+    }
+}
+
+impl From<Trap> for Error {
+    fn from(e: Trap) -> Error {
+        Error::Trap(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Error {
+        Error::Runtime(e)
+    }
+}
@@ -1,40 +1,187 @@
 use mem;
+use table;
 use expr;
+use float;
+use section::{FunctionType, GlobalType};
+use ty::{ValueType, BlockType};
 use value::Value;
-use error::RuntimeError;
+use error::{RuntimeError, Trap, TrapFrame};
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Positions of the `else` (if any) and `end` matching a
+/// `block`, `loop` or `if`.
+#[derive(Clone, Copy)]
+struct Block {
+    else_pos: Option<usize>,
+    end: usize,
+}
+
+/// A function body prepared for execution.
+pub struct Code {
+    /// Declared locals, following the parameters.
+    locals: Vec<ValueType>,
+    ops: Vec<expr::Op>,
+    blocks: Vec<Option<Block>>,
+    /// Code section offsets of `ops` and the final `end`, if known.
+    offsets: Vec<u32>,
+}
+
+impl Code {
+    pub fn new(locals: Vec<ValueType>, ops: Vec<expr::Op>, offsets: Vec<u32>) -> Code {
+        let mut blocks = vec![None; ops.len()];
+        let mut open = vec![];
+
+        for (pos, op) in ops.iter().enumerate() {
+            match *op {
+                expr::Op::Block(_) | expr::Op::Loop(_) | expr::Op::If(_) => {
+                    open.push((pos, None))
+                }
+                expr::Op::Else => {
+                    if let Some(&mut (_, ref mut else_pos)) = open.last_mut() {
+                        *else_pos = Some(pos);
+                    }
+                }
+                expr::Op::End => {
+                    if let Some((start, else_pos)) = open.pop() {
+                        blocks[start] = Some(Block { else_pos, end: pos });
+                        if let Some(else_pos) = else_pos {
+                            blocks[else_pos] = Some(Block { else_pos: None, end: pos });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Code { locals, ops, blocks, offsets }
+    }
+}
+
+/// A function implemented by the embedder.  It receives the instance's
+/// memory and the call's arguments, and returns the call's results.
+pub type HostFunc = dyn Fn(&mut mem::Memory, &[Value]) -> Result<Vec<Value>, RuntimeError>;
+
+#[derive(Clone)]
+pub enum Body {
+    Wasm(Rc<Code>),
+    Host(Rc<HostFunc>),
+}
+
+pub struct Function {
+    pub ty: FunctionType,
+    pub body: Body,
+}
+
+/// A global variable.  Imported globals share their cell with the
+/// definition they resolved to, so writes are seen on both sides.
+#[derive(Clone)]
+pub struct Global {
+    pub ty: GlobalType,
+    cell: Rc<Cell<Value>>,
+}
+
+impl Global {
+    pub fn new(ty: GlobalType, value: Value) -> Global {
+        Global { ty, cell: Rc::new(Cell::new(value)) }
+    }
+
+    pub fn get(&self) -> Value {
+        self.cell.get()
+    }
+
+    /// Store `value`, which the caller has checked against `ty`.
+    pub fn set(&self, value: Value) {
+        self.cell.set(value)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Label {
+    /// Number of values carried by a branch to this label.
+    arity: usize,
+    /// Operand stack height on entry to the block.
+    height: usize,
+    /// Where execution continues after a branch to this label.
+    cont: usize,
+}
+
+struct Frame {
+    func: u32,
+    code: Rc<Code>,
+    pc: usize,
+    locals: Vec<Value>,
+    arity: usize,
+    stack_base: usize,
+    label_base: usize,
+}
+
+impl Frame {
+    /// Where this frame is: the op executing, or the call in progress.
+    fn location(&self) -> TrapFrame {
+        TrapFrame {
+            func: self.func,
+            name: None,
+            offset: self.code.offsets.get(self.pc.saturating_sub(1)).cloned(),
+        }
+    }
+}
+
+/// Bounds on the resources a computation may use.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Values held on the operand stack plus locals of active calls.
+    pub stack: usize,
+    /// Active calls to non-host functions.
+    pub depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { stack: 1 << 20, depth: 1 << 14 }
+    }
+}
 
 pub struct Context {
-    mem: mem::Memory,
+    /// The module's type section, for `call_indirect`.
+    pub types: Vec<FunctionType>,
+    pub mem: mem::Memory,
+    pub tables: Vec<table::Table>,
+    pub globals: Vec<Global>,
+    pub funcs: Vec<Function>,
     stack: Vec<Value>,
+    labels: Vec<Label>,
+    frames: Vec<Frame>,
+    locals_len: usize,
+    limits: Limits,
+    fuel: Option<u64>,
 }
 
 macro_rules! mem_load {
     ($self:ident, $immed:ident, $access:tt, $value:path) => (
-        $self.mem.$access(&$immed)
-            .ok_or(RuntimeError::MemoryFault)
+        $self.pop_i32()
+            .and_then(|addr| $self.mem.$access(addr as u32, &$immed)
+                      .ok_or(RuntimeError::MemoryFault))
             .and_then(|v| $self.push($value(v)))
     );
 }
 
 macro_rules! mem_store {
-    ($self:ident, $immed:ident, $access:tt, $pop:ident) => (
-        $self.$pop()
-            .and_then(|v| $self.mem.$access(v, &$immed)
+    ($self:ident, $immed:ident, $access:tt, $pop:ident) => ({
+        let v = $self.$pop()?;
+        $self.pop_i32()
+            .and_then(|addr| $self.mem.$access(addr as u32, v, &$immed)
                       .ok_or(RuntimeError::MemoryFault))
-    );
+    });
 }
 
 macro_rules! stack_pop {
     ($name:ident, $ty:tt, $vty:path) => (
         fn $name(&mut self) -> Result<$ty, RuntimeError> {
             match self.stack.pop() {
-                Some(v) => {
-                    println!("pop {:?}", v);
-                    match v {
-                        $vty(vv) => Ok(vv),
-                        _ => Err(RuntimeError::TypeFault),
-                    }
-                },
+                Some($vty(v)) => Ok(v),
+                Some(_) => Err(RuntimeError::TypeFault),
                 None => Err(RuntimeError::StackUnderflow),
             }
         }
@@ -53,26 +200,38 @@ macro_rules! unary {
 /// Stack operation with two inputs and one output
 macro_rules! binary {
     ($self:ident, $poplhs:ident, $poprhs:ident, $vty:ident, $val:expr) => ({
-        let lhs = $self.$poplhs()?;
-        $self.$poprhs()
-             .map(|rhs| $val(lhs, rhs))
+        let rhs = $self.$poprhs()?;
+        $self.$poplhs()
+             .map(|lhs| $val(lhs, rhs))
              .and_then(|v| $self.push(Value::$vty(v)))
     });
 }
 
+/// Stack operation with one input and one output, that may trap.
+macro_rules! unary_trap {
+    ($self:ident, $pop:ident, $vty:ident, $val:expr) => (
+        $self.$pop()
+             .and_then($val)
+             .and_then(|v| $self.push(Value::$vty(v)))
+    );
+}
+
 /// Stack operation with two inputs and one output, that may trap.
 macro_rules! binary_trap {
     ($self:ident, $poplhs:ident, $poprhs:ident, $vty:ident, $val:expr) => ({
-        let lhs = $self.$poplhs()?;
-        $self.$poprhs()
-             .and_then(|rhs| $val(lhs, rhs))
+        let rhs = $self.$poprhs()?;
+        $self.$poplhs()
+             .and_then(|lhs| $val(lhs, rhs))
              .and_then(|v| $self.push(Value::$vty(v)))
     });
 }
 
 fn div_i32(x: i32, y: i32) -> Result<i32, RuntimeError> {
-    x.checked_div(y)
-        .ok_or(RuntimeError::DivideByZero)
+    match (x, y) {
+        (_, 0) => Err(RuntimeError::DivideByZero),
+        (::std::i32::MIN, -1) => Err(RuntimeError::IntegerOverflow),
+        _ => Ok(x / y),
+    }
 }
 
 fn div_u32(x: i32, y: i32) -> Result<i32, RuntimeError> {
@@ -81,9 +240,13 @@ fn div_u32(x: i32, y: i32) -> Result<i32, RuntimeError> {
         .map(|u| u as i32)
 }
 
+/// The remainder of `::std::i32::MIN / -1` is representable, so this only traps
+/// on division by zero.
 fn rem_i32(x: i32, y: i32) -> Result<i32, RuntimeError> {
-    x.checked_rem(y)
-        .ok_or(RuntimeError::DivideByZero)
+    match y {
+        0 => Err(RuntimeError::DivideByZero),
+        _ => Ok(x.wrapping_rem(y)),
+    }
 }
 
 fn rem_u32(x: i32, y: i32) -> Result<i32, RuntimeError> {
@@ -93,8 +256,11 @@ fn rem_u32(x: i32, y: i32) -> Result<i32, RuntimeError> {
 }
 
 fn div_i64(x: i64, y: i64) -> Result<i64, RuntimeError> {
-    x.checked_div(y)
-        .ok_or(RuntimeError::DivideByZero)
+    match (x, y) {
+        (_, 0) => Err(RuntimeError::DivideByZero),
+        (::std::i64::MIN, -1) => Err(RuntimeError::IntegerOverflow),
+        _ => Ok(x / y),
+    }
 }
 
 fn div_u64(x: i64, y: i64) -> Result<i64, RuntimeError> {
@@ -104,8 +270,10 @@ fn div_u64(x: i64, y: i64) -> Result<i64, RuntimeError> {
 }
 
 fn rem_i64(x: i64, y: i64) -> Result<i64, RuntimeError> {
-    x.checked_rem(y)
-        .ok_or(RuntimeError::DivideByZero)
+    match y {
+        0 => Err(RuntimeError::DivideByZero),
+        _ => Ok(x.wrapping_rem(y)),
+    }
 }
 
 fn rem_u64(x: i64, y: i64) -> Result<i64, RuntimeError> {
@@ -114,180 +282,642 @@ fn rem_u64(x: i64, y: i64) -> Result<i64, RuntimeError> {
         .map(|u| u as i64)
 }
 
+/// Truncate towards zero, trapping if the result is out of the range
+/// `$min..$max` of the `$via` integer type, which is then reinterpreted
+/// as the `$ity` the value stack holds.  The bounds are powers of two,
+/// so are exact in either float type.
+macro_rules! trunc {
+    ($name:ident, $fty:ty, $via:ty, $ity:ty, $min:expr, $max:expr) => (
+        fn $name(x: $fty) -> Result<$ity, RuntimeError> {
+            if x.is_nan() {
+                return Err(RuntimeError::InvalidConversionToInteger);
+            }
+
+            let t = x.trunc();
+            if !($min..$max).contains(&t) {
+                return Err(RuntimeError::IntegerOverflow);
+            }
+            Ok(t as $via as $ity)
+        }
+    );
+}
+
+trunc!(trunc_i32_f32, f32, i32, i32, -2147483648.0, 2147483648.0);
+trunc!(trunc_u32_f32, f32, u32, i32, 0.0, 4294967296.0);
+trunc!(trunc_i32_f64, f64, i32, i32, -2147483648.0, 2147483648.0);
+trunc!(trunc_u32_f64, f64, u32, i32, 0.0, 4294967296.0);
+trunc!(trunc_i64_f32, f32, i64, i64, -9223372036854775808.0, 9223372036854775808.0);
+trunc!(trunc_u64_f32, f32, u64, i64, 0.0, 18446744073709551616.0);
+trunc!(trunc_i64_f64, f64, i64, i64, -9223372036854775808.0, 9223372036854775808.0);
+trunc!(trunc_u64_f64, f64, u64, i64, 0.0, 18446744073709551616.0);
+
 impl Context {
+    /// A context with one page of memory, for tests.
+    #[cfg(test)]
     pub fn new() -> Context {
         let mut mem = mem::Memory::new();
         mem.grow(1);
 
-        let stack = vec![];
+        Context::with_memory(mem)
+    }
 
-        Context { mem, stack }
+    pub fn with_memory(mem: mem::Memory) -> Context {
+        Context {
+            types: vec![],
+            mem,
+            tables: vec![],
+            globals: vec![],
+            funcs: vec![],
+            stack: vec![],
+            labels: vec![],
+            frames: vec![],
+            locals_len: 0,
+            limits: Limits::default(),
+            fuel: None,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Exceeding `limits` traps with `RuntimeError::StackOverflow`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Remaining fuel, or `None` if execution is unmetered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Meter execution: each op executed consumes one unit of fuel, and
+    /// running out traps with `RuntimeError::OutOfFuel`.  `None` turns
+    /// metering off.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Top up the fuel budget.  Has no effect when execution is
+    /// unmetered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(ref mut left) = self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    /// Call function `index` with `args`, returning its results.  A
+    /// trap's backtrace holds the frames of this call, without names.
+    pub fn invoke(&mut self, index: u32, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let types = match self.funcs.get(index as usize) {
+            Some(func) if func.ty.params.len() == args.len() => func.ty.results.clone(),
+            Some(_) => return Err(RuntimeError::TypeFault.into()),
+            None => return Err(RuntimeError::BadFunctionIndex.into()),
+        };
+
+        let base = self.stack.len();
+        let depth = self.frames.len();
+        let label_base = self.labels.len();
+        for arg in args {
+            self.push(*arg)?;
+        }
+
+        if let Err(error) = self.call(index).and_then(|_| self.run(depth)) {
+            let backtrace = self.frames[depth..].iter().rev().map(Frame::location).collect();
+            self.frames.truncate(depth);
+            self.locals_len = self.frames.iter().map(|f| f.locals.len()).sum();
+            self.labels.truncate(label_base);
+            self.stack.truncate(base);
+            return Err(Trap { error, backtrace });
+        }
+
+        if self.stack.len() < base + types.len() {
+            return Err(RuntimeError::StackUnderflow.into());
+        }
+        let top = self.stack.len() - types.len();
+        let results = self.stack.split_off(top);
+        self.stack.truncate(base);
+
+        if results.iter().map(Value::ty).ne(types) {
+            return Err(RuntimeError::TypeFault.into());
+        }
+        Ok(results)
+    }
+
+    /// Enter function `index`, taking its parameters from the operand
+    /// stack.
+    fn call(&mut self, index: u32) -> Result<(), RuntimeError> {
+        let (body, params, arity) = match self.funcs.get(index as usize) {
+            Some(func) => (func.body.clone(), func.ty.params.len(), func.ty.results.len()),
+            None => return Err(RuntimeError::BadFunctionIndex),
+        };
+
+        if self.stack.len() < params {
+            return Err(RuntimeError::StackUnderflow);
+        }
+
+        let stack_base = self.stack.len() - params;
+        let mut locals: Vec<Value> = self.stack.drain(stack_base..).collect();
+
+        {
+            let func = &self.funcs[index as usize];
+            if func.ty.params.iter().zip(&locals).any(|(ty, arg)| *ty != arg.ty()) {
+                return Err(RuntimeError::TypeFault);
+            }
+        }
+
+        let code = match body {
+            Body::Wasm(code) => code,
+            Body::Host(func) => {
+                let results = func(&mut self.mem, &locals)?;
+                if results.iter().map(Value::ty).ne(self.funcs[index as usize].ty.results.iter().cloned()) {
+                    return Err(RuntimeError::TypeFault);
+                }
+                for v in results {
+                    self.push(v)?;
+                }
+                return Ok(());
+            }
+        };
+
+        if self.frames.len() >= self.limits.depth ||
+            self.stack.len() + self.locals_len + locals.len() + code.locals.len() > self.limits.stack
+        {
+            return Err(RuntimeError::StackOverflow);
+        }
+
+        locals.extend(code.locals.iter().map(|ty| Value::zero(*ty)));
+        self.locals_len += locals.len();
+
+        self.frames.push(Frame {
+            func: index,
+            code,
+            pc: 0,
+            locals,
+            arity,
+            stack_base,
+            label_base: self.labels.len(),
+        });
+        Ok(())
+    }
+
+    fn local(&mut self, index: u32) -> Result<&mut Value, RuntimeError> {
+        self.frame()?
+            .locals
+            .get_mut(index as usize)
+            .ok_or(RuntimeError::BadLocalIndex)
+    }
+
+    fn set_local(&mut self, index: u32, value: Value) -> Result<(), RuntimeError> {
+        let local = self.local(index)?;
+        if local.ty() != value.ty() {
+            return Err(RuntimeError::TypeFault);
+        }
+        *local = value;
+        Ok(())
+    }
+
+    /// Execute until the frame stack unwinds back to `depth` frames.
+    fn run(&mut self, depth: usize) -> Result<(), RuntimeError> {
+        while self.frames.len() > depth {
+            let (code, pc) = {
+                let frame = self.frames.last_mut().unwrap();
+                frame.pc += 1;
+                (frame.code.clone(), frame.pc - 1)
+            };
+
+            match code.ops.get(pc) {
+                Some(op) => {
+                    self.consume_fuel()?;
+                    self.exec(op)?
+                }
+                None => self.ret()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn consume_fuel(&mut self) -> Result<(), RuntimeError> {
+        match self.fuel {
+            Some(0) => Err(RuntimeError::OutOfFuel),
+            Some(ref mut left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn frame(&mut self) -> Result<&mut Frame, RuntimeError> {
+        self.frames.last_mut()
+            .ok_or(RuntimeError::BadControl)
+    }
+
+    /// The block starting at the op just executed.
+    fn block(&mut self) -> Result<Block, RuntimeError> {
+        let frame = self.frame()?;
+        frame.code.blocks[frame.pc - 1]
+            .ok_or(RuntimeError::BadControl)
+    }
+
+    /// Parameter and result counts of a block of type `ty`.
+    fn block_arity(&self, ty: &BlockType) -> Result<(usize, usize), RuntimeError> {
+        match *ty {
+            BlockType::Void => Ok((0, 0)),
+            BlockType::Single(_) => Ok((0, 1)),
+            BlockType::Index(index) => self.types.get(index as usize)
+                .map(|ty| (ty.params.len(), ty.results.len()))
+                .ok_or(RuntimeError::TypeFault),
+        }
+    }
+
+    /// Push a label for a block whose `params` are on top of the stack.
+    fn enter(&mut self, params: usize, arity: usize, cont: usize) -> Result<(), RuntimeError> {
+        if self.stack.len() < params {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        let height = self.stack.len() - params;
+        self.labels.push(Label { arity, height, cont });
+        Ok(())
+    }
+
+    /// Drop operand stack values down to `height`, keeping the top
+    /// `arity` values.
+    fn unwind(&mut self, height: usize, arity: usize) -> Result<(), RuntimeError> {
+        if self.stack.len() < height + arity {
+            return Err(RuntimeError::StackUnderflow);
+        }
+
+        let top = self.stack.len() - arity;
+        self.stack.drain(height..top);
+        Ok(())
+    }
+
+    fn branch(&mut self, depth: u32) -> Result<(), RuntimeError> {
+        let label_base = self.frame()?.label_base;
+        let depth = depth as usize;
+        let labels = self.labels.len() - label_base;
+
+        if depth == labels {
+            return self.ret();
+        } else if depth > labels {
+            return Err(RuntimeError::BadControl);
+        }
+
+        let index = self.labels.len() - 1 - depth;
+        let label = self.labels[index];
+        self.labels.truncate(index);
+        self.unwind(label.height, label.arity)?;
+        self.frame()?.pc = label.cont;
+        Ok(())
+    }
+
+    fn ret(&mut self) -> Result<(), RuntimeError> {
+        let frame = self.frames.pop()
+            .ok_or(RuntimeError::BadControl)?;
+        self.labels.truncate(frame.label_base);
+        self.locals_len -= frame.locals.len();
+        self.unwind(frame.stack_base, frame.arity)
+    }
+
+    fn global(&self, index: u32) -> Result<&Global, RuntimeError> {
+        self.globals.get(index as usize)
+            .ok_or(RuntimeError::BadGlobalIndex)
     }
 
     fn push(&mut self, v: Value) -> Result<(), RuntimeError> {
-        // TODO: stack limit
-        println!("push {:?}", v);
+        if self.stack.len() + self.locals_len >= self.limits.stack {
+            return Err(RuntimeError::StackOverflow);
+        }
         self.stack.push(v);
         Ok(())
     }
 
-    stack_pop!(pop_I32, i32, Value::I32);
-    stack_pop!(pop_I64, i64, Value::I64);
-    stack_pop!(pop_F32, f32, Value::F32);
-    stack_pop!(pop_F64, f64, Value::F64);
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        self.stack.pop()
+            .ok_or(RuntimeError::StackUnderflow)
+    }
+
+    stack_pop!(pop_i32, i32, Value::I32);
+    stack_pop!(pop_i64, i64, Value::I64);
+    stack_pop!(pop_f32, f32, Value::F32);
+    stack_pop!(pop_f64, f64, Value::F64);
 
     fn exec(&mut self, op: &expr::Op) -> Result<(), RuntimeError> {
         use expr::Op::*;
 
-        match op {
-            &Unreachable => Err(RuntimeError::Unreachable),
-            &Nop => Ok(()),
-
-            &I32Clz => unary!(self, pop_I32, I32, |i| i.leading_zeros() as i32),
-            &I32Ctz => unary!(self, pop_I32, I32, |i| i.trailing_zeros() as i32),
-            &I32Popcnt => unary!(self, pop_I32, I32, |i| i.count_ones() as i32),
-            &I32Add => binary!(self, pop_I32, pop_I32, I32, |x, y| x + y),
-            &I32Sub => binary!(self, pop_I32, pop_I32, I32, |x, y| x - y),
-            &I32Mul => binary!(self, pop_I32, pop_I32, I32, |x, y| x * y),
-            &I32DivSigned => binary_trap!(self, pop_I32, pop_I32, I32, div_i32),
-            &I32DivUnsigned => binary_trap!(self, pop_I32, pop_I32, I32, div_u32),
-            &I32RemSigned => binary_trap!(self, pop_I32, pop_I32, I32, rem_i32),
-            &I32RemUnsigned => binary_trap!(self, pop_I32, pop_I32, I32, rem_u32),
-            &I32And => binary!(self, pop_I32, pop_I32, I32, |x, y| x & y),
-            &I32Or => binary!(self, pop_I32, pop_I32, I32, |x, y| x | y),
-            &I32Xor => binary!(self, pop_I32, pop_I32, I32, |x, y| x ^ y),
-            &I32Shl => binary!(self, pop_I32, pop_I32, I32,
+        match *op {
+            Unreachable => Err(RuntimeError::Unreachable),
+            Nop => Ok(()),
+
+            Block(ref ty) => {
+                let block = self.block()?;
+                let (params, results) = self.block_arity(ty)?;
+                self.enter(params, results, block.end + 1)
+            },
+            Loop(ref ty) => {
+                // A branch to a loop carries its parameters back to the start.
+                let pos = self.frame()?.pc - 1;
+                let (params, _) = self.block_arity(ty)?;
+                self.enter(params, params, pos)
+            },
+            If(ref ty) => {
+                let block = self.block()?;
+                let cond = self.pop_i32()?;
+                let (params, results) = self.block_arity(ty)?;
+                self.enter(params, results, block.end + 1)?;
+                if cond == 0 {
+                    self.frame()?.pc = block.else_pos
+                        .map(|pos| pos + 1)
+                        .unwrap_or(block.end);
+                }
+                Ok(())
+            },
+            Else => {
+                let block = self.block()?;
+                self.frame()?.pc = block.end;
+                Ok(())
+            },
+            End => {
+                if self.labels.len() <= self.frame()?.label_base {
+                    return Err(RuntimeError::BadControl);
+                }
+                self.labels.pop();
+                Ok(())
+            },
+            Branch(depth) => self.branch(depth),
+            BranchIf(depth) => {
+                if self.pop_i32()? != 0 {
+                    self.branch(depth)
+                } else {
+                    Ok(())
+                }
+            },
+            BranchTable(ref table) => {
+                let index = self.pop_i32()? as u32 as usize;
+                let depth = table.targets.get(index)
+                    .cloned()
+                    .unwrap_or(table.default);
+                self.branch(depth)
+            },
+            Return => self.ret(),
+            Call(index) => self.call(index),
+            CallIndirect(type_index) => {
+                let elem = self.pop_i32()? as u32;
+                let func = self.tables.first()
+                    .ok_or(RuntimeError::UndefinedElement)?
+                    .get(elem)
+                    .ok_or(RuntimeError::UndefinedElement)?
+                    .ok_or(RuntimeError::UninitializedElement)?;
+
+                let expected = self.types.get(type_index as usize)
+                    .ok_or(RuntimeError::IndirectCallTypeMismatch)?;
+                match self.funcs.get(func as usize) {
+                    Some(f) if f.ty == *expected => {}
+                    Some(_) => return Err(RuntimeError::IndirectCallTypeMismatch),
+                    None => return Err(RuntimeError::BadFunctionIndex),
+                }
+                self.call(func)
+            },
+
+            GetLocal(index) => {
+                let value = *self.local(index)?;
+                self.push(value)
+            },
+            SetLocal(index) => {
+                let value = self.pop()?;
+                self.set_local(index, value)
+            },
+            TeeLocal(index) => {
+                let value = self.pop()?;
+                self.set_local(index, value)?;
+                self.push(value)
+            },
+
+            Drop => self.pop().map(|_| ()),
+            Select => {
+                let cond = self.pop_i32()?;
+                let v2 = self.pop()?;
+                let v1 = self.pop()?;
+                if v1.ty() != v2.ty() {
+                    return Err(RuntimeError::TypeFault);
+                }
+                self.push(if cond != 0 { v1 } else { v2 })
+            },
+
+            GetGlobal(index) => {
+                let value = self.global(index)?.get();
+                self.push(value)
+            },
+            SetGlobal(index) => {
+                let value = self.pop()?;
+                let global = self.global(index)?;
+                if !global.ty.mutable || global.ty.content != value.ty() {
+                    return Err(RuntimeError::TypeFault);
+                }
+                global.set(value);
+                Ok(())
+            },
+
+            I32Eqz => unary!(self, pop_i32, I32, |i| (i == 0) as i32),
+            I32Eq => binary!(self, pop_i32, pop_i32, I32, |x, y| (x == y) as i32),
+            I32Ne => binary!(self, pop_i32, pop_i32, I32, |x, y| (x != y) as i32),
+            I32LtSigned => binary!(self, pop_i32, pop_i32, I32, |x, y| (x < y) as i32),
+            I32LtUnsigned => binary!(self, pop_i32, pop_i32, I32,
+                                      |x, y| ((x as u32) < (y as u32)) as i32),
+            I32GtSigned => binary!(self, pop_i32, pop_i32, I32, |x, y| (x > y) as i32),
+            I32GtUnsigned => binary!(self, pop_i32, pop_i32, I32,
+                                      |x, y| ((x as u32) > (y as u32)) as i32),
+            I32LeSigned => binary!(self, pop_i32, pop_i32, I32, |x, y| (x <= y) as i32),
+            I32LeUnsigned => binary!(self, pop_i32, pop_i32, I32,
+                                      |x, y| ((x as u32) <= (y as u32)) as i32),
+            I32GeSigned => binary!(self, pop_i32, pop_i32, I32, |x, y| (x >= y) as i32),
+            I32GeUnsigned => binary!(self, pop_i32, pop_i32, I32,
+                                      |x, y| ((x as u32) >= (y as u32)) as i32),
+
+            I64Eqz => unary!(self, pop_i64, I32, |i| (i == 0) as i32),
+            I64Eq => binary!(self, pop_i64, pop_i64, I32, |x, y| (x == y) as i32),
+            I64Ne => binary!(self, pop_i64, pop_i64, I32, |x, y| (x != y) as i32),
+            I64LtSigned => binary!(self, pop_i64, pop_i64, I32, |x, y| (x < y) as i32),
+            I64LtUnsigned => binary!(self, pop_i64, pop_i64, I32,
+                                      |x, y| ((x as u64) < (y as u64)) as i32),
+            I64GtSigned => binary!(self, pop_i64, pop_i64, I32, |x, y| (x > y) as i32),
+            I64GtUnsigned => binary!(self, pop_i64, pop_i64, I32,
+                                      |x, y| ((x as u64) > (y as u64)) as i32),
+            I64LeSigned => binary!(self, pop_i64, pop_i64, I32, |x, y| (x <= y) as i32),
+            I64LeUnsigned => binary!(self, pop_i64, pop_i64, I32,
+                                      |x, y| ((x as u64) <= (y as u64)) as i32),
+            I64GeSigned => binary!(self, pop_i64, pop_i64, I32, |x, y| (x >= y) as i32),
+            I64GeUnsigned => binary!(self, pop_i64, pop_i64, I32,
+                                      |x, y| ((x as u64) >= (y as u64)) as i32),
+
+            // IEEE comparisons: every ordered comparison with a NaN is
+            // false, and -0 == +0.
+            F32Eq => binary!(self, pop_f32, pop_f32, I32, |x, y| (x == y) as i32),
+            F32Ne => binary!(self, pop_f32, pop_f32, I32, |x, y| (x != y) as i32),
+            F32Lt => binary!(self, pop_f32, pop_f32, I32, |x, y| (x < y) as i32),
+            F32Gt => binary!(self, pop_f32, pop_f32, I32, |x, y| (x > y) as i32),
+            F32Le => binary!(self, pop_f32, pop_f32, I32, |x, y| (x <= y) as i32),
+            F32Ge => binary!(self, pop_f32, pop_f32, I32, |x, y| (x >= y) as i32),
+
+            F64Eq => binary!(self, pop_f64, pop_f64, I32, |x, y| (x == y) as i32),
+            F64Ne => binary!(self, pop_f64, pop_f64, I32, |x, y| (x != y) as i32),
+            F64Lt => binary!(self, pop_f64, pop_f64, I32, |x, y| (x < y) as i32),
+            F64Gt => binary!(self, pop_f64, pop_f64, I32, |x, y| (x > y) as i32),
+            F64Le => binary!(self, pop_f64, pop_f64, I32, |x, y| (x <= y) as i32),
+            F64Ge => binary!(self, pop_f64, pop_f64, I32, |x, y| (x >= y) as i32),
+
+            I32Clz => unary!(self, pop_i32, I32, |i| i.leading_zeros() as i32),
+            I32Ctz => unary!(self, pop_i32, I32, |i| i.trailing_zeros() as i32),
+            I32Popcnt => unary!(self, pop_i32, I32, |i| i.count_ones() as i32),
+            I32Add => binary!(self, pop_i32, pop_i32, I32, |x: i32, y| x.wrapping_add(y)),
+            I32Sub => binary!(self, pop_i32, pop_i32, I32, |x: i32, y| x.wrapping_sub(y)),
+            I32Mul => binary!(self, pop_i32, pop_i32, I32, |x: i32, y| x.wrapping_mul(y)),
+            I32DivSigned => binary_trap!(self, pop_i32, pop_i32, I32, div_i32),
+            I32DivUnsigned => binary_trap!(self, pop_i32, pop_i32, I32, div_u32),
+            I32RemSigned => binary_trap!(self, pop_i32, pop_i32, I32, rem_i32),
+            I32RemUnsigned => binary_trap!(self, pop_i32, pop_i32, I32, rem_u32),
+            I32And => binary!(self, pop_i32, pop_i32, I32, |x, y| x & y),
+            I32Or => binary!(self, pop_i32, pop_i32, I32, |x, y| x | y),
+            I32Xor => binary!(self, pop_i32, pop_i32, I32, |x, y| x ^ y),
+            I32Shl => binary!(self, pop_i32, pop_i32, I32,
                                |x: i32, y| x.wrapping_shl(y as u32)),
-            &I32ShrSigned => binary!(self, pop_I32, pop_I32, I32, 
+            I32ShrSigned => binary!(self, pop_i32, pop_i32, I32,
                                      |x: i32, y| x.wrapping_shr(y as u32)),
-            &I32ShrUnsigned => binary!(self, pop_I32, pop_I32, I32,
+            I32ShrUnsigned => binary!(self, pop_i32, pop_i32, I32,
                                        |x: i32, y| (x as u32).wrapping_shr(y as u32) as i32),
-            &I32Rotl => binary!(self, pop_I32, pop_I32, I32,
+            I32Rotl => binary!(self, pop_i32, pop_i32, I32,
                                 |x: i32, y| x.rotate_left(y as u32 % 32)),
-            &I32Rotr => binary!(self, pop_I32, pop_I32, I32,
+            I32Rotr => binary!(self, pop_i32, pop_i32, I32,
                                 |x: i32, y| x.rotate_right(y as u32 % 32)),
 
-            &I64Clz => unary!(self, pop_I64, I64, |i| i.leading_zeros() as i64),
-            &I64Ctz => unary!(self, pop_I64, I64, |i| i.trailing_zeros() as i64),
-            &I64Popcnt => unary!(self, pop_I64, I64, |i| i.count_ones() as i64),
-            &I64Add => binary!(self, pop_I64, pop_I64, I64, |x, y| x + y),
-            &I64Sub => binary!(self, pop_I64, pop_I64, I64, |x, y| x - y),
-            &I64Mul => binary!(self, pop_I64, pop_I64, I64, |x, y| x * y),
-            &I64DivSigned => binary_trap!(self, pop_I64, pop_I64, I64, div_i64),
-            &I64DivUnsigned => binary_trap!(self, pop_I64, pop_I64, I64, div_u64),
-            &I64RemSigned => binary_trap!(self, pop_I64, pop_I64, I64, rem_i64),
-            &I64RemUnsigned => binary_trap!(self, pop_I64, pop_I64, I64, rem_u64),
-            &I64And => binary!(self, pop_I64, pop_I64, I64, |x, y| x & y),
-            &I64Or => binary!(self, pop_I64, pop_I64, I64, |x, y| x | y),
-            &I64Xor => binary!(self, pop_I64, pop_I64, I64, |x, y| x ^ y),
-            &I64Shl => binary!(self, pop_I64, pop_I64, I64,
+            I64Clz => unary!(self, pop_i64, I64, |i| i.leading_zeros() as i64),
+            I64Ctz => unary!(self, pop_i64, I64, |i| i.trailing_zeros() as i64),
+            I64Popcnt => unary!(self, pop_i64, I64, |i| i.count_ones() as i64),
+            I64Add => binary!(self, pop_i64, pop_i64, I64, |x: i64, y| x.wrapping_add(y)),
+            I64Sub => binary!(self, pop_i64, pop_i64, I64, |x: i64, y| x.wrapping_sub(y)),
+            I64Mul => binary!(self, pop_i64, pop_i64, I64, |x: i64, y| x.wrapping_mul(y)),
+            I64DivSigned => binary_trap!(self, pop_i64, pop_i64, I64, div_i64),
+            I64DivUnsigned => binary_trap!(self, pop_i64, pop_i64, I64, div_u64),
+            I64RemSigned => binary_trap!(self, pop_i64, pop_i64, I64, rem_i64),
+            I64RemUnsigned => binary_trap!(self, pop_i64, pop_i64, I64, rem_u64),
+            I64And => binary!(self, pop_i64, pop_i64, I64, |x, y| x & y),
+            I64Or => binary!(self, pop_i64, pop_i64, I64, |x, y| x | y),
+            I64Xor => binary!(self, pop_i64, pop_i64, I64, |x, y| x ^ y),
+            I64Shl => binary!(self, pop_i64, pop_i64, I64,
                                |x: i64, y| x.wrapping_shl(y as u32)),
-            &I64ShrSigned => binary!(self, pop_I64, pop_I64, I64, 
+            I64ShrSigned => binary!(self, pop_i64, pop_i64, I64,
                                      |x: i64, y| x.wrapping_shr(y as u32)),
-            &I64ShrUnsigned => binary!(self, pop_I64, pop_I64, I64,
+            I64ShrUnsigned => binary!(self, pop_i64, pop_i64, I64,
                                        |x: i64, y| (x as u64).wrapping_shr(y as u32) as i64),
-            &I64Rotl => binary!(self, pop_I64, pop_I64, I64,
+            I64Rotl => binary!(self, pop_i64, pop_i64, I64,
                                 |x: i64, y| x.rotate_left(y as u32 % 64)),
-            &I64Rotr => binary!(self, pop_I64, pop_I64, I64,
+            I64Rotr => binary!(self, pop_i64, pop_i64, I64,
                                 |x: i64, y| x.rotate_right(y as u32 % 64)),
 
-            &F32Abs => unary!(self, pop_F32, F32, |f| f.abs()),
-            &F32Neg => unary!(self, pop_F32, F32, |f| -f),
-            &F32Ceil => unary!(self, pop_F32, F32, |f| f.ceil()),
-            &F32Floor => unary!(self, pop_F32, F32, |f| f.floor()),
-            &F32Trunc => unary!(self, pop_F32, F32, |f| f.trunc()),
-            &F32Nearest => unary!(self, pop_F32, F32, |f| f.round()),
-            &F32Sqrt => unary!(self, pop_F32, F32, |f| f.sqrt()),
-            &F32Add => binary!(self, pop_F32, pop_F32, F32, |x, y| x + y),
-            &F32Sub => binary!(self, pop_F32, pop_F32, F32, |x, y| x - y),
-            &F32Mul => binary!(self, pop_F32, pop_F32, F32, |x, y| x * y),
-            &F32Div => binary!(self, pop_F32, pop_F32, F32, |x, y| x / y),
-            &F32Min => binary!(self, pop_F32, pop_F32, F32, |x: f32, y| x.min(y)),
-            &F32Max => binary!(self, pop_F32, pop_F32, F32, |x: f32, y| x.max(y)),
-            &F32Copysign => binary!(self, pop_F32, pop_F32, F32, |x, y: f32| x * x.signum() * y.signum()),
-
-            &F64Abs => unary!(self, pop_F64, F64, |f| f.abs()),
-            &F64Neg => unary!(self, pop_F64, F64, |f| -f),
-            &F64Ceil => unary!(self, pop_F64, F64, |f| f.ceil()),
-            &F64Floor => unary!(self, pop_F64, F64, |f| f.floor()),
-            &F64Trunc => unary!(self, pop_F64, F64, |f| f.trunc()),
-            &F64Nearest => unary!(self, pop_F64, F64, |f| f.round()),
-            &F64Sqrt => unary!(self, pop_F64, F64, |f| f.sqrt()),
-            &F64Add => binary!(self, pop_F64, pop_F64, F64, |x, y| x + y),
-            &F64Sub => binary!(self, pop_F64, pop_F64, F64, |x, y| x - y),
-            &F64Mul => binary!(self, pop_F64, pop_F64, F64, |x, y| x * y),
-            &F64Div => binary!(self, pop_F64, pop_F64, F64, |x, y| x / y),
-            &F64Min => binary!(self, pop_F64, pop_F64, F64, |x: f64, y| x.min(y)),
-            &F64Max => binary!(self, pop_F64, pop_F64, F64, |x: f64, y| x.max(y)),
-            &F64Copysign => binary!(self, pop_F64, pop_F64, F64, |x, y: f64| x * x.signum() * y.signum()),
-
-            &I32WrapI64 => unary!(self, pop_I64, I32, |i| i as i32),
-
-            &I32TruncSignedF32 => unary!(self, pop_F32, I32, |f| f.trunc() as i32),
-            &I32TruncUnsignedF32 => unary!(self, pop_F32, I32, |f| f.trunc() as u32 as i32),
-            &I32TruncSignedF64 => unary!(self, pop_F64, I32, |f| f.trunc() as i32),
-            &I32TruncUnsignedF64 => unary!(self, pop_F64, I32, |f| f.trunc() as u32 as i32),
-
-            &I64ExtendSignedI32 => unary!(self, pop_I32, I64, |i| i as i64),
-            &I64ExtendUnsignedI32 => unary!(self, pop_I32, I64, |i| i as u32 as i64),
-            &I64TruncSignedF32 => unary!(self, pop_F32, I64, |f| f.trunc() as i64),
-            &I64TruncUnsignedF32 => unary!(self, pop_F32, I64, |f| f.trunc() as u64 as i64),
-            &I64TruncSignedF64 => unary!(self, pop_F64, I64, |f| f.trunc() as i64),
-            &I64TruncUnsignedF64 => unary!(self, pop_F64, I64, |f| f.trunc() as u64 as i64),
-
-            &F32ConvertSignedI32 => unary!(self, pop_I32, F32, |i| i as f32),
-            &F32ConvertUnsignedI32 => unary!(self, pop_I32, F32, |i| i as u32 as f32),
-            &F32ConvertSignedI64 => unary!(self, pop_I64, F32, |i| i as f32),
-            &F32ConvertUnsignedI64 => unary!(self, pop_I64, F32, |i| i as u64 as f32),
-            &F32DemoteF64 => unary!(self, pop_F64, F32, |f| f as f32),
-
-            &F64ConvertSignedI32 => unary!(self, pop_I32, F64, |i| i as f64),
-            &F64ConvertUnsignedI32 => unary!(self, pop_I32, F64, |i| i as u32 as f64),
-            &F64ConvertSignedI64 => unary!(self, pop_I64, F64, |i| i as f64),
-            &F64ConvertUnsignedI64 => unary!(self, pop_I64, F64, |i| i as u64 as f64),
-            &F64PromoteF32 => unary!(self, pop_F32, F64, |f| f as f64),
-
-            &F32ReinterpretI32 => unary!(self, pop_I32, F32, |i| f32::from_bits(i as u32)),
-            &F64ReinterpretI64 => unary!(self, pop_I64, F64, |i| f64::from_bits(i as u64)),
-            &I32ReinterpretF32 => unary!(self, pop_F32, I32, |f| f.to_bits() as i32),
-            &I64ReinterpretF64 => unary!(self, pop_F64, I64, |f| f.to_bits() as i64),
-
-            &I32Const(c) => self.push(Value::I32(c)),
-            &I64Const(c) => self.push(Value::I64(c)),
-            &F32Const(c) => self.push(Value::F32(c)),
-            &F64Const(c) => self.push(Value::F64(c)),
-
-            &I32Load(ref immed) => mem_load!(self, immed, i32_load, Value::I32),
-            &I64Load(ref immed) => mem_load!(self, immed, i64_load, Value::I64),
-            &F32Load(ref immed) => mem_load!(self, immed, f32_load, Value::F32),
-            &F64Load(ref immed) => mem_load!(self, immed, f64_load, Value::F64),
-            &I32Load8Signed(ref immed) => mem_load!(self, immed, i32_load8_s, Value::I32),
-            &I32Load8Unsigned(ref immed) => mem_load!(self, immed, i32_load8_u, Value::I32),
-            &I32Load16Signed(ref immed) => mem_load!(self, immed, i32_load16_s, Value::I32),
-            &I32Load16Unsigned(ref immed) => mem_load!(self, immed, i32_load16_u, Value::I32),
-            &I64Load8Signed(ref immed) => mem_load!(self, immed, i64_load8_s, Value::I64),
-            &I64Load8Unsigned(ref immed) => mem_load!(self, immed, i64_load8_u, Value::I64),
-            &I64Load16Signed(ref immed) => mem_load!(self, immed, i64_load16_s, Value::I64),
-            &I64Load16Unsigned(ref immed) => mem_load!(self, immed, i64_load16_u, Value::I64),
-            &I64Load32Signed(ref immed) => mem_load!(self, immed, i64_load32_s, Value::I64),
-            &I64Load32Unsigned(ref immed) => mem_load!(self, immed, i64_load32_u, Value::I64),
-
-            &I32Store(ref immed) => mem_store!(self, immed, i32_store, pop_I32),
-            &I64Store(ref immed) => mem_store!(self, immed, i64_store, pop_I64),
-            &F32Store(ref immed) => mem_store!(self, immed, f32_store, pop_F32),
-            &F64Store(ref immed) => mem_store!(self, immed, f64_store, pop_F64),
-            &I32Store8(ref immed) => mem_store!(self, immed, i32_store8, pop_I32),
-            &I32Store16(ref immed) => mem_store!(self, immed, i32_store16, pop_I32),
-            &I64Store8(ref immed) => mem_store!(self, immed, i64_store8, pop_I64),
-            &I64Store16(ref immed) => mem_store!(self, immed, i64_store16, pop_I64),
-            &I64Store32(ref immed) => mem_store!(self, immed, i64_store32, pop_I64),
-
-            &CurrentMemory(0) => {
+            F32Abs => unary!(self, pop_f32, F32, |f| f.abs()),
+            F32Neg => unary!(self, pop_f32, F32, |f| -f),
+            F32Ceil => unary!(self, pop_f32, F32, float::ceil_f32),
+            F32Floor => unary!(self, pop_f32, F32, float::floor_f32),
+            F32Trunc => unary!(self, pop_f32, F32, float::trunc_f32),
+            F32Nearest => unary!(self, pop_f32, F32, float::nearest_f32),
+            F32Sqrt => unary!(self, pop_f32, F32, |f| f.sqrt()),
+            F32Add => binary!(self, pop_f32, pop_f32, F32, |x, y| x + y),
+            F32Sub => binary!(self, pop_f32, pop_f32, F32, |x, y| x - y),
+            F32Mul => binary!(self, pop_f32, pop_f32, F32, |x, y| x * y),
+            F32Div => binary!(self, pop_f32, pop_f32, F32, |x, y| x / y),
+            F32Min => binary!(self, pop_f32, pop_f32, F32, float::min_f32),
+            F32Max => binary!(self, pop_f32, pop_f32, F32, float::max_f32),
+            F32Copysign => binary!(self, pop_f32, pop_f32, F32, float::copysign_f32),
+
+            F64Abs => unary!(self, pop_f64, F64, |f| f.abs()),
+            F64Neg => unary!(self, pop_f64, F64, |f| -f),
+            F64Ceil => unary!(self, pop_f64, F64, float::ceil_f64),
+            F64Floor => unary!(self, pop_f64, F64, float::floor_f64),
+            F64Trunc => unary!(self, pop_f64, F64, float::trunc_f64),
+            F64Nearest => unary!(self, pop_f64, F64, float::nearest_f64),
+            F64Sqrt => unary!(self, pop_f64, F64, |f| f.sqrt()),
+            F64Add => binary!(self, pop_f64, pop_f64, F64, |x, y| x + y),
+            F64Sub => binary!(self, pop_f64, pop_f64, F64, |x, y| x - y),
+            F64Mul => binary!(self, pop_f64, pop_f64, F64, |x, y| x * y),
+            F64Div => binary!(self, pop_f64, pop_f64, F64, |x, y| x / y),
+            F64Min => binary!(self, pop_f64, pop_f64, F64, float::min_f64),
+            F64Max => binary!(self, pop_f64, pop_f64, F64, float::max_f64),
+            F64Copysign => binary!(self, pop_f64, pop_f64, F64, float::copysign_f64),
+
+            I32WrapI64 => unary!(self, pop_i64, I32, |i| i as i32),
+
+            I32TruncSignedF32 => unary_trap!(self, pop_f32, I32, trunc_i32_f32),
+            I32TruncUnsignedF32 => unary_trap!(self, pop_f32, I32, trunc_u32_f32),
+            I32TruncSignedF64 => unary_trap!(self, pop_f64, I32, trunc_i32_f64),
+            I32TruncUnsignedF64 => unary_trap!(self, pop_f64, I32, trunc_u32_f64),
+
+            I64ExtendSignedI32 => unary!(self, pop_i32, I64, |i| i as i64),
+            I64ExtendUnsignedI32 => unary!(self, pop_i32, I64, |i| i as u32 as i64),
+            I64TruncSignedF32 => unary_trap!(self, pop_f32, I64, trunc_i64_f32),
+            I64TruncUnsignedF32 => unary_trap!(self, pop_f32, I64, trunc_u64_f32),
+            I64TruncSignedF64 => unary_trap!(self, pop_f64, I64, trunc_i64_f64),
+            I64TruncUnsignedF64 => unary_trap!(self, pop_f64, I64, trunc_u64_f64),
+
+            F32ConvertSignedI32 => unary!(self, pop_i32, F32, |i| i as f32),
+            F32ConvertUnsignedI32 => unary!(self, pop_i32, F32, |i| i as u32 as f32),
+            F32ConvertSignedI64 => unary!(self, pop_i64, F32, |i| i as f32),
+            F32ConvertUnsignedI64 => unary!(self, pop_i64, F32, |i| i as u64 as f32),
+            F32DemoteF64 => unary!(self, pop_f64, F32, |f| f as f32),
+
+            F64ConvertSignedI32 => unary!(self, pop_i32, F64, |i| i as f64),
+            F64ConvertUnsignedI32 => unary!(self, pop_i32, F64, |i| i as u32 as f64),
+            F64ConvertSignedI64 => unary!(self, pop_i64, F64, |i| i as f64),
+            F64ConvertUnsignedI64 => unary!(self, pop_i64, F64, |i| i as u64 as f64),
+            F64PromoteF32 => unary!(self, pop_f32, F64, |f| f as f64),
+
+            F32ReinterpretI32 => unary!(self, pop_i32, F32, |i| f32::from_bits(i as u32)),
+            F64ReinterpretI64 => unary!(self, pop_i64, F64, |i| f64::from_bits(i as u64)),
+            I32ReinterpretF32 => unary!(self, pop_f32, I32, |f| f.to_bits() as i32),
+            I64ReinterpretF64 => unary!(self, pop_f64, I64, |f| f.to_bits() as i64),
+
+            I32Extend8Signed => unary!(self, pop_i32, I32, |i| i as i8 as i32),
+            I32Extend16Signed => unary!(self, pop_i32, I32, |i| i as i16 as i32),
+            I64Extend8Signed => unary!(self, pop_i64, I64, |i| i as i8 as i64),
+            I64Extend16Signed => unary!(self, pop_i64, I64, |i| i as i16 as i64),
+            I64Extend32Signed => unary!(self, pop_i64, I64, |i| i as i32 as i64),
+
+            I32Const(c) => self.push(Value::I32(c)),
+            I64Const(c) => self.push(Value::I64(c)),
+            F32Const(c) => self.push(Value::F32(c)),
+            F64Const(c) => self.push(Value::F64(c)),
+
+            I32Load(ref immed) => mem_load!(self, immed, i32_load, Value::I32),
+            I64Load(ref immed) => mem_load!(self, immed, i64_load, Value::I64),
+            F32Load(ref immed) => mem_load!(self, immed, f32_load, Value::F32),
+            F64Load(ref immed) => mem_load!(self, immed, f64_load, Value::F64),
+            I32Load8Signed(ref immed) => mem_load!(self, immed, i32_load8_s, Value::I32),
+            I32Load8Unsigned(ref immed) => mem_load!(self, immed, i32_load8_u, Value::I32),
+            I32Load16Signed(ref immed) => mem_load!(self, immed, i32_load16_s, Value::I32),
+            I32Load16Unsigned(ref immed) => mem_load!(self, immed, i32_load16_u, Value::I32),
+            I64Load8Signed(ref immed) => mem_load!(self, immed, i64_load8_s, Value::I64),
+            I64Load8Unsigned(ref immed) => mem_load!(self, immed, i64_load8_u, Value::I64),
+            I64Load16Signed(ref immed) => mem_load!(self, immed, i64_load16_s, Value::I64),
+            I64Load16Unsigned(ref immed) => mem_load!(self, immed, i64_load16_u, Value::I64),
+            I64Load32Signed(ref immed) => mem_load!(self, immed, i64_load32_s, Value::I64),
+            I64Load32Unsigned(ref immed) => mem_load!(self, immed, i64_load32_u, Value::I64),
+
+            I32Store(ref immed) => mem_store!(self, immed, i32_store, pop_i32),
+            I64Store(ref immed) => mem_store!(self, immed, i64_store, pop_i64),
+            F32Store(ref immed) => mem_store!(self, immed, f32_store, pop_f32),
+            F64Store(ref immed) => mem_store!(self, immed, f64_store, pop_f64),
+            I32Store8(ref immed) => mem_store!(self, immed, i32_store8, pop_i32),
+            I32Store16(ref immed) => mem_store!(self, immed, i32_store16, pop_i32),
+            I64Store8(ref immed) => mem_store!(self, immed, i64_store8, pop_i64),
+            I64Store16(ref immed) => mem_store!(self, immed, i64_store16, pop_i64),
+            I64Store32(ref immed) => mem_store!(self, immed, i64_store32, pop_i64),
+
+            CurrentMemory(0) => {
                 let pages = self.mem.len_pages() as i32;
                 self.push(Value::I32(pages))
             },
-            &GrowMemory(0) => {
-                self.pop_I32()
+            GrowMemory(0) => {
+                self.pop_i32()
                     .map(|v| self.mem.grow(v))
                     .and_then(|v| self.push(Value::I32(v)))
             },
@@ -299,13 +929,390 @@ impl Context {
 #[cfg(test)]
 mod test {
     use super::*;
+    use expr::Op::*;
+    use ty::BlockType;
+
+    fn function(params: Vec<ValueType>, ret: Option<ValueType>,
+                locals: Vec<ValueType>, ops: Vec<expr::Op>) -> Function {
+        Function {
+            ty: FunctionType { params, results: ret.into_iter().collect() },
+            body: Body::Wasm(Rc::new(Code::new(locals, ops, vec![]))),
+        }
+    }
+
+    fn run(ret: Option<ValueType>, ops: Vec<expr::Op>) -> Result<Vec<Value>, RuntimeError> {
+        let mut ctx = Context::new();
+        ctx.funcs.push(function(vec![], ret, vec![], ops));
+        ctx.invoke(0, &[]).map_err(|t| t.error)
+    }
+
+    #[test]
+    fn test_block_branch() {
+        let i32_block = || Block(BlockType::Single(ValueType::I32));
+        assert_eq!(run(Some(ValueType::I32),
+                       vec![i32_block(), I32Const(1), I32Const(2), Branch(0), I32Const(3), End]),
+                   Ok(vec![Value::I32(2)]));
+        assert_eq!(run(Some(ValueType::I32),
+                       vec![i32_block(), I32Const(5), Return, End, I32Const(6)]),
+                   Ok(vec![Value::I32(5)]));
+        assert_eq!(run(None, vec![Loop(BlockType::Void), I32Const(0), BranchIf(0), End]),
+                   Ok(vec![]));
+        assert_eq!(run(None, vec![Branch(1)]),
+                   Err(RuntimeError::BadControl));
+    }
+
+    #[test]
+    fn test_if_else() {
+        let cond = |c| run(Some(ValueType::I32),
+                           vec![I32Const(c), If(BlockType::Single(ValueType::I32)),
+                                I32Const(10), Else, I32Const(20), End]);
+        assert_eq!(cond(1), Ok(vec![Value::I32(10)]));
+        assert_eq!(cond(0), Ok(vec![Value::I32(20)]));
+
+        assert_eq!(run(Some(ValueType::I32),
+                       vec![I32Const(7), I32Const(0), If(BlockType::Void), Unreachable, End]),
+                   Ok(vec![Value::I32(7)]));
+    }
+
+    #[test]
+    fn test_branch_table() {
+        let table = |i| run(Some(ValueType::I32), vec![
+            Block(BlockType::Void),
+            Block(BlockType::Void),
+            Block(BlockType::Void),
+            I32Const(i),
+            BranchTable(expr::BranchTable { targets: vec![0, 1], default: 2 }),
+            End,
+            I32Const(100), Return,
+            End,
+            I32Const(101), Return,
+            End,
+            I32Const(102),
+        ]);
+        assert_eq!(table(0), Ok(vec![Value::I32(100)]));
+        assert_eq!(table(1), Ok(vec![Value::I32(101)]));
+        assert_eq!(table(2), Ok(vec![Value::I32(102)]));
+        assert_eq!(table(-1), Ok(vec![Value::I32(102)]));
+    }
 
     #[test]
     fn test_basic() {
         let mut ctx = Context::new();
+        ctx.exec(&expr::Op::I32Const(4))
+            .unwrap();
         ctx.exec(&expr::Op::I32Const(3))
             .unwrap();
         ctx.exec(&expr::Op::I32Store(expr::MemoryImmed { align: 0, offset: 0 }))
             .unwrap();
     }
+
+    #[test]
+    fn test_memory() {
+        let immed = |offset| expr::MemoryImmed { align: 0, offset };
+        assert_eq!(run(Some(ValueType::I32),
+                       vec![I32Const(8), I64Const(-1), I64Store(immed(0)),
+                            I32Const(4), I32Load(immed(8))]),
+                   Ok(vec![Value::I32(-1)]));
+        assert_eq!(run(Some(ValueType::I32), vec![I32Const(-1), I32Load8Unsigned(immed(1))]),
+                   Err(RuntimeError::MemoryFault));
+        assert_eq!(run(None, vec![I32Const(0xfffd), I32Const(0), I32Store(immed(0))]),
+                   Err(RuntimeError::MemoryFault));
+    }
+
+    #[test]
+    fn test_compare() {
+        let i32_op = |x, y, op| run(Some(ValueType::I32), vec![I32Const(x), I32Const(y), op]);
+        assert_eq!(i32_op(-1, 1, I32LtSigned), Ok(vec![Value::I32(1)]));
+        assert_eq!(i32_op(-1, 1, I32LtUnsigned), Ok(vec![Value::I32(0)]));
+        assert_eq!(i32_op(-1, 1, I32GeUnsigned), Ok(vec![Value::I32(1)]));
+        assert_eq!(i32_op(3, 1, I32Sub), Ok(vec![Value::I32(2)]));
+        assert_eq!(run(Some(ValueType::I32), vec![I32Const(0), I32Eqz]), Ok(vec![Value::I32(1)]));
+
+        let i64_op = |x, y, op| run(Some(ValueType::I32), vec![I64Const(x), I64Const(y), op]);
+        assert_eq!(i64_op(1 << 40, -1, I64GtSigned), Ok(vec![Value::I32(1)]));
+        assert_eq!(i64_op(1 << 40, -1, I64GtUnsigned), Ok(vec![Value::I32(0)]));
+        assert_eq!(i64_op(5, 5, I64LeUnsigned), Ok(vec![Value::I32(1)]));
+
+        let f64_op = |x, y, op| run(Some(ValueType::I32), vec![F64Const(x), F64Const(y), op]);
+        assert_eq!(f64_op(f64::NAN, 1.0, F64Lt), Ok(vec![Value::I32(0)]));
+        assert_eq!(f64_op(f64::NAN, 1.0, F64Ge), Ok(vec![Value::I32(0)]));
+        assert_eq!(f64_op(f64::NAN, f64::NAN, F64Ne), Ok(vec![Value::I32(1)]));
+        assert_eq!(f64_op(-0.0, 0.0, F64Eq), Ok(vec![Value::I32(1)]));
+        assert_eq!(run(Some(ValueType::I32), vec![F32Const(1.0), F32Const(2.0), F32Lt]),
+                   Ok(vec![Value::I32(1)]));
+    }
+
+    #[test]
+    fn test_integer() {
+        let i32_op = |x, y, op| run(Some(ValueType::I32), vec![I32Const(x), I32Const(y), op]);
+        assert_eq!(i32_op(i32::MAX, 1, I32Add), Ok(vec![Value::I32(i32::MIN)]));
+        assert_eq!(i32_op(i32::MIN, 1, I32Sub), Ok(vec![Value::I32(i32::MAX)]));
+        assert_eq!(i32_op(0x10000, 0x10000, I32Mul), Ok(vec![Value::I32(0)]));
+        assert_eq!(i32_op(i32::MIN, -1, I32DivSigned), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(i32_op(i32::MIN, -1, I32RemSigned), Ok(vec![Value::I32(0)]));
+        assert_eq!(i32_op(-7, 2, I32DivSigned), Ok(vec![Value::I32(-3)]));
+        assert_eq!(i32_op(-7, 2, I32RemSigned), Ok(vec![Value::I32(-1)]));
+        assert_eq!(i32_op(-7, 2, I32DivUnsigned), Ok(vec![Value::I32(0x7ffffffc)]));
+        assert_eq!(i32_op(1, 0, I32RemUnsigned), Err(RuntimeError::DivideByZero));
+        assert_eq!(i32_op(1, 33, I32Shl), Ok(vec![Value::I32(2)]));
+        assert_eq!(i32_op(-2, 33, I32ShrSigned), Ok(vec![Value::I32(-1)]));
+        assert_eq!(i32_op(-2, 33, I32ShrUnsigned), Ok(vec![Value::I32(0x7fffffff)]));
+        assert_eq!(i32_op(1, -1, I32Rotl), Ok(vec![Value::I32(i32::MIN)]));
+        assert_eq!(run(Some(ValueType::I32), vec![I32Const(0), I32Clz]), Ok(vec![Value::I32(32)]));
+
+        let i64_op = |x, y, op| run(Some(ValueType::I64), vec![I64Const(x), I64Const(y), op]);
+        assert_eq!(i64_op(i64::MAX, 1, I64Add), Ok(vec![Value::I64(i64::MIN)]));
+        assert_eq!(i64_op(i64::MIN, 1, I64Sub), Ok(vec![Value::I64(i64::MAX)]));
+        assert_eq!(i64_op(1 << 32, 1 << 32, I64Mul), Ok(vec![Value::I64(0)]));
+        assert_eq!(i64_op(i64::MIN, -1, I64DivSigned), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(i64_op(i64::MIN, -1, I64RemSigned), Ok(vec![Value::I64(0)]));
+        assert_eq!(i64_op(1, 0, I64DivSigned), Err(RuntimeError::DivideByZero));
+        assert_eq!(i64_op(1, 65, I64Shl), Ok(vec![Value::I64(2)]));
+        assert_eq!(i64_op(-1, 1, I64ShrUnsigned), Ok(vec![Value::I64(i64::MAX)]));
+        assert_eq!(i64_op(1, -1, I64Rotr), Ok(vec![Value::I64(2)]));
+    }
+
+    #[test]
+    fn test_sign_extension() {
+        let i32_op = |x, op| run(Some(ValueType::I32), vec![I32Const(x), op]);
+        assert_eq!(i32_op(0x7f, I32Extend8Signed), Ok(vec![Value::I32(127)]));
+        assert_eq!(i32_op(0x80, I32Extend8Signed), Ok(vec![Value::I32(-128)]));
+        assert_eq!(i32_op(0x1234_8000, I32Extend16Signed), Ok(vec![Value::I32(-0x8000)]));
+
+        let i64_op = |x, op| run(Some(ValueType::I64), vec![I64Const(x), op]);
+        assert_eq!(i64_op(0x01ff, I64Extend8Signed), Ok(vec![Value::I64(-1)]));
+        assert_eq!(i64_op(0x7fff, I64Extend16Signed), Ok(vec![Value::I64(0x7fff)]));
+        assert_eq!(i64_op(0x1_8000_0000, I64Extend32Signed), Ok(vec![Value::I64(-0x8000_0000)]));
+        assert_eq!(run(Some(ValueType::I64), vec![I32Const(1), I64Extend8Signed]),
+                   Err(RuntimeError::TypeFault));
+    }
+
+    #[test]
+    fn test_float() {
+        let f32_op = |x, y, op| run(Some(ValueType::F32), vec![F32Const(x), F32Const(y), op]);
+        let bits = |r: Result<Vec<Value>, RuntimeError>| match r {
+            Ok(ref v) if v.len() == 1 => match v[0] {
+                Value::F32(f) => f.to_bits() as u64,
+                Value::F64(f) => f.to_bits(),
+                _ => panic!("not a float"),
+            },
+            _ => panic!("{:?}", r),
+        };
+
+        assert_eq!(bits(f32_op(3.0, 2.0, F32Sub)), 1.0f32.to_bits() as u64);
+        assert_eq!(bits(f32_op(1.0, 4.0, F32Div)), 0.25f32.to_bits() as u64);
+        assert_eq!(bits(f32_op(-0.0, 0.0, F32Min)), (-0.0f32).to_bits() as u64);
+        assert_eq!(bits(f32_op(1.0, -0.0, F32Copysign)), (-1.0f32).to_bits() as u64);
+        assert_eq!(bits(run(Some(ValueType::F64), vec![F64Const(2.5), F64Nearest])),
+                   2.0f64.to_bits());
+        assert_eq!(bits(run(Some(ValueType::F64), vec![F64Const(-0.0), F64Neg])), 0);
+        assert_eq!(bits(run(Some(ValueType::F64),
+                            vec![F64Const(f64::from_bits(0x7ff4_0000_0000_0000)), F64Abs])),
+                   0x7ff4_0000_0000_0000);
+        assert!(match run(Some(ValueType::F64),
+                          vec![F64Const(f64::NAN), F64Const(1.0), F64Max]) {
+            Ok(ref v) => match v[0] { Value::F64(f) => f.is_nan(), _ => false },
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_trunc() {
+        use std::{f32, f64};
+
+        assert_eq!(trunc_i32_f32(-2147483648.0), Ok(i32::MIN));
+        assert_eq!(trunc_i32_f32(2147483648.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_i32_f64(-2147483648.9), Ok(i32::MIN));
+        assert_eq!(trunc_i32_f64(-2147483649.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_i32_f64(2147483647.9), Ok(i32::MAX));
+        assert_eq!(trunc_u32_f32(-0.9), Ok(0));
+        assert_eq!(trunc_u32_f32(-1.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_u32_f64(4294967295.9), Ok(-1));
+        assert_eq!(trunc_u32_f64(4294967296.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_i64_f32(-9223372036854775808.0), Ok(i64::MIN));
+        assert_eq!(trunc_i64_f64(9223372036854775808.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_i64_f64(9223372036854774784.0), Ok(9223372036854774784));
+        assert_eq!(trunc_u64_f32(18446742974197923840.0), Ok(-1099511627776));
+        assert_eq!(trunc_u64_f64(18446744073709551616.0), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_u64_f64(f64::INFINITY), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(trunc_i32_f32(f32::NAN), Err(RuntimeError::InvalidConversionToInteger));
+        assert_eq!(trunc_u64_f64(-f64::NAN), Err(RuntimeError::InvalidConversionToInteger));
+
+        assert_eq!(run(Some(ValueType::I32), vec![F32Const(-1.5), I32TruncSignedF32]),
+                   Ok(vec![Value::I32(-1)]));
+        assert_eq!(run(Some(ValueType::I64), vec![F64Const(f64::NAN), I64TruncUnsignedF64]),
+                   Err(RuntimeError::InvalidConversionToInteger));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut ctx = Context::new();
+        let counter = Global::new(GlobalType { content: ValueType::I32, mutable: true },
+                                  Value::I32(10));
+        ctx.globals.push(counter.clone());
+        ctx.globals.push(Global::new(GlobalType { content: ValueType::I64, mutable: false },
+                                     Value::I64(1)));
+        ctx.funcs.push(function(vec![], Some(ValueType::I32), vec![],
+                                vec![GetGlobal(0), I32Const(1), I32Add, SetGlobal(0),
+                                     GetGlobal(0)]));
+        ctx.funcs.push(function(vec![], None, vec![], vec![I64Const(2), SetGlobal(1)]));
+        ctx.funcs.push(function(vec![], None, vec![], vec![I64Const(2), SetGlobal(0)]));
+
+        assert_eq!(ctx.invoke(0, &[]), Ok(vec![Value::I32(11)]));
+        assert_eq!(counter.get(), Value::I32(11));
+        counter.set(Value::I32(20));
+        assert_eq!(ctx.invoke(0, &[]), Ok(vec![Value::I32(21)]));
+        assert_eq!(ctx.invoke(1, &[]).map_err(|t| t.error), Err(RuntimeError::TypeFault));
+        assert_eq!(ctx.invoke(2, &[]).map_err(|t| t.error), Err(RuntimeError::TypeFault));
+    }
+
+    #[test]
+    fn test_call_indirect() {
+        let i32_to_i32 = FunctionType { params: vec![ValueType::I32], results: vec![ValueType::I32] };
+        let mut ctx = Context::new();
+        ctx.types = vec![i32_to_i32.clone(), FunctionType { params: vec![], results: vec![] }];
+        ctx.funcs.push(function(vec![ValueType::I32], Some(ValueType::I32), vec![],
+                                vec![GetLocal(0), I32Const(1), I32Add]));
+        ctx.funcs.push(function(vec![ValueType::I32], Some(ValueType::I32), vec![],
+                                vec![GetLocal(0), I32Const(2), I32Mul]));
+        ctx.funcs.push(function(vec![], None, vec![], vec![]));
+        ctx.funcs.push(function(vec![ValueType::I32, ValueType::I32], Some(ValueType::I32),
+                                vec![],
+                                vec![GetLocal(0), GetLocal(1), CallIndirect(0)]));
+
+        let mut table = table::Table::limited(4, None, None).unwrap();
+        table.init(0, &[0, 1, 2]).unwrap();
+        ctx.tables.push(table);
+
+        let call = |ctx: &mut Context, x, elem| {
+            ctx.invoke(3, &[Value::I32(x), Value::I32(elem)]).map_err(|t| t.error)
+        };
+        assert_eq!(call(&mut ctx, 10, 0), Ok(vec![Value::I32(11)]));
+        assert_eq!(call(&mut ctx, 10, 1), Ok(vec![Value::I32(20)]));
+        assert_eq!(call(&mut ctx, 10, 2), Err(RuntimeError::IndirectCallTypeMismatch));
+        assert_eq!(call(&mut ctx, 10, 3), Err(RuntimeError::UninitializedElement));
+        assert_eq!(call(&mut ctx, 10, 4), Err(RuntimeError::UndefinedElement));
+        assert_eq!(call(&mut ctx, 10, -1), Err(RuntimeError::UndefinedElement));
+    }
+
+    #[test]
+    fn test_drop_select() {
+        assert_eq!(run(Some(ValueType::I32), vec![I32Const(1), I32Const(2), Drop]),
+                   Ok(vec![Value::I32(1)]));
+        assert_eq!(run(Some(ValueType::I64), vec![I64Const(1), I64Const(2), I32Const(1), Select]),
+                   Ok(vec![Value::I64(1)]));
+        assert_eq!(run(Some(ValueType::F32), vec![F32Const(1.0), F32Const(2.0), I32Const(0), Select]),
+                   Ok(vec![Value::F32(2.0)]));
+        assert_eq!(run(Some(ValueType::I32), vec![I32Const(1), I64Const(2), I32Const(0), Select]),
+                   Err(RuntimeError::TypeFault));
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut ctx = Context::new();
+        ctx.funcs.push(function(vec![ValueType::I32], Some(ValueType::I32),
+                                vec![ValueType::I32],
+                                vec![GetLocal(0), TeeLocal(1), GetLocal(1), I32Add]));
+        assert_eq!(ctx.invoke(0, &[Value::I32(4)]), Ok(vec![Value::I32(8)]));
+        assert_eq!(ctx.invoke(0, &[Value::I64(4)]).map_err(|t| t.error), Err(RuntimeError::TypeFault));
+
+        let mut ctx = Context::new();
+        ctx.funcs.push(function(vec![], None, vec![ValueType::I32],
+                                vec![F32Const(1.0), SetLocal(0)]));
+        assert_eq!(ctx.invoke(0, &[]).map_err(|t| t.error), Err(RuntimeError::TypeFault));
+    }
+
+    #[test]
+    fn test_call() {
+        let mut ctx = Context::new();
+        // fac(n) = n == 0 ? 1 : n * fac(n - 1), without comparisons
+        ctx.funcs.push(function(vec![ValueType::I64], Some(ValueType::I64), vec![],
+                                vec![Block(BlockType::Void),
+                                     GetLocal(0), I32WrapI64, BranchIf(0),
+                                     I64Const(1), Return,
+                                     End,
+                                     GetLocal(0),
+                                     GetLocal(0), I64Const(-1), I64Add, Call(0),
+                                     I64Mul]));
+        ctx.funcs.push(function(vec![], Some(ValueType::I64), vec![],
+                                vec![I64Const(5), Call(0), I64Const(1), I64Add]));
+        assert_eq!(ctx.invoke(0, &[Value::I64(10)]), Ok(vec![Value::I64(3628800)]));
+        assert_eq!(ctx.invoke(1, &[]), Ok(vec![Value::I64(121)]));
+        assert_eq!(ctx.stack.len(), 0);
+        assert_eq!(ctx.frames.len(), 0);
+    }
+
+    #[test]
+    fn test_multi_value() {
+        let mut ctx = Context::new();
+        ctx.types = vec![
+            FunctionType { params: vec![ValueType::I32], results: vec![ValueType::I32; 2] },
+            FunctionType { params: vec![ValueType::I32], results: vec![ValueType::I32] },
+        ];
+        let ops = vec![GetLocal(0), Block(BlockType::Index(0)), I32Const(1), I32Add, GetLocal(0), End];
+        ctx.funcs.push(Function {
+            ty: ctx.types[0].clone(),
+            body: Body::Wasm(Rc::new(Code::new(vec![], ops, vec![]))),
+        });
+        // Count down to zero, passing the counter to each iteration.
+        ctx.funcs.push(function(vec![ValueType::I32], Some(ValueType::I32), vec![],
+                                vec![GetLocal(0),
+                                     Loop(BlockType::Index(1)),
+                                     I32Const(-1), I32Add, TeeLocal(0), GetLocal(0), BranchIf(0),
+                                     End]));
+        ctx.funcs.push(function(vec![], Some(ValueType::I32), vec![],
+                                vec![I32Const(5), Call(0), I32Sub]));
+
+        assert_eq!(ctx.invoke(0, &[Value::I32(5)]), Ok(vec![Value::I32(6), Value::I32(5)]));
+        assert_eq!(ctx.invoke(1, &[Value::I32(10)]), Ok(vec![Value::I32(0)]));
+        assert_eq!(ctx.invoke(2, &[]), Ok(vec![Value::I32(1)]));
+        assert_eq!(ctx.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut ctx = Context::new();
+        ctx.funcs.push(function(vec![], None, vec![],
+                                vec![Loop(BlockType::Void), Branch(0), End]));
+        ctx.funcs.push(function(vec![], Some(ValueType::I32), vec![],
+                                vec![I32Const(1), I32Const(2), I32Add]));
+
+        ctx.set_fuel(Some(1000));
+        assert_eq!(ctx.invoke(0, &[]).map_err(|t| t.error), Err(RuntimeError::OutOfFuel));
+        assert_eq!(ctx.fuel(), Some(0));
+        assert_eq!(ctx.stack.len(), 0);
+
+        ctx.add_fuel(2);
+        assert_eq!(ctx.invoke(1, &[]).map_err(|t| t.error), Err(RuntimeError::OutOfFuel));
+        ctx.add_fuel(5);
+        assert_eq!(ctx.invoke(1, &[]), Ok(vec![Value::I32(3)]));
+        assert_eq!(ctx.fuel(), Some(2));
+
+        ctx.set_fuel(None);
+        ctx.add_fuel(5);
+        assert_eq!(ctx.fuel(), None);
+        assert_eq!(ctx.invoke(1, &[]), Ok(vec![Value::I32(3)]));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut ctx = Context::new();
+        ctx.funcs.push(function(vec![], None, vec![], vec![Call(0)]));
+        ctx.funcs.push(function(vec![], None, vec![], vec![I32Const(0), Call(1)]));
+        ctx.funcs.push(function(vec![ValueType::I32], Some(ValueType::I32),
+                                vec![ValueType::I64; 10],
+                                vec![GetLocal(0)]));
+
+        assert_eq!(ctx.invoke(0, &[]).map_err(|t| t.error), Err(RuntimeError::StackOverflow));
+        assert_eq!(ctx.frames.len(), 0);
+
+        ctx.set_limits(Limits { stack: 100, depth: 1000 });
+        assert_eq!(ctx.invoke(1, &[]).map_err(|t| t.error), Err(RuntimeError::StackOverflow));
+        assert_eq!((ctx.stack.len(), ctx.frames.len(), ctx.locals_len), (0, 0, 0));
+
+        ctx.set_limits(Limits { stack: 10, depth: 1 });
+        assert_eq!(ctx.invoke(2, &[Value::I32(1)]).map_err(|t| t.error), Err(RuntimeError::StackOverflow));
+        ctx.set_limits(Limits { stack: 12, depth: 1 });
+        assert_eq!(ctx.invoke(2, &[Value::I32(1)]), Ok(vec![Value::I32(1)]));
+    }
 }
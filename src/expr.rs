@@ -1,11 +1,12 @@
 use untrusted;
 use error::CodecError;
-use ty::BlockType;
+use ty::{BlockType, ValueType};
 use codec;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MemoryImmed {
-    pub align: u8,
+    /// Log2 of the alignment, as encoded; validation bounds it.
+    pub align: u32,
     pub offset: u32
 }
 
@@ -13,16 +14,19 @@ impl MemoryImmed {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<MemoryImmed, CodecError> {
         let flags = codec::read_varu32(rd)?;
         let offset = codec::read_varu32(rd)?;
-        let align = flags as u8;
+        Ok(MemoryImmed { align: flags, offset })
+    }
 
-        Ok(MemoryImmed { align, offset })
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_varu32(out, self.align);
+        codec::write_varu32(out, self.offset);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BranchTable {
-    targets: Vec<u32>,
-    default: u32,
+    pub targets: Vec<u32>,
+    pub default: u32,
 }
 
 impl BranchTable {
@@ -38,10 +42,18 @@ impl BranchTable {
 
         Ok(BranchTable { targets, default })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_varu32(out, self.targets.len() as u32);
+        for target in &self.targets {
+            codec::write_varu32(out, *target);
+        }
+        codec::write_varu32(out, self.default);
+    }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Op {
     Unreachable,
     Nop,
@@ -190,6 +202,11 @@ pub enum Op {
     I64ReinterpretF64,
     F32ReinterpretI32,
     F64ReinterpretI64,
+    I32Extend8Signed,
+    I32Extend16Signed,
+    I64Extend8Signed,
+    I64Extend16Signed,
+    I64Extend32Signed,
     I32Load(MemoryImmed),
     I64Load(MemoryImmed),
     F32Load(MemoryImmed),
@@ -229,7 +246,6 @@ fn call_indirect(rd: &mut untrusted::Reader) -> Result<u32, CodecError> {
 impl Op {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<Op, CodecError> {
         let op = codec::read_u8(rd)?;
-        println!("op {:02x}", op);
         match op {
             0x00 => Ok(Op::Unreachable),
             0x01 => Ok(Op::Nop),
@@ -278,8 +294,8 @@ impl Op {
             0x40 => Ok(Op::GrowMemory(codec::read_varu1(rd)?)),
             0x41 => Ok(Op::I32Const(codec::read_vari32(rd)?)),
             0x42 => Ok(Op::I64Const(codec::read_vari64(rd)?)),
-            0x43 => Ok(Op::F32Const(codec::read_u32(rd)? as f32)),
-            0x44 => Ok(Op::F64Const(codec::read_u64(rd)? as f64)),
+            0x43 => Ok(Op::F32Const(f32::from_bits(codec::read_u32(rd)?))),
+            0x44 => Ok(Op::F64Const(f64::from_bits(codec::read_u64(rd)?))),
             0x45 => Ok(Op::I32Eqz),
             0x46 => Ok(Op::I32Eq),
             0x47 => Ok(Op::I32Ne),
@@ -404,25 +420,358 @@ impl Op {
             0xbe => Ok(Op::F32ReinterpretI32),
             0xbf => Ok(Op::F64ReinterpretI64),
 
+            0xc0 => Ok(Op::I32Extend8Signed),
+            0xc1 => Ok(Op::I32Extend16Signed),
+            0xc2 => Ok(Op::I64Extend8Signed),
+            0xc3 => Ok(Op::I64Extend16Signed),
+            0xc4 => Ok(Op::I64Extend32Signed),
+
             op => Err(CodecError::BadOpcode(op)),
         }
     }
 
-    fn is_init_op(&self) -> bool {
+    pub fn encode(&self, out: &mut Vec<u8>) {
         match *self {
-            Op::I32Const(_) |
-                Op::I64Const(_) |
-                Op::F32Const(_) |
-                Op::F64Const(_) |
-                Op::GetGlobal(_) => true,
-            _ => false
+            Op::Unreachable => codec::write_u8(out, 0x00),
+            Op::Nop => codec::write_u8(out, 0x01),
+            Op::Block(ref ty) => {
+                codec::write_u8(out, 0x02);
+                ty.encode(out);
+            }
+            Op::Loop(ref ty) => {
+                codec::write_u8(out, 0x03);
+                ty.encode(out);
+            }
+            Op::If(ref ty) => {
+                codec::write_u8(out, 0x04);
+                ty.encode(out);
+            }
+            Op::Else => codec::write_u8(out, 0x05),
+            Op::End => codec::write_u8(out, 0x0b),
+            Op::Branch(index) => {
+                codec::write_u8(out, 0x0c);
+                codec::write_varu32(out, index);
+            }
+            Op::BranchIf(index) => {
+                codec::write_u8(out, 0x0d);
+                codec::write_varu32(out, index);
+            }
+            Op::BranchTable(ref table) => {
+                codec::write_u8(out, 0x0e);
+                table.encode(out);
+            }
+            Op::Return => codec::write_u8(out, 0x0f),
+            Op::Call(index) => {
+                codec::write_u8(out, 0x10);
+                codec::write_varu32(out, index);
+            }
+            Op::CallIndirect(index) => {
+                codec::write_u8(out, 0x11);
+                codec::write_varu32(out, index);
+                codec::write_u8(out, 0);
+            }
+            Op::Drop => codec::write_u8(out, 0x1a),
+            Op::Select => codec::write_u8(out, 0x1b),
+            Op::GetLocal(index) => {
+                codec::write_u8(out, 0x20);
+                codec::write_varu32(out, index);
+            }
+            Op::SetLocal(index) => {
+                codec::write_u8(out, 0x21);
+                codec::write_varu32(out, index);
+            }
+            Op::TeeLocal(index) => {
+                codec::write_u8(out, 0x22);
+                codec::write_varu32(out, index);
+            }
+            Op::GetGlobal(index) => {
+                codec::write_u8(out, 0x23);
+                codec::write_varu32(out, index);
+            }
+            Op::SetGlobal(index) => {
+                codec::write_u8(out, 0x24);
+                codec::write_varu32(out, index);
+            }
+            Op::I32Load(ref immed) => {
+                codec::write_u8(out, 0x28);
+                immed.encode(out);
+            }
+            Op::I64Load(ref immed) => {
+                codec::write_u8(out, 0x29);
+                immed.encode(out);
+            }
+            Op::F32Load(ref immed) => {
+                codec::write_u8(out, 0x2a);
+                immed.encode(out);
+            }
+            Op::F64Load(ref immed) => {
+                codec::write_u8(out, 0x2b);
+                immed.encode(out);
+            }
+            Op::I32Load8Signed(ref immed) => {
+                codec::write_u8(out, 0x2c);
+                immed.encode(out);
+            }
+            Op::I32Load8Unsigned(ref immed) => {
+                codec::write_u8(out, 0x2d);
+                immed.encode(out);
+            }
+            Op::I32Load16Signed(ref immed) => {
+                codec::write_u8(out, 0x2e);
+                immed.encode(out);
+            }
+            Op::I32Load16Unsigned(ref immed) => {
+                codec::write_u8(out, 0x2f);
+                immed.encode(out);
+            }
+            Op::I64Load8Signed(ref immed) => {
+                codec::write_u8(out, 0x30);
+                immed.encode(out);
+            }
+            Op::I64Load8Unsigned(ref immed) => {
+                codec::write_u8(out, 0x31);
+                immed.encode(out);
+            }
+            Op::I64Load16Signed(ref immed) => {
+                codec::write_u8(out, 0x32);
+                immed.encode(out);
+            }
+            Op::I64Load16Unsigned(ref immed) => {
+                codec::write_u8(out, 0x33);
+                immed.encode(out);
+            }
+            Op::I64Load32Signed(ref immed) => {
+                codec::write_u8(out, 0x34);
+                immed.encode(out);
+            }
+            Op::I64Load32Unsigned(ref immed) => {
+                codec::write_u8(out, 0x35);
+                immed.encode(out);
+            }
+            Op::I32Store(ref immed) => {
+                codec::write_u8(out, 0x36);
+                immed.encode(out);
+            }
+            Op::I64Store(ref immed) => {
+                codec::write_u8(out, 0x37);
+                immed.encode(out);
+            }
+            Op::F32Store(ref immed) => {
+                codec::write_u8(out, 0x38);
+                immed.encode(out);
+            }
+            Op::F64Store(ref immed) => {
+                codec::write_u8(out, 0x39);
+                immed.encode(out);
+            }
+            Op::I32Store8(ref immed) => {
+                codec::write_u8(out, 0x3a);
+                immed.encode(out);
+            }
+            Op::I32Store16(ref immed) => {
+                codec::write_u8(out, 0x3b);
+                immed.encode(out);
+            }
+            Op::I64Store8(ref immed) => {
+                codec::write_u8(out, 0x3c);
+                immed.encode(out);
+            }
+            Op::I64Store16(ref immed) => {
+                codec::write_u8(out, 0x3d);
+                immed.encode(out);
+            }
+            Op::I64Store32(ref immed) => {
+                codec::write_u8(out, 0x3e);
+                immed.encode(out);
+            }
+            Op::CurrentMemory(reserved) => {
+                codec::write_u8(out, 0x3f);
+                codec::write_u8(out, reserved);
+            }
+            Op::GrowMemory(reserved) => {
+                codec::write_u8(out, 0x40);
+                codec::write_u8(out, reserved);
+            }
+            Op::I32Const(c) => {
+                codec::write_u8(out, 0x41);
+                codec::write_vari32(out, c);
+            }
+            Op::I64Const(c) => {
+                codec::write_u8(out, 0x42);
+                codec::write_vari64(out, c);
+            }
+            Op::F32Const(c) => {
+                codec::write_u8(out, 0x43);
+                codec::write_u32(out, c.to_bits());
+            }
+            Op::F64Const(c) => {
+                codec::write_u8(out, 0x44);
+                codec::write_u64(out, c.to_bits());
+            }
+            Op::I32Eqz => codec::write_u8(out, 0x45),
+            Op::I32Eq => codec::write_u8(out, 0x46),
+            Op::I32Ne => codec::write_u8(out, 0x47),
+            Op::I32LtSigned => codec::write_u8(out, 0x48),
+            Op::I32LtUnsigned => codec::write_u8(out, 0x49),
+            Op::I32GtSigned => codec::write_u8(out, 0x4a),
+            Op::I32GtUnsigned => codec::write_u8(out, 0x4b),
+            Op::I32LeSigned => codec::write_u8(out, 0x4c),
+            Op::I32LeUnsigned => codec::write_u8(out, 0x4d),
+            Op::I32GeSigned => codec::write_u8(out, 0x4e),
+            Op::I32GeUnsigned => codec::write_u8(out, 0x4f),
+            Op::I64Eqz => codec::write_u8(out, 0x50),
+            Op::I64Eq => codec::write_u8(out, 0x51),
+            Op::I64Ne => codec::write_u8(out, 0x52),
+            Op::I64LtSigned => codec::write_u8(out, 0x53),
+            Op::I64LtUnsigned => codec::write_u8(out, 0x54),
+            Op::I64GtSigned => codec::write_u8(out, 0x55),
+            Op::I64GtUnsigned => codec::write_u8(out, 0x56),
+            Op::I64LeSigned => codec::write_u8(out, 0x57),
+            Op::I64LeUnsigned => codec::write_u8(out, 0x58),
+            Op::I64GeSigned => codec::write_u8(out, 0x59),
+            Op::I64GeUnsigned => codec::write_u8(out, 0x5a),
+            Op::F32Eq => codec::write_u8(out, 0x5b),
+            Op::F32Ne => codec::write_u8(out, 0x5c),
+            Op::F32Lt => codec::write_u8(out, 0x5d),
+            Op::F32Gt => codec::write_u8(out, 0x5e),
+            Op::F32Le => codec::write_u8(out, 0x5f),
+            Op::F32Ge => codec::write_u8(out, 0x60),
+            Op::F64Eq => codec::write_u8(out, 0x61),
+            Op::F64Ne => codec::write_u8(out, 0x62),
+            Op::F64Lt => codec::write_u8(out, 0x63),
+            Op::F64Gt => codec::write_u8(out, 0x64),
+            Op::F64Le => codec::write_u8(out, 0x65),
+            Op::F64Ge => codec::write_u8(out, 0x66),
+            Op::I32Clz => codec::write_u8(out, 0x67),
+            Op::I32Ctz => codec::write_u8(out, 0x68),
+            Op::I32Popcnt => codec::write_u8(out, 0x69),
+            Op::I32Add => codec::write_u8(out, 0x6a),
+            Op::I32Sub => codec::write_u8(out, 0x6b),
+            Op::I32Mul => codec::write_u8(out, 0x6c),
+            Op::I32DivSigned => codec::write_u8(out, 0x6d),
+            Op::I32DivUnsigned => codec::write_u8(out, 0x6e),
+            Op::I32RemSigned => codec::write_u8(out, 0x6f),
+            Op::I32RemUnsigned => codec::write_u8(out, 0x70),
+            Op::I32And => codec::write_u8(out, 0x71),
+            Op::I32Or => codec::write_u8(out, 0x72),
+            Op::I32Xor => codec::write_u8(out, 0x73),
+            Op::I32Shl => codec::write_u8(out, 0x74),
+            Op::I32ShrSigned => codec::write_u8(out, 0x75),
+            Op::I32ShrUnsigned => codec::write_u8(out, 0x76),
+            Op::I32Rotl => codec::write_u8(out, 0x77),
+            Op::I32Rotr => codec::write_u8(out, 0x78),
+            Op::I64Clz => codec::write_u8(out, 0x79),
+            Op::I64Ctz => codec::write_u8(out, 0x7a),
+            Op::I64Popcnt => codec::write_u8(out, 0x7b),
+            Op::I64Add => codec::write_u8(out, 0x7c),
+            Op::I64Sub => codec::write_u8(out, 0x7d),
+            Op::I64Mul => codec::write_u8(out, 0x7e),
+            Op::I64DivSigned => codec::write_u8(out, 0x7f),
+            Op::I64DivUnsigned => codec::write_u8(out, 0x80),
+            Op::I64RemSigned => codec::write_u8(out, 0x81),
+            Op::I64RemUnsigned => codec::write_u8(out, 0x82),
+            Op::I64And => codec::write_u8(out, 0x83),
+            Op::I64Or => codec::write_u8(out, 0x84),
+            Op::I64Xor => codec::write_u8(out, 0x85),
+            Op::I64Shl => codec::write_u8(out, 0x86),
+            Op::I64ShrSigned => codec::write_u8(out, 0x87),
+            Op::I64ShrUnsigned => codec::write_u8(out, 0x88),
+            Op::I64Rotl => codec::write_u8(out, 0x89),
+            Op::I64Rotr => codec::write_u8(out, 0x8a),
+            Op::F32Abs => codec::write_u8(out, 0x8b),
+            Op::F32Neg => codec::write_u8(out, 0x8c),
+            Op::F32Ceil => codec::write_u8(out, 0x8d),
+            Op::F32Floor => codec::write_u8(out, 0x8e),
+            Op::F32Trunc => codec::write_u8(out, 0x8f),
+            Op::F32Nearest => codec::write_u8(out, 0x90),
+            Op::F32Sqrt => codec::write_u8(out, 0x91),
+            Op::F32Add => codec::write_u8(out, 0x92),
+            Op::F32Sub => codec::write_u8(out, 0x93),
+            Op::F32Mul => codec::write_u8(out, 0x94),
+            Op::F32Div => codec::write_u8(out, 0x95),
+            Op::F32Min => codec::write_u8(out, 0x96),
+            Op::F32Max => codec::write_u8(out, 0x97),
+            Op::F32Copysign => codec::write_u8(out, 0x98),
+            Op::F64Abs => codec::write_u8(out, 0x99),
+            Op::F64Neg => codec::write_u8(out, 0x9a),
+            Op::F64Ceil => codec::write_u8(out, 0x9b),
+            Op::F64Floor => codec::write_u8(out, 0x9c),
+            Op::F64Trunc => codec::write_u8(out, 0x9d),
+            Op::F64Nearest => codec::write_u8(out, 0x9e),
+            Op::F64Sqrt => codec::write_u8(out, 0x9f),
+            Op::F64Add => codec::write_u8(out, 0xa0),
+            Op::F64Sub => codec::write_u8(out, 0xa1),
+            Op::F64Mul => codec::write_u8(out, 0xa2),
+            Op::F64Div => codec::write_u8(out, 0xa3),
+            Op::F64Min => codec::write_u8(out, 0xa4),
+            Op::F64Max => codec::write_u8(out, 0xa5),
+            Op::F64Copysign => codec::write_u8(out, 0xa6),
+            Op::I32WrapI64 => codec::write_u8(out, 0xa7),
+            Op::I32TruncSignedF32 => codec::write_u8(out, 0xa8),
+            Op::I32TruncUnsignedF32 => codec::write_u8(out, 0xa9),
+            Op::I32TruncSignedF64 => codec::write_u8(out, 0xaa),
+            Op::I32TruncUnsignedF64 => codec::write_u8(out, 0xab),
+            Op::I64ExtendSignedI32 => codec::write_u8(out, 0xac),
+            Op::I64ExtendUnsignedI32 => codec::write_u8(out, 0xad),
+            Op::I64TruncSignedF32 => codec::write_u8(out, 0xae),
+            Op::I64TruncUnsignedF32 => codec::write_u8(out, 0xaf),
+            Op::I64TruncSignedF64 => codec::write_u8(out, 0xb0),
+            Op::I64TruncUnsignedF64 => codec::write_u8(out, 0xb1),
+            Op::F32ConvertSignedI32 => codec::write_u8(out, 0xb2),
+            Op::F32ConvertUnsignedI32 => codec::write_u8(out, 0xb3),
+            Op::F32ConvertSignedI64 => codec::write_u8(out, 0xb4),
+            Op::F32ConvertUnsignedI64 => codec::write_u8(out, 0xb5),
+            Op::F32DemoteF64 => codec::write_u8(out, 0xb6),
+            Op::F64ConvertSignedI32 => codec::write_u8(out, 0xb7),
+            Op::F64ConvertUnsignedI32 => codec::write_u8(out, 0xb8),
+            Op::F64ConvertSignedI64 => codec::write_u8(out, 0xb9),
+            Op::F64ConvertUnsignedI64 => codec::write_u8(out, 0xba),
+            Op::F64PromoteF32 => codec::write_u8(out, 0xbb),
+            Op::I32ReinterpretF32 => codec::write_u8(out, 0xbc),
+            Op::I64ReinterpretF64 => codec::write_u8(out, 0xbd),
+            Op::F32ReinterpretI32 => codec::write_u8(out, 0xbe),
+            Op::F64ReinterpretI64 => codec::write_u8(out, 0xbf),
+            Op::I32Extend8Signed => codec::write_u8(out, 0xc0),
+            Op::I32Extend16Signed => codec::write_u8(out, 0xc1),
+            Op::I64Extend8Signed => codec::write_u8(out, 0xc2),
+            Op::I64Extend16Signed => codec::write_u8(out, 0xc3),
+            Op::I64Extend32Signed => codec::write_u8(out, 0xc4),
+        }
+    }
+
+    /// For loads and stores: the immediate, log2 of the access size, and
+    /// the value type loaded or stored.
+    pub(crate) fn memory_access(&self) -> Option<(&MemoryImmed, u32, ValueType)> {
+        use self::Op::*;
+        use ty::ValueType::*;
+
+        match *self {
+            I32Load(ref m) | I32Store(ref m) => Some((m, 2, I32)),
+            I64Load(ref m) | I64Store(ref m) => Some((m, 3, I64)),
+            F32Load(ref m) | F32Store(ref m) => Some((m, 2, F32)),
+            F64Load(ref m) | F64Store(ref m) => Some((m, 3, F64)),
+            I32Load8Signed(ref m) | I32Load8Unsigned(ref m) | I32Store8(ref m) => Some((m, 0, I32)),
+            I32Load16Signed(ref m) | I32Load16Unsigned(ref m) | I32Store16(ref m) => Some((m, 1, I32)),
+            I64Load8Signed(ref m) | I64Load8Unsigned(ref m) | I64Store8(ref m) => Some((m, 0, I64)),
+            I64Load16Signed(ref m) | I64Load16Unsigned(ref m) | I64Store16(ref m) => Some((m, 1, I64)),
+            I64Load32Signed(ref m) | I64Load32Unsigned(ref m) | I64Store32(ref m) => Some((m, 2, I64)),
+            _ => None,
         }
     }
+
+    fn is_init_op(&self) -> bool {
+        matches!(*self,
+                 Op::I32Const(_) |
+                 Op::I64Const(_) |
+                 Op::F32Const(_) |
+                 Op::F64Const(_) |
+                 Op::GetGlobal(_))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InitExpr {
-    ops: Vec<Op>,
+    pub ops: Vec<Op>,
 }
 
 impl InitExpr {
@@ -445,4 +794,11 @@ impl InitExpr {
 
         Ok(InitExpr { ops })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for op in &self.ops {
+            op.encode(out);
+        }
+        Op::End.encode(out);
+    }
 }
@@ -0,0 +1,141 @@
+//! Float operators whose wasm semantics differ from Rust's `std`.
+//!
+//! Rust's `round` breaks ties away from zero, its rounding functions
+//! pass signalling NaNs through unchanged, and its `min`/`max` ignore
+//! NaN operands and treat the zeros as equal; wasm requires ties to
+//! even, quiet NaN results, NaN propagation, and -0 < +0.
+
+macro_rules! float_ops {
+    ($fty:ident, $bits:ident, $ceil:ident, $floor:ident, $trunc:ident, $nearest:ident,
+     $min:ident, $max:ident, $copysign:ident) => (
+        pub fn $ceil(x: $fty) -> $fty {
+            if x.is_nan() {
+                return x + x;
+            }
+            x.ceil()
+        }
+
+        pub fn $floor(x: $fty) -> $fty {
+            if x.is_nan() {
+                return x + x;
+            }
+            x.floor()
+        }
+
+        pub fn $trunc(x: $fty) -> $fty {
+            if x.is_nan() {
+                return x + x;
+            }
+            x.trunc()
+        }
+
+        /// Round to the nearest integer, ties to even, keeping the sign
+        /// of zero results.
+        pub fn $nearest(x: $fty) -> $fty {
+            if x.is_nan() {
+                return x + x;
+            }
+            if x.is_infinite() || x == 0.0 {
+                return x;
+            }
+
+            let r = x.round();
+            let r = if (r - x).abs() == 0.5 {
+                2.0 * (x / 2.0).round()
+            } else {
+                r
+            };
+            $copysign(r, x)
+        }
+
+        pub fn $min(x: $fty, y: $fty) -> $fty {
+            if x.is_nan() || y.is_nan() {
+                return x + y;
+            }
+            if x == y {
+                // Only differs for zeros: -0 wins.
+                return $fty::from_bits(x.to_bits() | y.to_bits());
+            }
+            if x < y { x } else { y }
+        }
+
+        pub fn $max(x: $fty, y: $fty) -> $fty {
+            if x.is_nan() || y.is_nan() {
+                return x + y;
+            }
+            if x == y {
+                // Only differs for zeros: +0 wins.
+                return $fty::from_bits(x.to_bits() & y.to_bits());
+            }
+            if x > y { x } else { y }
+        }
+
+        /// `x` with the sign bit of `y`.  Works on the bits, so NaNs are
+        /// preserved.
+        pub fn $copysign(x: $fty, y: $fty) -> $fty {
+            let sign: $bits = 1 << (::std::mem::size_of::<$bits>() * 8 - 1);
+            $fty::from_bits((x.to_bits() & !sign) | (y.to_bits() & sign))
+        }
+    );
+}
+
+float_ops!(f32, u32, ceil_f32, floor_f32, trunc_f32, nearest_f32, min_f32, max_f32, copysign_f32);
+float_ops!(f64, u64, ceil_f64, floor_f64, trunc_f64, nearest_f64, min_f64, max_f64, copysign_f64);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{f32, f64};
+
+    #[test]
+    fn test_nearest() {
+        let cases: [(f64, f64); 9] = [(0.5, 0.0), (1.5, 2.0), (2.5, 2.0), (-0.5, -0.0),
+                                       (-3.5, -4.0), (4.4, 4.0), (-4.6, -5.0), (-0.0, -0.0),
+                                       (8388609.0, 8388609.0)];
+        for &(x, r) in &cases {
+            assert_eq!(nearest_f32(x as f32).to_bits(), (r as f32).to_bits(), "nearest({})", x);
+            assert_eq!(nearest_f64(x).to_bits(), r.to_bits(), "nearest({})", x);
+        }
+        assert_eq!(nearest_f64(4503599627370497.0), 4503599627370497.0);
+        assert_eq!(nearest_f32(f32::NEG_INFINITY), f32::NEG_INFINITY);
+        assert!(nearest_f64(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_rounding_quiets_nan() {
+        let snan = f32::from_bits(0x7fa0_0000);
+        for op in &[ceil_f32, floor_f32, trunc_f32, nearest_f32] {
+            assert_eq!(op(snan).to_bits(), 0x7fe0_0000);
+        }
+        let snan = f64::from_bits(0xfff4_0000_0000_0000);
+        for op in &[ceil_f64, floor_f64, trunc_f64, nearest_f64] {
+            assert_eq!(op(snan).to_bits(), 0xfffc_0000_0000_0000);
+        }
+        assert_eq!(floor_f64(-1.5), -2.0);
+        assert_eq!(trunc_f32(-1.5), -1.0);
+    }
+
+    #[test]
+    fn test_min_max() {
+        assert_eq!(min_f32(-0.0, 0.0).to_bits(), (-0.0f32).to_bits());
+        assert_eq!(min_f32(0.0, -0.0).to_bits(), (-0.0f32).to_bits());
+        assert_eq!(max_f32(-0.0, 0.0).to_bits(), 0);
+        assert_eq!(max_f64(0.0, -0.0).to_bits(), 0);
+        assert!(min_f32(f32::NAN, 1.0).is_nan());
+        assert!(max_f64(1.0, f64::NAN).is_nan());
+        assert_eq!(min_f64(1.0, 2.0), 1.0);
+        assert_eq!(max_f64(f64::NEG_INFINITY, -2.0), -2.0);
+    }
+
+    #[test]
+    fn test_copysign() {
+        assert_eq!(copysign_f32(1.0, -0.0), -1.0);
+        assert_eq!(copysign_f32(-0.0, 1.0).to_bits(), 0);
+        assert_eq!(copysign_f64(2.0, -f64::NAN), -2.0);
+
+        let nan = f32::from_bits(0x7fa0_0001);
+        assert_eq!(copysign_f32(nan, -1.0).to_bits(), 0xffa0_0001);
+        assert_eq!(copysign_f64(f64::from_bits(0xfff8_0000_0000_0001), 1.0).to_bits(),
+                   0x7ff8_0000_0000_0001);
+    }
+}
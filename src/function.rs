@@ -5,10 +5,10 @@ use codec;
 
 use untrusted;
 
-#[derive(Debug)]
-struct Local {
-    count: u32,
-    ty: ValueType,
+#[derive(Debug, Clone)]
+pub struct Local {
+    pub count: u32,
+    pub ty: ValueType,
 }
 
 impl Local {
@@ -17,32 +17,48 @@ impl Local {
         let ty = ValueType::decode(rd)?;
         Ok(Local { count, ty })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_varu32(out, self.count);
+        self.ty.encode(out);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionBody {
-    locals: Vec<Local>,
-    ops: Vec<Op>,
+    pub locals: Vec<Local>,
+    pub ops: Vec<Op>,
+    /// Byte offset of each op, then of the final `end`.  `decode`
+    /// counts from the start of the body; a decoded code section counts
+    /// from the start of its payload.  Empty when not decoded.
+    pub offsets: Vec<u32>,
 }
 
 impl FunctionBody {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<FunctionBody, CodecError> {
-        let body_size = codec::read_varu32(rd)?;
+        let (body_size, mut pos) = codec::measured(rd, codec::read_varu32)?;
 
         let mut body = rd.skip_and_get_input(body_size as usize)
             .map_err(|_| CodecError::Truncated)
-            .map(|inp| untrusted::Reader::new(inp))?;
+            .map(untrusted::Reader::new)?;
 
-        let mut locals = vec![];
-        let local_count = codec::read_varu32(&mut body)?;
+        let (locals, len) = codec::measured(&mut body, |body| {
+            let mut locals = vec![];
+            let local_count = codec::read_varu32(body)?;
 
-        for _ in 0..local_count {
-            locals.push(Local::decode(&mut body)?);
-        }
+            for _ in 0..local_count {
+                locals.push(Local::decode(body)?);
+            }
+            Ok(locals)
+        })?;
+        pos += len;
 
         let mut ops = vec![];
+        let mut offsets = vec![];
         loop {
-            let op = Op::decode(&mut body)?;
+            let (op, len) = codec::measured(&mut body, Op::decode)?;
+            offsets.push(pos as u32);
+            pos += len;
 
             if body.at_end() {
                 match op {
@@ -54,6 +70,33 @@ impl FunctionBody {
             ops.push(op);
         }
 
-        Ok(FunctionBody { locals, ops })
+        Ok(FunctionBody { locals, ops, offsets })
+    }
+
+    /// Encode the body, size-prefixed and with the final `end` that
+    /// `decode` strips.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut body = vec![];
+        codec::write_varu32(&mut body, self.locals.len() as u32);
+        for local in &self.locals {
+            local.encode(&mut body);
+        }
+        for op in &self.ops {
+            op.encode(&mut body);
+        }
+        Op::End.encode(&mut body);
+
+        codec::write_bytes(out, &body);
+    }
+
+    /// The declared locals, with each run expanded to one entry per local.
+    pub fn local_types(&self) -> Vec<ValueType> {
+        let mut types = vec![];
+        for local in &self.locals {
+            for _ in 0..local.count {
+                types.push(local.ty);
+            }
+        }
+        types
     }
 }
@@ -0,0 +1,459 @@
+use section::{Module, ExternalKind, ImportKind, FunctionType};
+use expr::{InitExpr, Op};
+use exec::{Context, Code, Body, Function, Global, Limits};
+use linker::Linker;
+use store::Store;
+use mem::Memory;
+use table::Table;
+use value::Value;
+use error::{Error, InstantiationError, RuntimeError, Trap};
+
+use std::rc::Rc;
+use std::collections::HashMap;
+
+/// A module whose memory, tables, globals and functions have been
+/// allocated and initialised, ready for execution.
+pub struct Instance {
+    ctx: Context,
+    exports: Vec<Export>,
+    /// Debug names of functions, from the module's "name" section.
+    names: HashMap<u32, String>,
+}
+
+struct Export {
+    name: String,
+    kind: ExternalKind,
+    index: u32,
+}
+
+/// Evaluate a constant initializer expression.  `imported` holds the
+/// values of imported globals, which are the only ones visible here.
+fn eval_init(init: &InitExpr, imported: &[Value]) -> Result<Value, InstantiationError> {
+    if init.ops.len() != 1 {
+        return Err(InstantiationError::BadInitExpr);
+    }
+
+    match init.ops[0] {
+        Op::I32Const(c) => Ok(Value::I32(c)),
+        Op::I64Const(c) => Ok(Value::I64(c)),
+        Op::F32Const(c) => Ok(Value::F32(c)),
+        Op::F64Const(c) => Ok(Value::F64(c)),
+        Op::GetGlobal(index) => imported.get(index as usize)
+            .cloned()
+            .ok_or(InstantiationError::BadIndex),
+        _ => Err(InstantiationError::BadInitExpr),
+    }
+}
+
+/// Evaluate a segment offset, which must be an i32.
+fn eval_offset(init: &InitExpr, imported: &[Value]) -> Result<u32, InstantiationError> {
+    match eval_init(init, imported)? {
+        Value::I32(offset) => Ok(offset as u32),
+        _ => Err(InstantiationError::TypeMismatch),
+    }
+}
+
+/// Fill in the names of `trap`'s frames.
+fn named(mut trap: Trap, names: &HashMap<u32, String>) -> Trap {
+    for frame in &mut trap.backtrace {
+        frame.name = names.get(&frame.func).cloned();
+    }
+    trap
+}
+
+impl Instance {
+    /// Instantiate a module that has no imports.
+    pub fn new(module: &Module) -> Result<Instance, InstantiationError> {
+        Linker::new().instantiate(module)
+    }
+
+    pub(crate) fn link(module: &Module, linker: &Linker, store: &Store)
+        -> Result<Instance, InstantiationError>
+    {
+        module.validate()?;
+
+        let types = module.types();
+        let mut imported_funcs = vec![];
+        let mut imported_globals = vec![];
+
+        for import in module.imports() {
+            match import.kind {
+                ImportKind::Function(type_index) => {
+                    let ty = types.get(type_index as usize)
+                        .ok_or(InstantiationError::BadIndex)?;
+                    let func = linker.resolve_func(import.module, import.field, ty)?;
+                    imported_funcs.push(Function { ty: ty.clone(), body: Body::Host(func) });
+                }
+                ImportKind::Global(ref ty) => {
+                    imported_globals.push(linker.resolve_global(import.module, import.field, ty)?);
+                }
+                _ => return Err(linker.unsupported(import.module, import.field)),
+            }
+        }
+
+        let mut mem = Memory::new();
+        if let Some(memory) = module.memories().first() {
+            mem = Memory::limited(memory.limits.maximum(), store.limiter());
+            if mem.grow(memory.limits.initial() as i32) < 0 {
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+        }
+
+        let imported_values: Vec<Value> = imported_globals.iter().map(Global::get).collect();
+
+        let mut ctx = Context::with_memory(mem);
+        ctx.types = types.to_vec();
+        ctx.funcs = imported_funcs;
+        ctx.globals = imported_globals;
+
+        for table in module.tables() {
+            let table = Table::limited(table.limits.initial(), table.limits.maximum(),
+                                       store.limiter())
+                .ok_or(InstantiationError::ResourceLimitExceeded)?;
+            ctx.tables.push(table);
+        }
+
+        for global in module.globals() {
+            let value = eval_init(&global.init, &imported_values)?;
+            if value.ty() != global.ty.content {
+                return Err(InstantiationError::TypeMismatch);
+            }
+            ctx.globals.push(Global::new(global.ty.clone(), value));
+        }
+
+        let code = module.code();
+
+        if module.functions().len() != code.len() {
+            return Err(InstantiationError::BadIndex);
+        }
+
+        for (type_index, body) in module.functions().iter().zip(code) {
+            let ty = types.get(*type_index as usize)
+                .ok_or(InstantiationError::BadIndex)?;
+            ctx.funcs.push(Function {
+                ty: ty.clone(),
+                body: Body::Wasm(Rc::new(Code::new(body.local_types(), body.ops.clone(),
+                                                  body.offsets.clone()))),
+            });
+        }
+
+        // Every segment must fit before any is written, so a failed
+        // instantiation leaves tables and memory untouched.
+        let mut elems = vec![];
+        for elem in module.elements() {
+            let offset = eval_offset(&elem.offset, &imported_values)?;
+            let table = ctx.tables.get(elem.index as usize)
+                .ok_or(InstantiationError::BadIndex)?;
+            if !table.fits(offset, elem.elems.len()) {
+                return Err(InstantiationError::ElementSegmentOutOfBounds);
+            }
+            elems.push((elem, offset));
+        }
+
+        let mut datas = vec![];
+        for data in module.data() {
+            if data.index != 0 || module.memories().is_empty() {
+                return Err(InstantiationError::BadIndex);
+            }
+
+            let offset = eval_offset(&data.init, &imported_values)?;
+            if !ctx.mem.fits(offset, data.data.len()) {
+                return Err(InstantiationError::DataSegmentOutOfBounds);
+            }
+            datas.push((data, offset));
+        }
+
+        for (elem, offset) in elems {
+            ctx.tables[elem.index as usize].init(offset, &elem.elems)
+                .ok_or(InstantiationError::ElementSegmentOutOfBounds)?;
+        }
+        for (data, offset) in datas {
+            ctx.mem.write(offset, data.data)
+                .ok_or(InstantiationError::DataSegmentOutOfBounds)?;
+        }
+
+        let exports = module.exports()
+            .iter()
+            .map(|e| Export { name: e.field.to_string(), kind: e.kind, index: e.index })
+            .collect();
+
+        let names = module.names()
+            .map(|names| names.functions()
+                 .into_iter()
+                 .map(|(index, name)| (index, name.to_string()))
+                 .collect())
+            .unwrap_or_default();
+
+        if let Some(start) = module.start() {
+            ctx.invoke(start, &[])
+                .map_err(|trap| InstantiationError::Start(named(trap, &names)))?;
+        }
+
+        Ok(Instance { ctx, exports, names })
+    }
+
+    fn export(&self, name: &str, kind: ExternalKind) -> Option<u32> {
+        self.exports.iter()
+            .find(|e| e.name == name && e.kind == kind)
+            .map(|e| e.index)
+    }
+
+    /// The names and signatures of the exported functions.
+    pub(crate) fn exported_funcs(&self) -> Vec<(String, FunctionType)> {
+        self.exports.iter()
+            .filter(|e| e.kind == ExternalKind::Function)
+            .map(|e| (e.name.clone(), self.ctx.funcs[e.index as usize].ty.clone()))
+            .collect()
+    }
+
+    /// The exported globals, sharing their cells with this instance.
+    pub(crate) fn exported_globals(&self) -> Vec<(String, Global)> {
+        self.exports.iter()
+            .filter(|e| e.kind == ExternalKind::Global)
+            .map(|e| (e.name.clone(), self.ctx.globals[e.index as usize].clone()))
+            .collect()
+    }
+
+    /// The debug name of function `index`, counting imports, if the
+    /// module's "name" section gives one.
+    pub fn function_name(&self, index: u32) -> Option<&str> {
+        self.names.get(&index).map(String::as_str)
+    }
+
+    /// Call the exported function `name` with `args`, returning its
+    /// results.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, Error> {
+        let index = self.export(name, ExternalKind::Function)
+            .ok_or(Error::NoSuchExport)?;
+        let names = &self.names;
+        Ok(self.ctx.invoke(index, args)
+           .map_err(|trap| named(trap, names))?)
+    }
+
+    /// The value of the exported global `name`.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.export(name, ExternalKind::Global)
+            .map(|index| self.ctx.globals[index as usize].get())
+    }
+
+    /// Set the exported global `name`, which must be mutable and of the
+    /// same type as `value`.
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), Error> {
+        let index = self.export(name, ExternalKind::Global)
+            .ok_or(Error::NoSuchExport)?;
+        let global = &self.ctx.globals[index as usize];
+
+        if !global.ty.mutable {
+            return Err(Error::ImmutableGlobal);
+        }
+        if global.ty.content != value.ty() {
+            return Err(Error::Runtime(RuntimeError::TypeFault));
+        }
+        global.set(value);
+        Ok(())
+    }
+
+    /// Like `invoke`, but give up with `RuntimeError::OutOfFuel` after
+    /// executing `fuel` ops.  The instance's own budget is left as it
+    /// was.
+    pub fn invoke_with_fuel(&mut self, name: &str, args: &[Value], fuel: u64)
+        -> Result<Vec<Value>, Error>
+    {
+        let saved = self.ctx.fuel();
+        self.ctx.set_fuel(Some(fuel));
+        let results = self.invoke(name, args);
+        self.ctx.set_fuel(saved);
+        results
+    }
+
+    /// The instance's linear memory.
+    pub fn memory(&self) -> &Memory {
+        &self.ctx.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.ctx.mem
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.ctx.limits()
+    }
+
+    /// Bound the operand stack and call depth of later calls.  Going
+    /// past either traps with `RuntimeError::StackOverflow`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.ctx.set_limits(limits)
+    }
+
+    /// Remaining fuel, or `None` if execution is unmetered.  Instances
+    /// start unmetered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.ctx.fuel()
+    }
+
+    /// Meter every later `invoke` against a budget of `fuel` ops, or
+    /// turn metering off with `None`.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.ctx.set_fuel(fuel)
+    }
+
+    /// Top up the fuel budget.  Has no effect when unmetered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.ctx.add_fuel(fuel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use expr::MemoryImmed;
+    use error::TrapFrame;
+
+    #[test]
+    fn test_instantiate() {
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // type: [] -> []
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            // function: one of type 0
+            0x03, 0x02, 0x01, 0x00,
+            // memory: initial 1 page
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            // global: immutable i32 = 42
+            0x06, 0x06, 0x01, 0x7f, 0x00, 0x41, 0x2a, 0x0b,
+            // start: function 0
+            0x08, 0x01, 0x00,
+            // code: i32.const 1; grow_memory; br_if 0
+            0x0a, 0x0a, 0x01, 0x08, 0x00, 0x41, 0x01, 0x40, 0x00, 0x0d, 0x00, 0x0b,
+            // data: "hi" at 8
+            0x0b, 0x08, 0x01, 0x00, 0x41, 0x08, 0x0b, 0x02, 0x68, 0x69,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        let inst = Instance::new(&module).unwrap();
+
+        assert_eq!(inst.ctx.mem.len_pages(), 2);
+        assert_eq!(inst.ctx.globals.iter().map(Global::get).collect::<Vec<_>>(),
+                   vec![Value::I32(42)]);
+        let at = |offset| MemoryImmed { align: 0, offset };
+        assert_eq!(inst.ctx.mem.i32_load8_u(0, &at(8)), Some(0x68));
+        assert_eq!(inst.ctx.mem.i32_load8_u(0, &at(9)), Some(0x69));
+        assert_eq!(&inst.memory().data()[8..10], b"hi");
+    }
+
+    #[test]
+    fn test_data_out_of_bounds() {
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // memory: initial 1 page
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            // data: "hi" at 65535
+            0x0b, 0x0a, 0x01, 0x00, 0x41, 0xff, 0xff, 0x03, 0x0b, 0x02, 0x68, 0x69,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        assert_eq!(Instance::new(&module).err(),
+                   Some(InstantiationError::DataSegmentOutOfBounds));
+
+        // A segment that fits ahead of one that does not.
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            0x0b, 0x11, 0x02,
+            0x00, 0x41, 0x00, 0x0b, 0x02, 0x68, 0x69,
+            0x00, 0x41, 0xff, 0xff, 0x03, 0x0b, 0x02, 0x68, 0x69,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        assert_eq!(Instance::new(&module).err(),
+                   Some(InstantiationError::DataSegmentOutOfBounds));
+
+        let mut memory = Memory::with_maximum(None);
+        memory.grow(1);
+        assert!(memory.fits(0, 2));
+        assert!(memory.fits(65534, 2));
+        assert!(!memory.fits(65535, 2));
+        assert!(!memory.fits(u32::MAX, 2));
+        let table = Table::limited(2, None, None).unwrap();
+        assert!(table.fits(0, 2));
+        assert!(!table.fits(1, 2));
+        assert!(!table.fits(u32::MAX, 2));
+    }
+
+    #[test]
+    fn test_call_indirect() {
+        // (module
+        //   (type $t (func (result i32)))
+        //   (table 2 anyfunc)
+        //   (elem (i32.const 1) $f)
+        //   (func $f (type $t) (i32.const 7))
+        //   (func (export "call") (param i32) (result i32)
+        //     (call_indirect (type $t) (get_local 0))))
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x0a, 0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+            0x03, 0x03, 0x02, 0x00, 0x01,
+            0x04, 0x04, 0x01, 0x70, 0x00, 0x02,
+            0x07, 0x08, 0x01, 0x04, 0x63, 0x61, 0x6c, 0x6c, 0x00, 0x01,
+            0x09, 0x07, 0x01, 0x00, 0x41, 0x01, 0x0b, 0x01, 0x00,
+            0x0a, 0x0e, 0x02, 0x04, 0x00, 0x41, 0x07, 0x0b,
+            0x07, 0x00, 0x20, 0x00, 0x11, 0x00, 0x00, 0x0b,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        let mut inst = Instance::new(&module).unwrap();
+
+        assert_eq!(inst.invoke("call", &[Value::I32(1)]), Ok(vec![Value::I32(7)]));
+        let trap = |error| Err(Error::Trap(Trap {
+            error,
+            backtrace: vec![TrapFrame { func: 1, name: None, offset: Some(10) }],
+        }));
+        assert_eq!(inst.invoke("call", &[Value::I32(0)]),
+                   trap(RuntimeError::UninitializedElement));
+        assert_eq!(inst.invoke("call", &[Value::I32(2)]),
+                   trap(RuntimeError::UndefinedElement));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        // (module
+        //   (func (export "run") (call 1))
+        //   (func unreachable))
+        // with a "name" section naming function 1 `dsp_filter`.
+        let mut bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x03, 0x02, 0x00, 0x00,
+            0x07, 0x07, 0x01, 0x03, 0x72, 0x75, 0x6e, 0x00, 0x00,
+            0x0a, 0x0a, 0x02, 0x04, 0x00, 0x10, 0x01, 0x0b, 0x03, 0x00, 0x00, 0x0b,
+            0x00, 0x14, 0x04, 0x6e, 0x61, 0x6d, 0x65,
+            0x01, 0x0d, 0x01, 0x01, 0x0a,
+            0x64, 0x73, 0x70, 0x5f, 0x66, 0x69, 0x6c, 0x74, 0x65, 0x72,
+        ];
+        let trap = Trap {
+            error: RuntimeError::Unreachable,
+            backtrace: vec![
+                TrapFrame { func: 1, name: Some("dsp_filter".to_string()), offset: Some(8) },
+                TrapFrame { func: 0, name: None, offset: Some(3) },
+            ],
+        };
+
+        {
+            let module = Module::decode_from(&bytes).unwrap();
+            let mut inst = Instance::new(&module).unwrap();
+            assert_eq!(inst.function_name(0), None);
+            assert_eq!(inst.function_name(1), Some("dsp_filter"));
+
+            match inst.invoke("run", &[]) {
+                Err(Error::Trap(t)) => {
+                    assert_eq!(t, trap);
+                    assert_eq!(t.to_string(),
+                               "trap: Unreachable\n  0: dsp_filter (func[1]) @ 0x8\n  1: func[0] @ 0x3");
+                }
+                r => panic!("expected trap, got {:?}", r),
+            }
+        }
+
+        // start: function 0
+        let code = bytes.iter().position(|&b| b == 0x0a).unwrap();
+        bytes.splice(code..code, vec![0x08, 0x01, 0x00]);
+        let module = Module::decode_from(&bytes).unwrap();
+        assert_eq!(Instance::new(&module).err(), Some(InstantiationError::Start(trap)));
+    }
+}
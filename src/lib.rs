@@ -1,3 +1,37 @@
+//! A WebAssembly interpreter.
+//!
+//! ```
+//! use wasmrs::{Module, Instance, Value};
+//!
+//! // (module (func (export "add") (param i32 i32) (result i32)
+//! //   (i32.add (get_local 0) (get_local 1))))
+//! let bytes = [
+//!     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+//!     0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+//!     0x03, 0x02, 0x01, 0x00,
+//!     0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64, 0x00, 0x00,
+//!     0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+//! ];
+//!
+//! let module = Module::decode_from(&bytes).unwrap();
+//! let mut instance = Instance::new(&module).unwrap();
+//! let results = instance.invoke("add", &[Value::I32(2), Value::I32(3)]);
+//! assert_eq!(results, Ok(vec![Value::I32(5)]));
+//! ```
+//!
+//! Modules in the text format are translated to binary with `parse_wat`:
+//!
+//! ```
+//! use wasmrs::{Module, Instance, Value, parse_wat};
+//!
+//! let bytes = parse_wat(r#"(module (func (export "add") (param i32 i32) (result i32)
+//!                            (i32.add (get_local 0) (get_local 1))))"#).unwrap();
+//! let module = Module::decode_from(&bytes).unwrap();
+//! let mut instance = Instance::new(&module).unwrap();
+//! assert_eq!(instance.invoke("add", &[Value::I32(2), Value::I32(3)]),
+//!            Ok(vec![Value::I32(5)]));
+//! ```
+
 extern crate untrusted;
 extern crate byteorder;
 
@@ -8,32 +42,163 @@ mod function;
 mod section;
 mod ty;
 mod mem;
+mod table;
 mod value;
+mod float;
 mod exec;
+mod instance;
+mod linker;
+mod store;
+mod validate;
+mod sexpr;
+mod wat;
+mod print;
+mod names;
+#[cfg(test)]
+mod script;
+
+pub use error::{Error, CodecError, InstantiationError, RuntimeError, Trap, TrapFrame,
+                ValidationError, CodeError, WatError, SyntaxError};
+pub use section::{Module, FunctionType};
+pub use names::Names;
+pub use instance::Instance;
+pub use exec::Limits;
+pub use linker::Linker;
+pub use store::{Store, ResourceLimiter, StoreLimits};
+pub use mem::Memory;
+pub use value::Value;
+pub use ty::ValueType;
+pub use wat::parse_wat;
+pub use print::print_wat;
 
 #[cfg(test)]
 mod tests {
-    extern crate atoms;
-    /*
+    use std::fs;
+    use std::path::Path;
+
+    /// Commands each corpus file is expected to skip, for features the
+    /// interpreter lacks.  Files not listed must skip nothing.
+    const SKIPS: &[(&str, usize)] = &[
+        // Table and memory imports.
+        ("imports.wast", 77),
+    ];
+
     #[test]
-    fn read_wast() {
-        let input = include_bytes!("../corpus/tee_local.wast").as_ref();
-        let mut parser = atoms::Parser::new(&input);
-        let mut i = 0;
-        loop {
-            match parser.read::<String>() {
-                Ok(v) => println!("{:?} {:?}", i, v),
-                Err(e) => { println!("done {:?}", e); break }
+    fn spec_corpus() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+        let mut problems = vec![];
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "wast") {
+                continue;
+            }
+
+            let text = fs::read_to_string(&path).unwrap();
+            let report = super::script::run(&text).unwrap();
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+
+            for outcome in report.failures() {
+                problems.push(format!("{}:{}: {:?}", file, outcome.line, outcome.status));
+            }
+            if report.passed() == 0 {
+                problems.push(format!("{}: no commands passed", file));
+            }
+
+            let budget = SKIPS.iter()
+                .find(|&&(name, _)| name == file)
+                .map_or(0, |&(_, skips)| skips);
+            if report.skipped() != budget {
+                problems.push(format!("{}: {} skipped, expected {}",
+                                      file, report.skipped(), budget));
             }
-            i += 1;
         }
+
+        assert!(problems.is_empty(), "\n{}", problems.join("\n"));
     }
-    */
 
     #[test]
     fn read_binary() {
         let input = include_bytes!("../webdsp_c.wasm").as_ref();
-        let module = super::section::Module::decode_from(&input);
+        let module = super::section::Module::decode_from(input);
         println!("{:?}", module);
     }
+    #[test]
+    fn encode_binary() {
+        // webdsp pads its section sizes, so only the re-encoding is stable.
+        let input = include_bytes!("../webdsp_c.wasm").as_ref();
+        let module = super::section::Module::decode_from(input).unwrap();
+        let output = module.encode();
+        assert!(output.len() < input.len());
+
+        // Offsets differ, so compare only the locals and ops.
+        let code = |module: &super::section::Module| {
+            module.code().iter()
+                .map(|body| format!("{:?} {:?}", body.locals, body.ops))
+                .collect::<Vec<_>>()
+        };
+        let decoded = super::section::Module::decode_from(&output).unwrap();
+        assert_eq!(code(&decoded), code(&module));
+        assert_eq!(decoded.encode(), output);
+
+        // Minimal encodings round-trip exactly.
+        let input = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // custom "x"
+            0x00, 0x03, 0x01, 0x78, 0xff,
+            // type: [i32] -> [i32], [] -> []
+            0x01, 0x09, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x00,
+            // import "m" "g": immutable f64 global
+            0x02, 0x08, 0x01, 0x01, 0x6d, 0x01, 0x67, 0x03, 0x7c, 0x00,
+            // function: types 0, 1
+            0x03, 0x03, 0x02, 0x00, 0x01,
+            // table: anyfunc 1..2
+            0x04, 0x05, 0x01, 0x70, 0x01, 0x01, 0x02,
+            // memory: 1
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            // global: mutable i64 = -129
+            0x06, 0x07, 0x01, 0x7e, 0x01, 0x42, 0xff, 0x7e, 0x0b,
+            // export "f": function 1
+            0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x01,
+            // start: function 2
+            0x08, 0x01, 0x02,
+            // element: table 0 at 0, function 1
+            0x09, 0x07, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x01, 0x01,
+            // code
+            0x0a, 0x30, 0x02,
+            // (local f32 f32) block i32 (get_local 0) (br_table 0 0 0) end
+            0x19, 0x01, 0x02, 0x7d, 0x02, 0x7f, 0x20, 0x00, 0x0e, 0x02, 0x00, 0x00, 0x00, 0x0b,
+            // f32.const 1.5; drop; current_memory; i32.load offset=4
+            0x43, 0x00, 0x00, 0xc0, 0x3f, 0x1a, 0x3f, 0x00, 0x28, 0x02, 0x04, 0x0b,
+            // i32.const 0; call_indirect 0; drop
+            0x14, 0x00, 0x41, 0x00, 0x41, 0x00, 0x11, 0x00, 0x00, 0x1a,
+            // f64.const nan:0x1; drop
+            0x44, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x7f, 0x1a, 0x0b,
+            // data: "hi" at 8
+            0x0b, 0x08, 0x01, 0x00, 0x41, 0x08, 0x0b, 0x02, 0x68, 0x69,
+        ];
+        let module = super::section::Module::decode_from(&input).unwrap();
+        assert_eq!(module.encode(), input.to_vec());
+    }
+    #[test]
+    fn section_order() {
+        use super::error::CodecError;
+        use super::section::Module;
+
+        let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        let types = [0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let funcs = [0x03, 0x02, 0x01, 0x00];
+        let custom = [0x00, 0x02, 0x01, 0x78];
+        let module = |sections: &[&[u8]]| {
+            let mut bytes = header.to_vec();
+            for section in sections {
+                bytes.extend_from_slice(section);
+            }
+            Module::decode_from(&bytes).map(|_| ())
+        };
+
+        assert_eq!(module(&[&custom, &types, &custom, &funcs, &custom]), Ok(()));
+        assert_eq!(module(&[&types, &types]), Err(CodecError::BadSectionOrder));
+        assert_eq!(module(&[&funcs, &types]), Err(CodecError::BadSectionOrder));
+    }
 }
@@ -0,0 +1,263 @@
+use section::{Module, FunctionType, GlobalType};
+use instance::Instance;
+use store::Store;
+use exec::{HostFunc, Global};
+use mem::Memory;
+use value::Value;
+use error::{Error, InstantiationError, RuntimeError};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+enum Definition {
+    Func(FunctionType, Rc<HostFunc>),
+    Global(Global),
+}
+
+/// Host definitions that satisfy a module's imports, keyed by import
+/// module and field name.
+pub struct Linker {
+    defs: HashMap<(String, String), Definition>,
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker { defs: HashMap::new() }
+    }
+
+    /// Define `module`.`field` as a host function with signature `ty`.
+    pub fn func<F>(&mut self, module: &str, field: &str, ty: FunctionType, f: F) -> &mut Linker
+        where F: Fn(&mut Memory, &[Value]) -> Result<Vec<Value>, RuntimeError> + 'static
+    {
+        self.defs.insert((module.to_string(), field.to_string()),
+                         Definition::Func(ty, Rc::new(f)));
+        self
+    }
+
+    /// Define `module`.`field` as a global holding `value`.  Instances
+    /// that import a mutable global share it with each other and with
+    /// `get_global`.
+    pub fn global(&mut self, module: &str, field: &str, value: Value, mutable: bool)
+        -> &mut Linker
+    {
+        let ty = GlobalType { content: value.ty(), mutable };
+        self.defs.insert((module.to_string(), field.to_string()),
+                         Definition::Global(Global::new(ty, value)));
+        self
+    }
+
+    /// Define every function and global exported by `instance` under
+    /// `module`.  Calls into `instance` while it is already running trap
+    /// with `RuntimeError::Host`.
+    pub fn instance(&mut self, module: &str, instance: Rc<RefCell<Instance>>) -> &mut Linker {
+        for (field, ty) in instance.borrow().exported_funcs() {
+            let inst = instance.clone();
+            let name = field.clone();
+            self.func(module, &field, ty, move |_, args| {
+                let mut inst = inst.try_borrow_mut()
+                    .map_err(|_| RuntimeError::Host("instance is already running".to_string()))?;
+                inst.invoke(&name, args).map_err(|e| match e {
+                    Error::Trap(trap) => trap.error,
+                    Error::Runtime(e) => e,
+                    e => RuntimeError::Host(format!("{:?}", e)),
+                })
+            });
+        }
+
+        for (field, global) in instance.borrow().exported_globals() {
+            self.defs.insert((module.to_string(), field), Definition::Global(global));
+        }
+        self
+    }
+
+    /// The current value of the global defined as `module`.`field`.
+    pub fn get_global(&self, module: &str, field: &str) -> Option<Value> {
+        match self.defs.get(&(module.to_string(), field.to_string())) {
+            Some(Definition::Global(global)) => Some(global.get()),
+            _ => None,
+        }
+    }
+
+    /// Resolve an imported function, checking its signature is `ty`.
+    pub(crate) fn resolve_func(&self, module: &str, field: &str, ty: &FunctionType)
+        -> Result<Rc<HostFunc>, InstantiationError>
+    {
+        match self.defs.get(&(module.to_string(), field.to_string())) {
+            Some(Definition::Func(def_ty, f)) if def_ty == ty => Ok(f.clone()),
+            Some(_) => Err(InstantiationError::IncompatibleImport(module.to_string(),
+                                                                  field.to_string())),
+            None => Err(InstantiationError::UnknownImport(module.to_string(),
+                                                          field.to_string())),
+        }
+    }
+
+    /// Resolve an imported global, checking its type is `ty`.
+    pub(crate) fn resolve_global(&self, module: &str, field: &str, ty: &GlobalType)
+        -> Result<Global, InstantiationError>
+    {
+        match self.defs.get(&(module.to_string(), field.to_string())) {
+            Some(Definition::Global(global)) if global.ty == *ty => Ok(global.clone()),
+            Some(_) => Err(InstantiationError::IncompatibleImport(module.to_string(),
+                                                                  field.to_string())),
+            None => Err(InstantiationError::UnknownImport(module.to_string(),
+                                                          field.to_string())),
+        }
+    }
+
+    /// Report an import of a kind the linker cannot satisfy.
+    pub(crate) fn unsupported(&self, module: &str, field: &str) -> InstantiationError {
+        if self.defs.contains_key(&(module.to_string(), field.to_string())) {
+            InstantiationError::IncompatibleImport(module.to_string(), field.to_string())
+        } else {
+            InstantiationError::UnknownImport(module.to_string(), field.to_string())
+        }
+    }
+
+    /// Instantiate `module`, resolving its imports against this linker.
+    pub fn instantiate(&self, module: &Module) -> Result<Instance, InstantiationError> {
+        self.instantiate_in(&Store::new(), module)
+    }
+
+    /// Instantiate `module` in `store`, whose limiter accounts for the
+    /// memory and tables the instance allocates.
+    pub fn instantiate_in(&self, store: &Store, module: &Module)
+        -> Result<Instance, InstantiationError>
+    {
+        Instance::link(module, self, store)
+    }
+}
+
+impl Default for Linker {
+    fn default() -> Linker {
+        Linker::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ty::ValueType;
+    use error::{Trap, TrapFrame};
+
+    // (module
+    //   (import "env" "double" (func (param i32) (result i32)))
+    //   (func (export "f") (param i32) (result i32)
+    //     (i32.add (call 0 (get_local 0)) (i32.const 1))))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x02, 0x0e, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x06, 0x64, 0x6f, 0x75, 0x62, 0x6c, 0x65,
+        0x00, 0x00,
+        0x03, 0x02, 0x01, 0x00,
+        0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x01,
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x20, 0x00, 0x10, 0x00, 0x41, 0x01, 0x6a, 0x0b,
+    ];
+
+    /// A trap in `f` at code section offset `offset`.
+    fn trap(error: RuntimeError, offset: u32) -> Error {
+        Error::Trap(Trap {
+            error,
+            backtrace: vec![TrapFrame { func: 1, name: None, offset: Some(offset) }],
+        })
+    }
+
+    fn i32_to_i32() -> FunctionType {
+        FunctionType { params: vec![ValueType::I32], results: vec![ValueType::I32] }
+    }
+
+    #[test]
+    fn test_host_call() {
+        let module = Module::decode_from(MODULE).unwrap();
+        let mut linker = Linker::new();
+        linker.func("env", "double", i32_to_i32(), |_, args| {
+            match args[0] {
+                Value::I32(x) if x >= 0 => Ok(vec![Value::I32(x * 2)]),
+                _ => Err(RuntimeError::Host("negative".to_string())),
+            }
+        });
+
+        let mut inst = linker.instantiate(&module).unwrap();
+        assert_eq!(inst.invoke("f", &[Value::I32(20)]), Ok(vec![Value::I32(41)]));
+        assert_eq!(inst.invoke("f", &[Value::I32(-1)]),
+                   Err(trap(RuntimeError::Host("negative".to_string()), 5)));
+
+        // f costs four ops; the host call is free.
+        assert_eq!(inst.invoke_with_fuel("f", &[Value::I32(1)], 3),
+                   Err(trap(RuntimeError::OutOfFuel, 9)));
+        assert_eq!(inst.invoke_with_fuel("f", &[Value::I32(1)], 4), Ok(vec![Value::I32(3)]));
+        assert_eq!(inst.fuel(), None);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let module = Module::decode_from(MODULE).unwrap();
+        assert_eq!(Instance::new(&module).err(),
+                   Some(InstantiationError::UnknownImport("env".to_string(),
+                                                          "double".to_string())));
+
+        let mut linker = Linker::new();
+        linker.func("env", "double",
+                    FunctionType { params: vec![ValueType::I64], results: vec![ValueType::I64] },
+                    |_, args| Ok(args.to_vec()));
+        assert_eq!(linker.instantiate(&module).err(),
+                   Some(InstantiationError::IncompatibleImport("env".to_string(),
+                                                               "double".to_string())));
+
+        let mut linker = Linker::new();
+        linker.func("env", "double", i32_to_i32(), |_, _| Ok(vec![]));
+        let mut inst = linker.instantiate(&module).unwrap();
+        assert_eq!(inst.invoke("f", &[Value::I32(1)]),
+                   Err(trap(RuntimeError::TypeFault, 5)));
+    }
+
+    // (module
+    //   (import "env" "base" (global i32))
+    //   (import "env" "counter" (global (mut i32)))
+    //   (global (export "h") (mut i32) (get_global 0))
+    //   (func (export "bump")
+    //     (set_global 1 (i32.add (get_global 1) (get_global 2)))))
+    const GLOBALS: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        0x02, 0x1c, 0x02,
+        0x03, 0x65, 0x6e, 0x76, 0x04, 0x62, 0x61, 0x73, 0x65, 0x03, 0x7f, 0x00,
+        0x03, 0x65, 0x6e, 0x76, 0x07, 0x63, 0x6f, 0x75, 0x6e, 0x74, 0x65, 0x72, 0x03, 0x7f, 0x01,
+        0x03, 0x02, 0x01, 0x00,
+        0x06, 0x06, 0x01, 0x7f, 0x01, 0x23, 0x00, 0x0b,
+        0x07, 0x0c, 0x02, 0x01, 0x68, 0x03, 0x02, 0x04, 0x62, 0x75, 0x6d, 0x70, 0x00, 0x00,
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x23, 0x01, 0x23, 0x02, 0x6a, 0x24, 0x01, 0x0b,
+    ];
+
+    #[test]
+    fn test_globals() {
+        let module = Module::decode_from(GLOBALS).unwrap();
+        let mut linker = Linker::new();
+        linker.global("env", "base", Value::I32(5), false)
+            .global("env", "counter", Value::I32(0), true);
+
+        let mut a = linker.instantiate(&module).unwrap();
+        let mut b = linker.instantiate(&module).unwrap();
+        assert_eq!(a.global("h"), Some(Value::I32(5)));
+
+        assert_eq!(a.invoke("bump", &[]), Ok(vec![]));
+        assert_eq!(linker.get_global("env", "counter"), Some(Value::I32(5)));
+
+        assert_eq!(a.set_global("h", Value::I32(1)), Ok(()));
+        assert_eq!(b.invoke("bump", &[]), Ok(vec![]));
+        assert_eq!(a.invoke("bump", &[]), Ok(vec![]));
+        assert_eq!(linker.get_global("env", "counter"), Some(Value::I32(11)));
+        assert_eq!(b.global("h"), Some(Value::I32(5)));
+
+        assert_eq!(a.set_global("h", Value::I64(1)),
+                   Err(Error::Runtime(RuntimeError::TypeFault)));
+        assert_eq!(a.set_global("bump", Value::I32(1)), Err(Error::NoSuchExport));
+
+        let mut linker = Linker::new();
+        linker.global("env", "base", Value::I32(5), false)
+            .global("env", "counter", Value::I32(0), false);
+        assert_eq!(linker.instantiate(&module).err(),
+                   Some(InstantiationError::IncompatibleImport("env".to_string(),
+                                                               "counter".to_string())));
+    }
+}
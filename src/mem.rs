@@ -1,19 +1,25 @@
 use expr::MemoryImmed;
-use byteorder::{ByteOrder, NativeEndian};
+use store::SharedLimiter;
+use byteorder::{ByteOrder, LittleEndian};
 
-const PAGE_SIZE: usize = 64 * 1024;
+pub const PAGE_SIZE: usize = 64 * 1024;
 const PAGE_SHIFT: usize = 16;
 
-struct Page([u8; PAGE_SIZE]);
+/// Memories are addressed with 32 bits, so may not exceed 4 GiB.
+pub const MAX_PAGES: usize = 65536;
 
+/// A linear memory: a contiguous, little-endian byte array that grows in
+/// 64 KiB pages.
 pub struct Memory {
-    pages: Vec<Box<Page>>
+    bytes: Vec<u8>,
+    maximum: usize,
+    limiter: Option<SharedLimiter>,
 }
 
 macro_rules! load {
     ($name:ident, $result:ty, $size:expr, $conv:expr) => (
-        pub fn $name(&self, immed: &MemoryImmed) -> Option<$result> {
-            self.get(immed, $size)
+        pub fn $name(&self, addr: u32, immed: &MemoryImmed) -> Option<$result> {
+            self.get(effective(addr, immed), $size)
                 .map(|xs: &[u8]| $conv(xs) as $result)
         }
     );
@@ -21,114 +27,181 @@ macro_rules! load {
 
 macro_rules! store {
     ($name:ident, $type:ty, $size:expr, $conv:expr) => (
-        pub fn $name(&mut self, value: $type, immed: &MemoryImmed) -> Option<()> {
-            self.get_mut(immed, $size)
+        pub fn $name(&mut self, addr: u32, value: $type, immed: &MemoryImmed) -> Option<()> {
+            self.get_mut(effective(addr, immed), $size)
                 .map(|xs: &mut [u8]| $conv(xs, value))
         }
     );
 }
 
+/// The effective address of an access: the dynamic address plus the
+/// static offset, which may exceed the 32-bit address space.
+fn effective(addr: u32, immed: &MemoryImmed) -> u64 {
+    addr as u64 + immed.offset as u64
+}
+
 impl Memory {
     pub fn new() -> Memory {
-        Memory { pages: vec![] }
+        Memory::with_maximum(None)
+    }
+
+    /// An empty memory that may grow to at most `maximum` pages.
+    pub fn with_maximum(maximum: Option<u32>) -> Memory {
+        Memory::limited(maximum, None)
+    }
+
+    pub(crate) fn limited(maximum: Option<u32>, limiter: Option<SharedLimiter>) -> Memory {
+        let maximum = maximum.map_or(MAX_PAGES, |max| (max as usize).min(MAX_PAGES));
+        Memory { bytes: vec![], maximum, limiter }
+    }
+
+    /// The most pages this memory may grow to.
+    pub fn maximum(&self) -> u32 {
+        self.maximum as u32
     }
 
     pub fn len_pages(&self) -> usize {
-        self.pages.len()
+        self.bytes.len() >> PAGE_SHIFT
     }
 
+    /// Grow by `delta` pages, returning the previous size in pages, or
+    /// -1 if the memory cannot grow that far.
     pub fn grow(&mut self, delta: i32) -> i32 {
         if delta < 0 {
             return -1;
         }
 
-        let old_pages = self.pages.len();
-        let new_pages = old_pages.checked_add(delta as usize);
+        let old_pages = self.len_pages();
+        let new_pages = old_pages + delta as usize;
+        if new_pages > self.maximum {
+            return -1;
+        }
 
-        match new_pages {
-            Some(_) => {
-                for _ in 0..delta {
-                    self.pages.push(Box::new(Page([0u8; PAGE_SIZE])));
-                }
-                old_pages as i32
+        let additional = (delta as usize) << PAGE_SHIFT;
+        if let Some(ref limiter) = self.limiter {
+            let allowed = delta == 0 || limiter.borrow_mut()
+                .memory_growing(self.bytes.len(), new_pages << PAGE_SHIFT,
+                                Some(self.maximum << PAGE_SHIFT));
+            if !allowed {
+                return -1;
             }
+        }
 
-            None => -1
+        if self.bytes.try_reserve_exact(additional).is_err() {
+            if let Some(ref limiter) = self.limiter {
+                limiter.borrow_mut().memory_released(additional);
+            }
+            return -1;
         }
+
+        self.bytes.resize(new_pages << PAGE_SHIFT, 0);
+        old_pages as i32
     }
 
-    fn get(&self, immed: &MemoryImmed, sz: usize) -> Option<&[u8]> {
-        let page = (immed.offset >> PAGE_SHIFT) as usize;
-        let offset = (immed.offset & 0xffff) as usize;
-        let offset_end = offset + sz;
+    /// The whole memory.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes
+    }
 
-        if page < self.pages.len() && offset_end <= PAGE_SIZE {
-            Some(&self.pages[page].0[offset..offset_end])
-        } else {
-            None
-        }
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    /// Fill `buf` from memory starting at byte address `addr`.
+    pub fn read(&self, addr: u32, buf: &mut [u8]) -> Option<()> {
+        self.get(addr as u64, buf.len())
+            .map(|xs| buf.copy_from_slice(xs))
+    }
+
+    /// Whether `len` bytes starting at byte address `addr` are in bounds.
+    pub fn fits(&self, addr: u32, len: usize) -> bool {
+        self.get(addr as u64, len).is_some()
+    }
+
+    /// Copy `data` into memory starting at byte address `addr`.
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> Option<()> {
+        self.get_mut(addr as u64, data.len())
+            .map(|xs| xs.copy_from_slice(data))
     }
 
-    fn get_mut(&mut self, immed: &MemoryImmed, sz: usize) -> Option<&mut [u8]> {
-        let page = (immed.offset >> PAGE_SHIFT) as usize;
-        let offset = (immed.offset & 0xffff) as usize;
-        let offset_end = offset + sz;
+    fn get(&self, start: u64, sz: usize) -> Option<&[u8]> {
+        let end = start.checked_add(sz as u64)?;
+        if end > self.bytes.len() as u64 {
+            return None;
+        }
+        Some(&self.bytes[start as usize..end as usize])
+    }
 
-        if page < self.pages.len() && offset_end <= PAGE_SIZE {
-            Some(&mut self.pages[page].0[offset..offset_end])
-        } else {
-            None
+    fn get_mut(&mut self, start: u64, sz: usize) -> Option<&mut [u8]> {
+        let end = start.checked_add(sz as u64)?;
+        if end > self.bytes.len() as u64 {
+            return None;
         }
+        Some(&mut self.bytes[start as usize..end as usize])
     }
 
     load!(i32_load8_s, i32, 1, |xs: &[u8]| xs[0] as i8);
     load!(i32_load8_u, i32, 1, |xs: &[u8]| xs[0] as u32);
-    load!(i32_load16_s, i32, 2, NativeEndian::read_i16);
-    load!(i32_load16_u, i32, 2, NativeEndian::read_u16);
-    load!(i32_load, i32, 4, NativeEndian::read_i32);
+    load!(i32_load16_s, i32, 2, LittleEndian::read_i16);
+    load!(i32_load16_u, i32, 2, LittleEndian::read_u16);
+    load!(i32_load, i32, 4, LittleEndian::read_i32);
 
     load!(i64_load8_s, i64, 1, |xs: &[u8]| xs[0] as i8);
     load!(i64_load8_u, i64, 1, |xs: &[u8]| xs[0] as u64);
-    load!(i64_load16_s, i64, 2, NativeEndian::read_i16);
-    load!(i64_load16_u, i64, 2, NativeEndian::read_u16);
-    load!(i64_load32_s, i64, 4, NativeEndian::read_i32);
-    load!(i64_load32_u, i64, 4, NativeEndian::read_u32);
-    load!(i64_load, i64, 8, NativeEndian::read_i64);
+    load!(i64_load16_s, i64, 2, LittleEndian::read_i16);
+    load!(i64_load16_u, i64, 2, LittleEndian::read_u16);
+    load!(i64_load32_s, i64, 4, LittleEndian::read_i32);
+    load!(i64_load32_u, i64, 4, LittleEndian::read_u32);
+    load!(i64_load, i64, 8, LittleEndian::read_i64);
 
-    load!(f32_load, f32, 4, NativeEndian::read_f32);
-    load!(f64_load, f64, 8, NativeEndian::read_f64);
+    load!(f32_load, f32, 4, LittleEndian::read_f32);
+    load!(f64_load, f64, 8, LittleEndian::read_f64);
 
     fn store8_32(xs: &mut [u8], value: i32) {
         xs[0] = (value as i8) as u8;
     }
 
     fn store16_32(xs: &mut [u8], value: i32) {
-        NativeEndian::write_i16(xs, value as i16)
+        LittleEndian::write_i16(xs, value as i16)
     }
 
     store!(i32_store8, i32, 1, Memory::store8_32);
     store!(i32_store16, i32, 2, Memory::store16_32);
-    store!(i32_store, i32, 4, NativeEndian::write_i32);
+    store!(i32_store, i32, 4, LittleEndian::write_i32);
 
     fn store8_64(xs: &mut [u8], value: i64) {
         xs[0] = (value as i8) as u8;
     }
 
     fn store16_64(xs: &mut [u8], value: i64) {
-        NativeEndian::write_i16(xs, value as i16)
+        LittleEndian::write_i16(xs, value as i16)
     }
 
     fn store32_64(xs: &mut [u8], value: i64) {
-        NativeEndian::write_i32(xs, value as i32)
+        LittleEndian::write_i32(xs, value as i32)
     }
 
     store!(i64_store8, i64, 1, Memory::store8_64);
     store!(i64_store16, i64, 2, Memory::store16_64);
     store!(i64_store32, i64, 4, Memory::store32_64);
-    store!(i64_store, i64, 8, NativeEndian::write_i64);
+    store!(i64_store, i64, 8, LittleEndian::write_i64);
 
-    store!(f32_store, f32, 4, NativeEndian::write_f32);
-    store!(f64_store, f64, 8, NativeEndian::write_f64);
+    store!(f32_store, f32, 4, LittleEndian::write_f32);
+    store!(f64_store, f64, 8, LittleEndian::write_f64);
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if let Some(ref limiter) = self.limiter {
+            limiter.borrow_mut().memory_released(self.bytes.len());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -143,50 +216,87 @@ mod test {
     fn test_load() {
         let mut m = Memory::new();
         m.grow(1);
-        m.i32_store(0xffff, &immed(0));
+        m.i32_store(0, 0xffff, &immed(0));
 
-        assert_eq!(Some(-1), m.i32_load8_s(&immed(0)));
-        assert_eq!(Some(-1), m.i32_load8_s(&immed(1)));
-        assert_eq!(Some(0), m.i32_load8_s(&immed(2)));
-        assert_eq!(Some(0), m.i32_load8_s(&immed(3)));
+        assert_eq!(Some(-1), m.i32_load8_s(0, &immed(0)));
+        assert_eq!(Some(-1), m.i32_load8_s(0, &immed(1)));
+        assert_eq!(Some(0), m.i32_load8_s(0, &immed(2)));
+        assert_eq!(Some(0), m.i32_load8_s(0, &immed(3)));
 
-        assert_eq!(Some(0xff), m.i32_load8_u(&immed(0)));
-        assert_eq!(Some(0xff), m.i32_load8_u(&immed(1)));
-        assert_eq!(Some(0), m.i32_load8_u(&immed(2)));
-        assert_eq!(Some(0), m.i32_load8_u(&immed(3)));
+        assert_eq!(Some(0xff), m.i32_load8_u(0, &immed(0)));
+        assert_eq!(Some(0xff), m.i32_load8_u(0, &immed(1)));
+        assert_eq!(Some(0), m.i32_load8_u(0, &immed(2)));
+        assert_eq!(Some(0), m.i32_load8_u(0, &immed(3)));
 
-        assert_eq!(Some(-1), m.i32_load16_s(&immed(0)));
-        assert_eq!(Some(0), m.i32_load16_s(&immed(2)));
+        assert_eq!(Some(-1), m.i32_load16_s(0, &immed(0)));
+        assert_eq!(Some(0), m.i32_load16_s(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i32_load16_u(&immed(0)));
-        assert_eq!(Some(0), m.i32_load16_u(&immed(2)));
+        assert_eq!(Some(0xffff), m.i32_load16_u(0, &immed(0)));
+        assert_eq!(Some(0), m.i32_load16_u(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i32_load(&immed(0)));
-        assert_eq!(Some(0xff), m.i32_load(&immed(1)));
+        assert_eq!(Some(0xffff), m.i32_load(0, &immed(0)));
+        assert_eq!(Some(0xff), m.i32_load(0, &immed(1)));
 
-        assert_eq!(Some(-1), m.i64_load8_s(&immed(0)));
-        assert_eq!(Some(-1), m.i64_load8_s(&immed(1)));
-        assert_eq!(Some(0), m.i64_load8_s(&immed(2)));
-        assert_eq!(Some(0), m.i64_load8_s(&immed(3)));
+        assert_eq!(Some(-1), m.i64_load8_s(0, &immed(0)));
+        assert_eq!(Some(-1), m.i64_load8_s(0, &immed(1)));
+        assert_eq!(Some(0), m.i64_load8_s(0, &immed(2)));
+        assert_eq!(Some(0), m.i64_load8_s(0, &immed(3)));
 
-        assert_eq!(Some(0xff), m.i64_load8_u(&immed(0)));
-        assert_eq!(Some(0xff), m.i64_load8_u(&immed(1)));
-        assert_eq!(Some(0), m.i64_load8_u(&immed(2)));
-        assert_eq!(Some(0), m.i64_load8_u(&immed(3)));
+        assert_eq!(Some(0xff), m.i64_load8_u(0, &immed(0)));
+        assert_eq!(Some(0xff), m.i64_load8_u(0, &immed(1)));
+        assert_eq!(Some(0), m.i64_load8_u(0, &immed(2)));
+        assert_eq!(Some(0), m.i64_load8_u(0, &immed(3)));
 
-        assert_eq!(Some(-1), m.i64_load16_s(&immed(0)));
-        assert_eq!(Some(0), m.i64_load16_s(&immed(2)));
+        assert_eq!(Some(-1), m.i64_load16_s(0, &immed(0)));
+        assert_eq!(Some(0), m.i64_load16_s(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i64_load16_u(&immed(0)));
-        assert_eq!(Some(0), m.i64_load16_u(&immed(2)));
+        assert_eq!(Some(0xffff), m.i64_load16_u(0, &immed(0)));
+        assert_eq!(Some(0), m.i64_load16_u(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i64_load32_s(&immed(0)));
-        assert_eq!(Some(0), m.i64_load32_s(&immed(2)));
+        assert_eq!(Some(0xffff), m.i64_load32_s(0, &immed(0)));
+        assert_eq!(Some(0), m.i64_load32_s(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i64_load32_u(&immed(0)));
-        assert_eq!(Some(0), m.i64_load32_u(&immed(2)));
+        assert_eq!(Some(0xffff), m.i64_load32_u(0, &immed(0)));
+        assert_eq!(Some(0), m.i64_load32_u(0, &immed(2)));
 
-        assert_eq!(Some(0xffff), m.i64_load(&immed(0)));
-        assert_eq!(Some(0xff), m.i64_load(&immed(1)));
+        assert_eq!(Some(0xffff), m.i64_load(0, &immed(0)));
+        assert_eq!(Some(0xff), m.i64_load(0, &immed(1)));
+    }
+
+    #[test]
+    fn test_address() {
+        let mut m = Memory::new();
+        m.grow(2);
+
+        assert_eq!(Some(()), m.i64_store(0xfffc, 0x0102030405060708, &immed(0)));
+        assert_eq!(Some(0x01020304), m.i32_load(0xfff0, &immed(0x10)));
+        assert_eq!(Some(0x05060708), m.i32_load(0xfffc, &immed(0)));
+        assert_eq!(Some(0x0102030405060708), m.i64_load(0xfffc, &immed(0)));
+
+        assert_eq!(Some(()), m.i32_store8(0x1ffff, 1, &immed(0)));
+        assert_eq!(None, m.i32_store16(0x1ffff, 1, &immed(0)));
+        assert_eq!(None, m.i32_load8_u(0x1ffff, &immed(1)));
+        assert_eq!(None, m.i32_load8_u(0xffffffff, &immed(0xffffffff)));
+        assert_eq!(None, m.i64_load(0xffffffff, &immed(0)));
+
+        assert_eq!(&m.data()[0xfffc..0x10004], &[8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut m = Memory::new();
+        assert_eq!(m.grow(0), 0);
+        assert_eq!(m.grow(2), 0);
+        assert_eq!(m.grow(-1), -1);
+        assert_eq!(m.grow(MAX_PAGES as i32), -1);
+        assert_eq!(m.len_pages(), 2);
+        assert_eq!(m.data().len(), 2 * PAGE_SIZE);
+
+        let mut m = Memory::with_maximum(Some(3));
+        assert_eq!(m.grow(2), 0);
+        assert_eq!(m.grow(2), -1);
+        assert_eq!(m.grow(1), 2);
+        assert_eq!(m.grow(1), -1);
+        assert_eq!(m.len_pages(), 3);
     }
 }
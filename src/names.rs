@@ -0,0 +1,143 @@
+//! The "name" custom section, which gives debug names to the module,
+//! its functions and their locals.
+
+use error::CodecError;
+use codec;
+
+use untrusted;
+use std::collections::HashMap;
+
+const SUBSECTION_MODULE: u8 = 0;
+const SUBSECTION_FUNCTION: u8 = 1;
+const SUBSECTION_LOCAL: u8 = 2;
+
+#[derive(Debug, Default)]
+pub struct Names<'a> {
+    module: Option<&'a str>,
+    functions: HashMap<u32, &'a str>,
+    locals: HashMap<u32, HashMap<u32, &'a str>>,
+}
+
+fn name<'a>(rd: &mut untrusted::Reader<'a>) -> Result<&'a str, CodecError> {
+    let len = codec::read_varu32(rd)?;
+    codec::read_utf8(rd, len as usize)
+}
+
+fn name_map<'a>(rd: &mut untrusted::Reader<'a>) -> Result<HashMap<u32, &'a str>, CodecError> {
+    let count = codec::read_varu32(rd)?;
+    let mut map = HashMap::new();
+    for _ in 0..count {
+        let index = codec::read_varu32(rd)?;
+        map.insert(index, name(rd)?);
+    }
+    Ok(map)
+}
+
+fn sorted<'a>(map: &HashMap<u32, &'a str>) -> Vec<(u32, &'a str)> {
+    let mut entries: Vec<_> = map.iter().map(|(i, n)| (*i, *n)).collect();
+    entries.sort();
+    entries
+}
+
+impl<'a> Names<'a> {
+    /// Decode the payload of a "name" section.  Unknown subsections are
+    /// skipped.
+    pub fn decode(rd: &mut untrusted::Reader<'a>) -> Result<Names<'a>, CodecError> {
+        let mut names = Names::default();
+
+        while !rd.at_end() {
+            let id = codec::read_varu7(rd)?;
+            let len = codec::read_varu32(rd)?;
+            let payload = rd.skip_and_get_input(len as usize)
+                .map_err(|_| CodecError::Truncated)?;
+
+            match id {
+                SUBSECTION_MODULE => {
+                    names.module = Some(payload.read_all(CodecError::TrailingData, name)?);
+                }
+                SUBSECTION_FUNCTION => {
+                    names.functions = payload.read_all(CodecError::TrailingData, name_map)?;
+                }
+                SUBSECTION_LOCAL => {
+                    names.locals = payload.read_all(CodecError::TrailingData, |prd| {
+                        let count = codec::read_varu32(prd)?;
+                        let mut locals = HashMap::new();
+                        for _ in 0..count {
+                            let func = codec::read_varu32(prd)?;
+                            locals.insert(func, name_map(prd)?);
+                        }
+                        Ok(locals)
+                    })?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(names)
+    }
+
+    pub fn module(&self) -> Option<&'a str> {
+        self.module
+    }
+
+    /// The name of function `index`, counting imports.
+    pub fn function(&self, index: u32) -> Option<&'a str> {
+        self.functions.get(&index).cloned()
+    }
+
+    /// The name of local `index` of function `func`.  Parameters are the
+    /// first locals.
+    pub fn local(&self, func: u32, index: u32) -> Option<&'a str> {
+        self.locals.get(&func)
+            .and_then(|locals| locals.get(&index))
+            .cloned()
+    }
+
+    /// Every named function, with its index, in index order.
+    pub fn functions(&self) -> Vec<(u32, &'a str)> {
+        sorted(&self.functions)
+    }
+
+    /// Every named local of function `func`, in index order.
+    pub fn locals(&self, func: u32) -> Vec<(u32, &'a str)> {
+        self.locals.get(&func)
+            .map(sorted)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let payload = [
+            // module "m"
+            0x00, 0x02, 0x01, 0x6d,
+            // functions: 0 "f", 2 "g"
+            0x01, 0x07, 0x02, 0x00, 0x01, 0x66, 0x02, 0x01, 0x67,
+            // unknown subsection
+            0x07, 0x01, 0xff,
+            // locals: function 2, local 1 "x"
+            0x02, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x78,
+        ];
+        let names = untrusted::Input::from(&payload)
+            .read_all(CodecError::TrailingData, Names::decode)
+            .unwrap();
+
+        assert_eq!(names.module(), Some("m"));
+        assert_eq!(names.function(0), Some("f"));
+        assert_eq!(names.function(1), None);
+        assert_eq!(names.functions(), vec![(0, "f"), (2, "g")]);
+        assert_eq!(names.local(2, 1), Some("x"));
+        assert_eq!(names.locals(2), vec![(1, "x")]);
+        assert_eq!(names.local(2, 0), None);
+        assert_eq!(names.local(0, 1), None);
+
+        let truncated = &payload[..6];
+        assert!(untrusted::Input::from(truncated)
+                .read_all(CodecError::TrailingData, Names::decode)
+                .is_err());
+    }
+}
@@ -0,0 +1,823 @@
+//! Printer for the WebAssembly text format.
+//!
+//! Output uses flat, indented instructions and the current instruction
+//! names, and parses back with `parse_wat` to the same module, less any
+//! custom sections.
+
+use section::{Module, FunctionType, ImportKind, ExternalKind, ResizableLimits};
+use expr::{Op, InitExpr};
+use ty::{ValueType, BlockType};
+use sexpr::is_idchar;
+use names::Names;
+
+use std::collections::{HashMap, HashSet};
+
+fn value_type(ty: ValueType) -> &'static str {
+    match ty {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+    }
+}
+
+/// ` (param ...) (result ...)`, or nothing for `[] -> []`.
+fn signature(ty: &FunctionType) -> String {
+    let mut out = String::new();
+    if !ty.params.is_empty() {
+        out.push_str(" (param");
+        for param in &ty.params {
+            out.push(' ');
+            out.push_str(value_type(*param));
+        }
+        out.push(')');
+    }
+    out.push_str(&results(&ty.results));
+    out
+}
+
+/// ` (result ...)`, or nothing if there are no results.
+fn results(types: &[ValueType]) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+    let names: Vec<_> = types.iter().map(|ty| value_type(*ty)).collect();
+    format!(" (result {})", names.join(" "))
+}
+
+fn limits(limits: &ResizableLimits) -> String {
+    match *limits {
+        ResizableLimits::Initial(initial) => format!("{}", initial),
+        ResizableLimits::InitialMax(initial, max) => format!("{} {}", initial, max),
+    }
+}
+
+/// A string literal, escaping anything but printable ASCII.
+fn string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A float literal that parses back to exactly `bits`.  NaNs keep their
+/// sign and payload; everything else is printed as the shortest
+/// decimal that rounds to it, which is what `Debug` gives.
+fn float(bits: u64, mant: u32, expo: u32, decimal: String) -> String {
+    let exp_mask = (1 << expo) - 1;
+    let payload = bits & ((1 << mant) - 1);
+
+    if (bits >> mant) & exp_mask != exp_mask || payload == 0 {
+        return decimal;
+    }
+
+    let sign = if bits >> (mant + expo) & 1 == 1 { "-" } else { "" };
+    if payload == 1 << (mant - 1) {
+        format!("{}nan", sign)
+    } else {
+        format!("{}nan:0x{:x}", sign, payload)
+    }
+}
+
+fn f32_literal(f: f32) -> String {
+    float(f.to_bits() as u64, 23, 8, format!("{:?}", f))
+}
+
+fn f64_literal(f: f64) -> String {
+    float(f.to_bits(), 52, 11, format!("{:?}", f))
+}
+
+fn mnemonic(op: &Op) -> &'static str {
+    match *op {
+        Op::Block(_) => "block",
+        Op::Loop(_) => "loop",
+        Op::If(_) => "if",
+        Op::Else => "else",
+        Op::End => "end",
+        Op::Branch(_) => "br",
+        Op::BranchIf(_) => "br_if",
+        Op::BranchTable(_) => "br_table",
+        Op::Call(_) => "call",
+        Op::CallIndirect(_) => "call_indirect",
+        Op::GetLocal(_) => "local.get",
+        Op::SetLocal(_) => "local.set",
+        Op::TeeLocal(_) => "local.tee",
+        Op::GetGlobal(_) => "global.get",
+        Op::SetGlobal(_) => "global.set",
+        Op::I32Const(_) => "i32.const",
+        Op::I64Const(_) => "i64.const",
+        Op::F32Const(_) => "f32.const",
+        Op::F64Const(_) => "f64.const",
+        Op::Unreachable => "unreachable",
+        Op::Nop => "nop",
+        Op::Return => "return",
+        Op::Drop => "drop",
+        Op::Select => "select",
+        Op::I32Eqz => "i32.eqz",
+        Op::I32Eq => "i32.eq",
+        Op::I32Ne => "i32.ne",
+        Op::I32LtSigned => "i32.lt_s",
+        Op::I32LtUnsigned => "i32.lt_u",
+        Op::I32GtSigned => "i32.gt_s",
+        Op::I32GtUnsigned => "i32.gt_u",
+        Op::I32LeSigned => "i32.le_s",
+        Op::I32LeUnsigned => "i32.le_u",
+        Op::I32GeSigned => "i32.ge_s",
+        Op::I32GeUnsigned => "i32.ge_u",
+        Op::I64Eqz => "i64.eqz",
+        Op::I64Eq => "i64.eq",
+        Op::I64Ne => "i64.ne",
+        Op::I64LtSigned => "i64.lt_s",
+        Op::I64LtUnsigned => "i64.lt_u",
+        Op::I64GtSigned => "i64.gt_s",
+        Op::I64GtUnsigned => "i64.gt_u",
+        Op::I64LeSigned => "i64.le_s",
+        Op::I64LeUnsigned => "i64.le_u",
+        Op::I64GeSigned => "i64.ge_s",
+        Op::I64GeUnsigned => "i64.ge_u",
+        Op::F32Eq => "f32.eq",
+        Op::F32Ne => "f32.ne",
+        Op::F32Lt => "f32.lt",
+        Op::F32Gt => "f32.gt",
+        Op::F32Le => "f32.le",
+        Op::F32Ge => "f32.ge",
+        Op::F64Eq => "f64.eq",
+        Op::F64Ne => "f64.ne",
+        Op::F64Lt => "f64.lt",
+        Op::F64Gt => "f64.gt",
+        Op::F64Le => "f64.le",
+        Op::F64Ge => "f64.ge",
+        Op::I32Clz => "i32.clz",
+        Op::I32Ctz => "i32.ctz",
+        Op::I32Popcnt => "i32.popcnt",
+        Op::I32Add => "i32.add",
+        Op::I32Sub => "i32.sub",
+        Op::I32Mul => "i32.mul",
+        Op::I32DivSigned => "i32.div_s",
+        Op::I32DivUnsigned => "i32.div_u",
+        Op::I32RemSigned => "i32.rem_s",
+        Op::I32RemUnsigned => "i32.rem_u",
+        Op::I32And => "i32.and",
+        Op::I32Or => "i32.or",
+        Op::I32Xor => "i32.xor",
+        Op::I32Shl => "i32.shl",
+        Op::I32ShrSigned => "i32.shr_s",
+        Op::I32ShrUnsigned => "i32.shr_u",
+        Op::I32Rotl => "i32.rotl",
+        Op::I32Rotr => "i32.rotr",
+        Op::I64Clz => "i64.clz",
+        Op::I64Ctz => "i64.ctz",
+        Op::I64Popcnt => "i64.popcnt",
+        Op::I64Add => "i64.add",
+        Op::I64Sub => "i64.sub",
+        Op::I64Mul => "i64.mul",
+        Op::I64DivSigned => "i64.div_s",
+        Op::I64DivUnsigned => "i64.div_u",
+        Op::I64RemSigned => "i64.rem_s",
+        Op::I64RemUnsigned => "i64.rem_u",
+        Op::I64And => "i64.and",
+        Op::I64Or => "i64.or",
+        Op::I64Xor => "i64.xor",
+        Op::I64Shl => "i64.shl",
+        Op::I64ShrSigned => "i64.shr_s",
+        Op::I64ShrUnsigned => "i64.shr_u",
+        Op::I64Rotl => "i64.rotl",
+        Op::I64Rotr => "i64.rotr",
+        Op::F32Abs => "f32.abs",
+        Op::F32Neg => "f32.neg",
+        Op::F32Ceil => "f32.ceil",
+        Op::F32Floor => "f32.floor",
+        Op::F32Trunc => "f32.trunc",
+        Op::F32Nearest => "f32.nearest",
+        Op::F32Sqrt => "f32.sqrt",
+        Op::F32Add => "f32.add",
+        Op::F32Sub => "f32.sub",
+        Op::F32Mul => "f32.mul",
+        Op::F32Div => "f32.div",
+        Op::F32Min => "f32.min",
+        Op::F32Max => "f32.max",
+        Op::F32Copysign => "f32.copysign",
+        Op::F64Abs => "f64.abs",
+        Op::F64Neg => "f64.neg",
+        Op::F64Ceil => "f64.ceil",
+        Op::F64Floor => "f64.floor",
+        Op::F64Trunc => "f64.trunc",
+        Op::F64Nearest => "f64.nearest",
+        Op::F64Sqrt => "f64.sqrt",
+        Op::F64Add => "f64.add",
+        Op::F64Sub => "f64.sub",
+        Op::F64Mul => "f64.mul",
+        Op::F64Div => "f64.div",
+        Op::F64Min => "f64.min",
+        Op::F64Max => "f64.max",
+        Op::F64Copysign => "f64.copysign",
+        Op::I32WrapI64 => "i32.wrap_i64",
+        Op::I32TruncSignedF32 => "i32.trunc_f32_s",
+        Op::I32TruncUnsignedF32 => "i32.trunc_f32_u",
+        Op::I32TruncSignedF64 => "i32.trunc_f64_s",
+        Op::I32TruncUnsignedF64 => "i32.trunc_f64_u",
+        Op::I64ExtendSignedI32 => "i64.extend_i32_s",
+        Op::I64ExtendUnsignedI32 => "i64.extend_i32_u",
+        Op::I64TruncSignedF32 => "i64.trunc_f32_s",
+        Op::I64TruncUnsignedF32 => "i64.trunc_f32_u",
+        Op::I64TruncSignedF64 => "i64.trunc_f64_s",
+        Op::I64TruncUnsignedF64 => "i64.trunc_f64_u",
+        Op::F32ConvertSignedI32 => "f32.convert_i32_s",
+        Op::F32ConvertUnsignedI32 => "f32.convert_i32_u",
+        Op::F32ConvertSignedI64 => "f32.convert_i64_s",
+        Op::F32ConvertUnsignedI64 => "f32.convert_i64_u",
+        Op::F32DemoteF64 => "f32.demote_f64",
+        Op::F64ConvertSignedI32 => "f64.convert_i32_s",
+        Op::F64ConvertUnsignedI32 => "f64.convert_i32_u",
+        Op::F64ConvertSignedI64 => "f64.convert_i64_s",
+        Op::F64ConvertUnsignedI64 => "f64.convert_i64_u",
+        Op::F64PromoteF32 => "f64.promote_f32",
+        Op::I32ReinterpretF32 => "i32.reinterpret_f32",
+        Op::I64ReinterpretF64 => "i64.reinterpret_f64",
+        Op::F32ReinterpretI32 => "f32.reinterpret_i32",
+        Op::F64ReinterpretI64 => "f64.reinterpret_i64",
+        Op::I32Extend8Signed => "i32.extend8_s",
+        Op::I32Extend16Signed => "i32.extend16_s",
+        Op::I64Extend8Signed => "i64.extend8_s",
+        Op::I64Extend16Signed => "i64.extend16_s",
+        Op::I64Extend32Signed => "i64.extend32_s",
+        Op::CurrentMemory(_) => "memory.size",
+        Op::GrowMemory(_) => "memory.grow",
+        Op::I32Load(_) => "i32.load",
+        Op::I64Load(_) => "i64.load",
+        Op::F32Load(_) => "f32.load",
+        Op::F64Load(_) => "f64.load",
+        Op::I32Load8Signed(_) => "i32.load8_s",
+        Op::I32Load8Unsigned(_) => "i32.load8_u",
+        Op::I32Load16Signed(_) => "i32.load16_s",
+        Op::I32Load16Unsigned(_) => "i32.load16_u",
+        Op::I64Load8Signed(_) => "i64.load8_s",
+        Op::I64Load8Unsigned(_) => "i64.load8_u",
+        Op::I64Load16Signed(_) => "i64.load16_s",
+        Op::I64Load16Unsigned(_) => "i64.load16_u",
+        Op::I64Load32Signed(_) => "i64.load32_s",
+        Op::I64Load32Unsigned(_) => "i64.load32_u",
+        Op::I32Store(_) => "i32.store",
+        Op::I64Store(_) => "i64.store",
+        Op::F32Store(_) => "f32.store",
+        Op::F64Store(_) => "f64.store",
+        Op::I32Store8(_) => "i32.store8",
+        Op::I32Store16(_) => "i32.store16",
+        Op::I64Store8(_) => "i64.store8",
+        Op::I64Store16(_) => "i64.store16",
+        Op::I64Store32(_) => "i64.store32",
+    }
+}
+
+/// `$name`, if `name` can be written as an identifier.
+fn ident(name: &str) -> Option<String> {
+    if !name.is_empty() && name.bytes().all(is_idchar) {
+        Some(format!("${}", name))
+    } else {
+        None
+    }
+}
+
+/// Identifiers for `names`, dropping any that are unusable or repeat.
+fn idents(names: Vec<(u32, &str)>) -> HashMap<u32, String> {
+    let mut seen = HashSet::new();
+    let mut idents = HashMap::new();
+    for (index, name) in names {
+        if let Some(id) = ident(name) {
+            if seen.insert(id.clone()) {
+                idents.insert(index, id);
+            }
+        }
+    }
+    idents
+}
+
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    names: Names<'a>,
+    func_names: HashMap<u32, String>,
+    /// Names of the locals of the function being printed.
+    local_names: HashMap<u32, String>,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Close the form opened on an earlier line.
+    fn close(&mut self) {
+        self.out.pop();
+        self.out.push_str(")\n");
+    }
+
+    fn func_ref(&self, index: u32) -> String {
+        match self.func_names.get(&index) {
+            Some(name) => name.clone(),
+            None => format!("{}", index),
+        }
+    }
+
+    fn local_ref(&self, index: u32) -> String {
+        match self.local_names.get(&index) {
+            Some(name) => name.clone(),
+            None => format!("{}", index),
+        }
+    }
+
+    /// `(param ...)` or `(local ...)` declarations for `types`, starting
+    /// at local `first`.
+    fn locals(&self, keyword: &str, first: u32, types: &[ValueType]) -> String {
+        let mut out = String::new();
+        let mut unnamed = vec![];
+
+        for (i, ty) in types.iter().enumerate() {
+            match self.local_names.get(&(first + i as u32)) {
+                Some(name) => {
+                    if !unnamed.is_empty() {
+                        out.push_str(&format!(" ({} {})", keyword, unnamed.join(" ")));
+                        unnamed.clear();
+                    }
+                    out.push_str(&format!(" ({} {} {})", keyword, name, value_type(*ty)));
+                }
+                None => unnamed.push(value_type(*ty)),
+            }
+        }
+        if !unnamed.is_empty() {
+            out.push_str(&format!(" ({} {})", keyword, unnamed.join(" ")));
+        }
+        out
+    }
+
+    /// The name and index comment for function `index`.
+    fn func_id(&self, index: u32) -> String {
+        match self.func_names.get(&index) {
+            Some(name) => format!("{} (;{};)", name, index),
+            None => format!("(;{};)", index),
+        }
+    }
+
+    fn instr(&self, op: &Op) -> String {
+        let name = mnemonic(op);
+
+        match *op {
+            Op::Block(ref ty) | Op::Loop(ref ty) | Op::If(ref ty) => match *ty {
+                BlockType::Single(ty) => format!("{} (result {})", name, value_type(ty)),
+                BlockType::Void => name.to_string(),
+                BlockType::Index(index) => format!("{} (type {})", name, index),
+            },
+            Op::Branch(depth) | Op::BranchIf(depth) => format!("{} {}", name, depth),
+            Op::BranchTable(ref table) => {
+                let mut out = name.to_string();
+                for target in table.targets.iter().chain(Some(&table.default)) {
+                    out.push_str(&format!(" {}", target));
+                }
+                out
+            }
+            Op::Call(index) => format!("{} {}", name, self.func_ref(index)),
+            Op::CallIndirect(index) => format!("{} (type {})", name, index),
+            Op::GetLocal(index) | Op::SetLocal(index) | Op::TeeLocal(index) => {
+                format!("{} {}", name, self.local_ref(index))
+            }
+            Op::GetGlobal(index) | Op::SetGlobal(index) => format!("{} {}", name, index),
+            Op::I32Const(c) => format!("{} {}", name, c),
+            Op::I64Const(c) => format!("{} {}", name, c),
+            Op::F32Const(c) => format!("{} {}", name, f32_literal(c)),
+            Op::F64Const(c) => format!("{} {}", name, f64_literal(c)),
+            _ => {
+                let mut out = name.to_string();
+                if let Some((immed, natural, _)) = op.memory_access() {
+                    if immed.offset != 0 {
+                        out.push_str(&format!(" offset={}", immed.offset));
+                    }
+                    if immed.align != natural {
+                        // An invalid module may claim an alignment too
+                        // large to write out.
+                        match 1u64.checked_shl(immed.align) {
+                            Some(align) => out.push_str(&format!(" align={}", align)),
+                            None => out.push_str(&format!(" (; align=2**{} ;)", immed.align)),
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    fn init_expr(&self, init: &InitExpr) -> String {
+        init.ops.iter()
+            .map(|op| format!("({})", self.instr(op)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Print a function's instructions.  The body need not be
+    /// balanced: a stray `else` or `end` is printed at the current level.
+    fn body(&mut self, ops: &[Op]) {
+        let mut depth = 0;
+        for op in ops {
+            match *op {
+                Op::Else if depth > 0 => {
+                    self.indent -= 1;
+                    self.line("else");
+                    self.indent += 1;
+                }
+                Op::End if depth > 0 => {
+                    depth -= 1;
+                    self.indent -= 1;
+                    self.line("end");
+                }
+                Op::Else => self.line("else"),
+                Op::End => self.line("end"),
+                Op::Block(_) | Op::Loop(_) | Op::If(_) => {
+                    let text = self.instr(op);
+                    self.line(&text);
+                    depth += 1;
+                    self.indent += 1;
+                }
+                _ => {
+                    let text = self.instr(op);
+                    self.line(&text);
+                }
+            }
+        }
+    }
+
+    fn module(&mut self, module: &Module) {
+        let header = match self.names.module().and_then(ident) {
+            Some(name) => format!("(module {}", name),
+            None => "(module".to_string(),
+        };
+        self.line(&header);
+        self.indent += 1;
+
+        for (index, ty) in module.types().iter().enumerate() {
+            let text = format!("(type (;{};) (func{}))", index, signature(ty));
+            self.line(&text);
+        }
+
+        let mut funcs = 0;
+        let mut tables = 0;
+        let mut memories = 0;
+        let mut globals = 0;
+
+        for import in module.imports() {
+            let desc = match import.kind {
+                ImportKind::Function(ty) => {
+                    funcs += 1;
+                    format!("(func {} (type {}))", self.func_id(funcs - 1), ty)
+                }
+                ImportKind::Table(ref table) => {
+                    tables += 1;
+                    format!("(table (;{};) {} funcref)", tables - 1, limits(&table.limits))
+                }
+                ImportKind::Memory(ref memory) => {
+                    memories += 1;
+                    format!("(memory (;{};) {})", memories - 1, limits(&memory.limits))
+                }
+                ImportKind::Global(ref global) => {
+                    globals += 1;
+                    let ty = if global.mutable {
+                        format!("(mut {})", value_type(global.content))
+                    } else {
+                        value_type(global.content).to_string()
+                    };
+                    format!("(global (;{};) {})", globals - 1, ty)
+                }
+            };
+            let text = format!("(import {} {} {})",
+                               string(import.module.as_bytes()),
+                               string(import.field.as_bytes()),
+                               desc);
+            self.line(&text);
+        }
+
+        let types = module.types();
+        for (ty, body) in module.functions().iter().zip(module.code()) {
+            let index = funcs;
+            funcs += 1;
+
+            self.local_names = idents(self.names.locals(index));
+
+            let mut text = format!("(func {} (type {})", self.func_id(index), ty);
+            if let Some(sig) = types.get(*ty as usize) {
+                text.push_str(&self.locals("param", 0, &sig.params));
+                text.push_str(&results(&sig.results));
+            }
+            self.line(&text);
+
+            self.indent += 1;
+            let params = types.get(*ty as usize).map_or(0, |sig| sig.params.len()) as u32;
+            let locals = self.locals("local", params, &body.local_types());
+            if !locals.is_empty() {
+                self.line(&locals[1..]);
+            }
+            self.body(&body.ops);
+            self.indent -= 1;
+            self.close();
+        }
+        self.local_names.clear();
+
+        for table in module.tables() {
+            let text = format!("(table (;{};) {} funcref)", tables, limits(&table.limits));
+            self.line(&text);
+            tables += 1;
+        }
+
+        for memory in module.memories() {
+            let text = format!("(memory (;{};) {})", memories, limits(&memory.limits));
+            self.line(&text);
+            memories += 1;
+        }
+
+        for global in module.globals() {
+            let ty = if global.ty.mutable {
+                format!("(mut {})", value_type(global.ty.content))
+            } else {
+                value_type(global.ty.content).to_string()
+            };
+            let text = format!("(global (;{};) {} {})", globals, ty, self.init_expr(&global.init));
+            self.line(&text);
+            globals += 1;
+        }
+
+        for export in module.exports() {
+            let desc = match export.kind {
+                ExternalKind::Function => format!("func {}", self.func_ref(export.index)),
+                ExternalKind::Table => format!("table {}", export.index),
+                ExternalKind::Memory => format!("memory {}", export.index),
+                ExternalKind::Global => format!("global {}", export.index),
+            };
+            let text = format!("(export {} ({}))", string(export.field.as_bytes()), desc);
+            self.line(&text);
+        }
+
+        if let Some(start) = module.start() {
+            let text = format!("(start {})", self.func_ref(start));
+            self.line(&text);
+        }
+
+        for (index, elem) in module.elements().iter().enumerate() {
+            let mut text = format!("(elem (;{};)", index);
+            if elem.index != 0 {
+                text.push_str(&format!(" {}", elem.index));
+            }
+            text.push_str(&format!(" {}", self.init_expr(&elem.offset)));
+            for func in &elem.elems {
+                text.push_str(&format!(" {}", self.func_ref(*func)));
+            }
+            text.push(')');
+            self.line(&text);
+        }
+
+        for (index, data) in module.data().iter().enumerate() {
+            let mut text = format!("(data (;{};)", index);
+            if data.index != 0 {
+                text.push_str(&format!(" {}", data.index));
+            }
+            text.push_str(&format!(" {} {})", self.init_expr(&data.init), string(data.data)));
+            self.line(&text);
+        }
+
+        self.indent -= 1;
+        self.close();
+    }
+}
+
+/// Render `module` in the text format.  The module, functions and
+/// locals are named from the "name" section where it gives usable
+/// names.
+pub fn print_wat(module: &Module) -> String {
+    let names = module.names().unwrap_or_default();
+    let func_names = idents(names.functions());
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        names,
+        func_names,
+        local_names: HashMap::new(),
+    };
+    printer.module(module);
+    printer.out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use section::Section;
+    use function::FunctionBody;
+    use expr::{MemoryImmed, BranchTable};
+    use wat::parse_wat;
+    use codec;
+
+    /// Print `module`, parse the text back and check it encodes the same.
+    fn round_trip(module: &Module) {
+        let text = print_wat(module);
+        assert_eq!(parse_wat(&text).map_err(|e| (e, text.clone())), Ok(module.encode()));
+    }
+
+    #[test]
+    fn test_print() {
+        let bytes = parse_wat(r#"
+            (module
+              (import "env" "log" (func $log (param i32)))
+              (memory 1)
+              (global (mut f32) (f32.const -0.5))
+              (func $f (export "f") (param i32) (result i32) (local i64)
+                (if (result i32) (get_local 0)
+                  (then (i32.load8_u offset=3 (i32.const 0)))
+                  (else (call 0 (i32.const 10)) (i32.const 1))))
+              (data (i32.const 8) "a\"\n"))
+        "#).unwrap();
+        let module = Module::decode_from(&bytes).unwrap();
+
+        assert_eq!(print_wat(&module), r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32) (result i32)))
+  (import "env" "log" (func (;0;) (type 0)))
+  (func (;1;) (type 1) (param i32) (result i32)
+    (local i64)
+    local.get 0
+    if (result i32)
+      i32.const 0
+      i32.load8_u offset=3
+    else
+      i32.const 10
+      call 0
+      i32.const 1
+    end)
+  (memory (;0;) 1)
+  (global (;0;) (mut f32) (f32.const -0.5))
+  (export "f" (func 1))
+  (data (;0;) (i32.const 8) "a\"\0a"))
+"#);
+    }
+
+    #[test]
+    fn test_round_trip_binary() {
+        let input = include_bytes!("../webdsp_c.wasm").as_ref();
+        round_trip(&Module::decode_from(input).unwrap());
+    }
+
+    #[test]
+    fn test_every_op() {
+        let immed = || MemoryImmed { align: 0, offset: 4 };
+        let mut ops = vec![
+            Op::Block(BlockType::Single(ValueType::I32)), Op::Loop(BlockType::Void),
+            Op::If(BlockType::Void), Op::Else, Op::End, Op::End, Op::End,
+            Op::Block(BlockType::Index(1)), Op::End,
+            Op::Unreachable, Op::Nop, Op::Branch(1), Op::BranchIf(1),
+            Op::BranchTable(BranchTable { targets: vec![0, 1], default: 2 }), Op::Return,
+            Op::Call(1), Op::CallIndirect(1), Op::Drop, Op::Select, Op::GetLocal(1),
+            Op::SetLocal(1), Op::TeeLocal(1), Op::GetGlobal(1), Op::SetGlobal(1),
+            Op::I32Load(immed()), Op::I64Load(immed()), Op::F32Load(immed()),
+            Op::F64Load(immed()), Op::I32Load8Signed(immed()), Op::I32Load8Unsigned(immed()),
+            Op::I32Load16Signed(immed()), Op::I32Load16Unsigned(immed()),
+            Op::I64Load8Signed(immed()), Op::I64Load8Unsigned(immed()),
+            Op::I64Load16Signed(immed()), Op::I64Load16Unsigned(immed()),
+            Op::I64Load32Signed(immed()), Op::I64Load32Unsigned(immed()),
+            Op::I32Store(immed()), Op::I64Store(immed()), Op::F32Store(immed()),
+            Op::F64Store(immed()), Op::I32Store8(immed()), Op::I32Store16(immed()),
+            Op::I64Store8(immed()), Op::I64Store16(immed()), Op::I64Store32(immed()),
+            Op::CurrentMemory(0), Op::GrowMemory(0), Op::I32Const(-7),
+            Op::I64Const(i64::MIN), Op::F32Const(1.5), Op::F64Const(-0.1), Op::I32Eqz,
+            Op::I32Eq, Op::I32Ne, Op::I32LtSigned, Op::I32LtUnsigned, Op::I32GtSigned,
+            Op::I32GtUnsigned, Op::I32LeSigned, Op::I32LeUnsigned, Op::I32GeSigned,
+            Op::I32GeUnsigned, Op::I64Eqz, Op::I64Eq, Op::I64Ne, Op::I64LtSigned,
+            Op::I64LtUnsigned, Op::I64GtSigned, Op::I64GtUnsigned, Op::I64LeSigned,
+            Op::I64LeUnsigned, Op::I64GeSigned, Op::I64GeUnsigned, Op::F32Eq, Op::F32Ne,
+            Op::F32Lt, Op::F32Gt, Op::F32Le, Op::F32Ge, Op::F64Eq, Op::F64Ne, Op::F64Lt,
+            Op::F64Gt, Op::F64Le, Op::F64Ge, Op::I32Clz, Op::I32Ctz, Op::I32Popcnt, Op::I32Add,
+            Op::I32Sub, Op::I32Mul, Op::I32DivSigned, Op::I32DivUnsigned, Op::I32RemSigned,
+            Op::I32RemUnsigned, Op::I32And, Op::I32Or, Op::I32Xor, Op::I32Shl,
+            Op::I32ShrSigned, Op::I32ShrUnsigned, Op::I32Rotl, Op::I32Rotr, Op::I64Clz,
+            Op::I64Ctz, Op::I64Popcnt, Op::I64Add, Op::I64Sub, Op::I64Mul, Op::I64DivSigned,
+            Op::I64DivUnsigned, Op::I64RemSigned, Op::I64RemUnsigned, Op::I64And, Op::I64Or,
+            Op::I64Xor, Op::I64Shl, Op::I64ShrSigned, Op::I64ShrUnsigned, Op::I64Rotl,
+            Op::I64Rotr, Op::F32Abs, Op::F32Neg, Op::F32Ceil, Op::F32Floor, Op::F32Trunc,
+            Op::F32Nearest, Op::F32Sqrt, Op::F32Add, Op::F32Sub, Op::F32Mul, Op::F32Div,
+            Op::F32Min, Op::F32Max, Op::F32Copysign, Op::F64Abs, Op::F64Neg, Op::F64Ceil,
+            Op::F64Floor, Op::F64Trunc, Op::F64Nearest, Op::F64Sqrt, Op::F64Add, Op::F64Sub,
+            Op::F64Mul, Op::F64Div, Op::F64Min, Op::F64Max, Op::F64Copysign, Op::I32WrapI64,
+            Op::I32TruncSignedF32, Op::I32TruncUnsignedF32, Op::I32TruncSignedF64,
+            Op::I32TruncUnsignedF64, Op::I64ExtendSignedI32, Op::I64ExtendUnsignedI32,
+            Op::I64TruncSignedF32, Op::I64TruncUnsignedF32, Op::I64TruncSignedF64,
+            Op::I64TruncUnsignedF64, Op::F32ConvertSignedI32, Op::F32ConvertUnsignedI32,
+            Op::F32ConvertSignedI64, Op::F32ConvertUnsignedI64, Op::F32DemoteF64,
+            Op::F64ConvertSignedI32, Op::F64ConvertUnsignedI32, Op::F64ConvertSignedI64,
+            Op::F64ConvertUnsignedI64, Op::F64PromoteF32, Op::I32ReinterpretF32,
+            Op::I64ReinterpretF64, Op::F32ReinterpretI32, Op::F64ReinterpretI64,
+            Op::I32Extend8Signed, Op::I32Extend16Signed, Op::I64Extend8Signed,
+            Op::I64Extend16Signed, Op::I64Extend32Signed,
+        ];
+
+        let floats = [0.0, -0.0, 1e-45, 3.4028235e38, f32::NEG_INFINITY,
+                      f32::from_bits(0x7fc0_0000), f32::from_bits(0xffa0_0001)];
+        for f in floats.iter() {
+            ops.push(Op::F32Const(*f));
+        }
+        let doubles = [5e-324, 0.1, 1e300, f64::INFINITY,
+                       f64::from_bits(0xfff8_0000_0000_0000), f64::from_bits(0x7ff0_0000_0000_0001)];
+        for f in doubles.iter() {
+            ops.push(Op::F64Const(*f));
+        }
+
+        let module = Module::from_sections(vec![
+            Section::Type(vec![
+                FunctionType { params: vec![], results: vec![] },
+                FunctionType { params: vec![ValueType::F32], results: vec![ValueType::I32, ValueType::I64] },
+            ]),
+            Section::Function(vec![0]),
+            Section::Code(vec![FunctionBody { locals: vec![], ops, offsets: vec![] }]),
+        ]);
+        round_trip(&module);
+    }
+
+    #[test]
+    fn test_unbalanced_body() {
+        // One function whose body is five `end`s.
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            0x0a, 0x08, 0x01, 0x06, 0x00, 0x0b, 0x0b, 0x0b, 0x0b, 0x0b,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        assert_eq!(print_wat(&module), r#"(module
+  (type (;0;) (func))
+  (func (;0;) (type 0)
+    end
+    end
+    end
+    end))
+"#);
+    }
+
+    #[test]
+    fn test_names() {
+        // A "name" section naming function 0 `$main` and function 1 `bad name`.
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x03, 0x03, 0x02, 0x00, 0x00,
+            0x0a, 0x09, 0x02, 0x04, 0x00, 0x10, 0x01, 0x0b, 0x02, 0x00, 0x0b,
+            0x00, 0x18, 0x04, 0x6e, 0x61, 0x6d, 0x65,
+            0x01, 0x11, 0x02, 0x00, 0x04, 0x6d, 0x61, 0x69, 0x6e,
+            0x01, 0x08, 0x62, 0x61, 0x64, 0x20, 0x6e, 0x61, 0x6d, 0x65,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        let text = print_wat(&module);
+
+        assert!(text.contains("(func $main (;0;) (type 0)\n    call 1)"));
+        assert!(text.contains("(func (;1;) (type 0))"));
+    }
+
+    #[test]
+    fn test_local_names() {
+        let plain = parse_wat("(module (func (param i32 i32) (local i32)
+                                 (local.set 2 (local.get 0))))").unwrap();
+        let mut bytes = plain.clone();
+
+        // module `dsp`; function 0: local 0 `in`, local 2 `acc`
+        let mut payload = vec![0x00, 0x04];
+        codec::write_utf8(&mut payload, "dsp");
+        let mut locals = vec![0x01, 0x00, 0x02];
+        codec::write_varu32(&mut locals, 0);
+        codec::write_utf8(&mut locals, "in");
+        codec::write_varu32(&mut locals, 2);
+        codec::write_utf8(&mut locals, "acc");
+        payload.push(0x02);
+        codec::write_bytes(&mut payload, &locals);
+
+        let mut section = vec![];
+        codec::write_utf8(&mut section, "name");
+        section.extend_from_slice(&payload);
+        bytes.push(0x00);
+        codec::write_bytes(&mut bytes, &section);
+
+        let module = Module::decode_from(&bytes).unwrap();
+        let text = print_wat(&module);
+        assert_eq!(text, r#"(module $dsp
+  (type (;0;) (func (param i32 i32)))
+  (func (;0;) (type 0) (param $in i32) (param i32)
+    (local $acc i32)
+    local.get $in
+    local.set $acc))
+"#);
+        assert_eq!(parse_wat(&text), Ok(plain));
+    }
+}
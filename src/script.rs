@@ -0,0 +1,453 @@
+//! Runner for `.wast` spec test scripts.
+
+use sexpr::{self, Sexpr};
+use wat;
+use section::{Module, FunctionType, ImportKind};
+use instance::Instance;
+use linker::Linker;
+use value::Value;
+use ty::ValueType;
+use error::{Error, InstantiationError, RuntimeError, SyntaxError, Trap};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Pass,
+    Fail(String),
+    /// The command uses a feature the runner or interpreter lacks.
+    Skip(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub line: usize,
+    pub status: Status,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.status == Status::Pass).count()
+    }
+
+    pub fn failures(&self) -> Vec<&Outcome> {
+        self.outcomes.iter()
+            .filter(|o| matches!(o.status, Status::Fail(_)))
+            .collect()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.outcomes.iter()
+            .filter(|o| matches!(o.status, Status::Skip(_)))
+            .count()
+    }
+}
+
+enum Expected {
+    Value(Value),
+    /// A NaN with the canonical payload, of the given type if known.
+    CanonicalNan(Option<ValueType>),
+    /// A NaN with the quiet bit set, of the given type if known.
+    ArithmeticNan(Option<ValueType>),
+}
+
+impl Expected {
+    fn matches(&self, v: &Value) -> bool {
+        // Floats are compared by bit pattern, so that -0 and NaN
+        // payloads are checked exactly.
+        let (ty, bits, quiet, canonical) = match *v {
+            Value::F32(f) => (ValueType::F32, f.to_bits() as u64 & 0x7fff_ffff,
+                              0x7fc0_0000, 0x7fc0_0000),
+            Value::F64(f) => (ValueType::F64, f.to_bits() & 0x7fff_ffff_ffff_ffff,
+                              0x7ff8_0000_0000_0000, 0x7ff8_0000_0000_0000),
+            _ => return match *self {
+                Expected::Value(ref e) => e == v,
+                _ => false,
+            },
+        };
+
+        match *self {
+            Expected::Value(Value::F32(e)) => *v == Value::F32(e) || match *v {
+                Value::F32(f) => f.to_bits() == e.to_bits(),
+                _ => false,
+            },
+            Expected::Value(Value::F64(e)) => *v == Value::F64(e) || match *v {
+                Value::F64(f) => f.to_bits() == e.to_bits(),
+                _ => false,
+            },
+            Expected::Value(_) => false,
+            Expected::CanonicalNan(t) => t.is_none_or(|t| t == ty) && bits == canonical,
+            Expected::ArithmeticNan(t) => t.is_none_or(|t| t == ty) && bits & quiet == quiet,
+        }
+    }
+}
+
+/// Parse `(i32.const 1)` and friends.
+fn constant(expr: &Sexpr) -> Option<Value> {
+    let items = expr.list()?;
+    if items.len() != 2 {
+        return None;
+    }
+
+    let lit = items[1].atom()?;
+    match items[0].atom()? {
+        "i32.const" => sexpr::parse_i32(lit).map(Value::I32),
+        "i64.const" => sexpr::parse_i64(lit).map(Value::I64),
+        "f32.const" => sexpr::parse_f32(lit).map(|bits| Value::F32(f32::from_bits(bits))),
+        "f64.const" => sexpr::parse_f64(lit).map(|bits| Value::F64(f64::from_bits(bits))),
+        _ => None,
+    }
+}
+
+fn expected(expr: &Sexpr) -> Option<Expected> {
+    let items = expr.list()?;
+    let ty = match items.first()?.atom()? {
+        "f32.const" => ValueType::F32,
+        "f64.const" => ValueType::F64,
+        _ => return constant(expr).map(Expected::Value),
+    };
+
+    match items.get(1)?.atom()? {
+        "nan:canonical" => Some(Expected::CanonicalNan(Some(ty))),
+        "nan:arithmetic" => Some(Expected::ArithmeticNan(Some(ty))),
+        _ => constant(expr).map(Expected::Value),
+    }
+}
+
+/// Host functions provided to spec tests under the `spectest` module.
+fn spectest() -> Linker {
+    let mut linker = Linker::new();
+    let prints = [
+        ("print", vec![]),
+        ("print_i32", vec![ValueType::I32]),
+        ("print_i64", vec![ValueType::I64]),
+        ("print_f32", vec![ValueType::F32]),
+        ("print_f64", vec![ValueType::F64]),
+        ("print_i32_f32", vec![ValueType::I32, ValueType::F32]),
+        ("print_f64_f64", vec![ValueType::F64, ValueType::F64]),
+    ];
+
+    for &(name, ref params) in &prints {
+        linker.func("spectest", name, FunctionType { params: params.clone(), results: vec![] },
+                    |_, _| Ok(vec![]));
+    }
+
+    linker.global("spectest", "global_i32", Value::I32(666), false);
+    linker.global("spectest", "global_i64", Value::I64(666), false);
+    linker.global("spectest", "global_f32", Value::F32(666.6), false);
+    linker.global("spectest", "global_f64", Value::F64(666.6), false);
+    // The linker cannot define tables or memories, so modules importing
+    // `spectest.table` or `spectest.memory` are skipped by `load`.
+    linker
+}
+
+/// Which `assert_*` command a load failure satisfies.
+fn failure_kind(e: &Error) -> Option<&'static str> {
+    match *e {
+        Error::Codec(_) | Error::Wat(_) => Some("assert_malformed"),
+        Error::Instantiation(InstantiationError::Invalid(_)) => Some("assert_invalid"),
+        Error::Instantiation(InstantiationError::UnknownImport(..)) |
+            Error::Instantiation(InstantiationError::IncompatibleImport(..)) |
+            Error::Instantiation(InstantiationError::ElementSegmentOutOfBounds) |
+            Error::Instantiation(InstantiationError::DataSegmentOutOfBounds) => {
+                Some("assert_unlinkable")
+            }
+        Error::Instantiation(InstantiationError::Start(_)) => Some("assert_uninstantiable"),
+        _ => None,
+    }
+}
+
+/// An instance, shared with the linker once registered.
+type Shared = Rc<RefCell<Instance>>;
+
+struct Runner {
+    linker: Linker,
+    /// Instantiated modules, most recent last.  Modules the runner
+    /// could not load are recorded as `None`.
+    instances: Vec<(Option<String>, Option<Shared>)>,
+}
+
+impl Runner {
+    fn load(&self, items: &[Sexpr]) -> Result<Result<Instance, Error>, Status> {
+        let bytes = match wat::module(items) {
+            Ok(bytes) => bytes,
+            Err(e) => return Ok(Err(e.into())),
+        };
+        let module = match Module::decode_from(&bytes) {
+            Ok(module) => module,
+            Err(e) => return Ok(Err(e.into())),
+        };
+
+        let shared = module.imports().iter()
+            .any(|import| matches!(import.kind, ImportKind::Table(_) | ImportKind::Memory(_)));
+        if shared {
+            return Err(Status::Skip("table or memory import"));
+        }
+
+        Ok(self.linker.instantiate(&module).map_err(Error::from))
+    }
+
+    fn module(&mut self, items: &[Sexpr]) -> Status {
+        let name = items.get(1)
+            .and_then(Sexpr::atom)
+            .filter(|a| a.starts_with('$'))
+            .map(str::to_string);
+
+        let status = match self.load(items) {
+            Ok(Ok(inst)) => {
+                self.instances.push((name, Some(Rc::new(RefCell::new(inst)))));
+                return Status::Pass;
+            }
+            Ok(Err(e)) => Status::Fail(format!("module failed to load: {:?}", e)),
+            Err(status) => status,
+        };
+
+        // Later commands refer to this module, so must not run against
+        // an earlier one.
+        self.instances.push((name, None));
+        status
+    }
+
+    /// Check that a module fails to load in the way `command` expects.
+    fn assert_bad_module(&self, command: &str, module: &Sexpr) -> Status {
+        let items = match module.list() {
+            Some(items) => items,
+            None => return Status::Fail("malformed command".to_string()),
+        };
+
+        match self.load(items) {
+            Ok(Ok(_)) => Status::Fail("module unexpectedly loaded".to_string()),
+            Ok(Err(ref e)) if failure_kind(e) == Some(command) => Status::Pass,
+            Ok(Err(e)) => Status::Fail(format!("wrong error for {}: {:?}", command, e)),
+            Err(status) => status,
+        }
+    }
+
+    /// The instance called `name`, or the most recent one.
+    fn instance(&self, name: Option<&str>) -> Result<Shared, Status> {
+        let inst = match name {
+            Some(name) => self.instances.iter()
+                .rev()
+                .find(|(n, _)| n.as_ref().is_some_and(|n| n == name)),
+            None => self.instances.last(),
+        };
+        match inst {
+            Some((_, Some(inst))) => Ok(inst.clone()),
+            Some(_) => Err(Status::Skip("module not loaded")),
+            None => Err(Status::Fail("no module".to_string())),
+        }
+    }
+
+    fn action(&mut self, action: &Sexpr) -> Result<Result<Vec<Value>, Error>, Status> {
+        let items = action.list()
+            .ok_or_else(|| Status::Fail("malformed action".to_string()))?;
+
+        let mut rest = &items[1..];
+        let name = match rest.first().and_then(Sexpr::atom) {
+            Some(name) if name.starts_with('$') => {
+                rest = &rest[1..];
+                Some(name)
+            }
+            _ => None,
+        };
+        let inst = self.instance(name)?;
+
+        let field = rest.first()
+            .and_then(Sexpr::string)
+            .and_then(|s| String::from_utf8(s.to_vec()).ok())
+            .ok_or_else(|| Status::Fail("malformed action".to_string()))?;
+
+        match items[0].atom() {
+            Some("invoke") => {
+                let mut args = vec![];
+                for arg in &rest[1..] {
+                    args.push(constant(arg)
+                              .ok_or_else(|| Status::Fail("malformed argument".to_string()))?);
+                }
+                Ok(inst.borrow_mut().invoke(&field, &args))
+            }
+            Some("get") => Ok(inst.borrow().global(&field)
+                              .map(|v| vec![v])
+                              .ok_or(Error::NoSuchExport)),
+            _ => Err(Status::Fail("unknown action".to_string())),
+        }
+    }
+
+    /// Make an instance's exports importable under a module name.
+    fn register(&mut self, items: &[Sexpr]) -> Status {
+        let module = match items.get(1).and_then(Sexpr::string) {
+            Some(module) => String::from_utf8_lossy(module).into_owned(),
+            None => return Status::Fail("malformed command".to_string()),
+        };
+
+        match self.instance(items.get(2).and_then(Sexpr::atom)) {
+            Ok(inst) => {
+                self.linker.instance(&module, inst);
+                Status::Pass
+            }
+            Err(status) => status,
+        }
+    }
+
+    fn assert_return(&mut self, action: &Sexpr, expected: &[Expected]) -> Status {
+        match self.action(action) {
+            Ok(Ok(results)) => {
+                if results.len() == expected.len() &&
+                    expected.iter().zip(&results).all(|(e, v)| e.matches(v)) {
+                    Status::Pass
+                } else {
+                    Status::Fail(format!("unexpected result {:?}", results))
+                }
+            }
+            Ok(Err(e)) => Status::Fail(format!("unexpected error {:?}", e)),
+            Err(status) => status,
+        }
+    }
+
+    fn command(&mut self, cmd: &Sexpr) -> Status {
+        let items = match cmd.list() {
+            Some(items) if !items.is_empty() => items,
+            _ => return Status::Fail("malformed command".to_string()),
+        };
+
+        if cmd.head().is_some_and(|h| h.starts_with("assert_")) && items.len() < 2 {
+            return Status::Fail("malformed command".to_string());
+        }
+
+        match cmd.head() {
+            Some("module") => self.module(items),
+            Some("invoke") | Some("get") => {
+                match self.action(cmd) {
+                    Ok(Ok(_)) => Status::Pass,
+                    Ok(Err(e)) => Status::Fail(format!("unexpected error {:?}", e)),
+                    Err(status) => status,
+                }
+            }
+            Some("assert_return") => {
+                let mut expect = vec![];
+                for e in &items[2..] {
+                    match expected(e) {
+                        Some(e) => expect.push(e),
+                        None => return Status::Fail("malformed expected value".to_string()),
+                    }
+                }
+                self.assert_return(&items[1], &expect)
+            }
+            Some("assert_return_canonical_nan") => {
+                self.assert_return(&items[1], &[Expected::CanonicalNan(None)])
+            }
+            Some("assert_return_arithmetic_nan") => {
+                self.assert_return(&items[1], &[Expected::ArithmeticNan(None)])
+            }
+            Some("assert_trap") if items[1].head() == Some("module") => {
+                self.assert_bad_module("assert_uninstantiable", &items[1])
+            }
+            Some("assert_trap") => {
+                match self.action(&items[1]) {
+                    Ok(Err(Error::Trap(_))) => Status::Pass,
+                    Ok(r) => Status::Fail(format!("expected trap, got {:?}", r)),
+                    Err(status) => status,
+                }
+            }
+            Some("assert_exhaustion") => {
+                match self.action(&items[1]) {
+                    Ok(Err(Error::Trap(Trap { error: RuntimeError::StackOverflow, .. }))) => Status::Pass,
+                    Ok(r) => Status::Fail(format!("expected exhaustion, got {:?}", r)),
+                    Err(status) => status,
+                }
+            }
+            Some(command @ "assert_invalid") |
+                Some(command @ "assert_malformed") |
+                Some(command @ "assert_unlinkable") |
+                Some(command @ "assert_uninstantiable") => self.assert_bad_module(command, &items[1]),
+            Some("register") => self.register(items),
+            _ => Status::Skip("unknown command"),
+        }
+    }
+}
+
+/// Run every command in the script `text`.
+pub fn run(text: &str) -> Result<Report, SyntaxError> {
+    let mut runner = Runner { linker: spectest(), instances: vec![] };
+    let mut outcomes = vec![];
+
+    for (line, cmd) in sexpr::parse(text)? {
+        let status = runner.command(&cmd);
+        outcomes.push(Outcome { line, status });
+    }
+
+    Ok(Report { outcomes })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_binary_script() {
+        let report = run(r#"
+            (module binary
+              "\00asm\01\00\00\00"
+              "\01\06\01\60\01\7f\01\7f"                    ;; (i32) -> i32
+              "\03\02\01\00"
+              "\07\05\01\01\66\00\00"                       ;; export "f"
+              "\0a\0a\01\08\00\20\00\41\01\6a\0f\0b"        ;; get_local 0 + 1
+            )
+            (assert_return (invoke "f" (i32.const 41)) (i32.const 42))
+            (assert_return (invoke "f" (i32.const 41)) (i32.const 43))
+            (assert_trap (invoke "g") "unknown")
+            (assert_malformed (module binary "\00asm\02\00\00\00") "unknown binary version")
+            (module $text (func (export "f") (result i32) (i32.const 7)))
+            (assert_return (invoke $text "f") (i32.const 7))
+            (module quote "(func (export \"g\"))")
+            (assert_return (invoke "g"))
+            (assert_return (invoke $text "f") (i32.const 7))
+            (assert_malformed (module quote "(func (i32.frob))") "unknown operator")
+        "#).unwrap();
+
+        let statuses: Vec<_> = report.outcomes.iter().map(|o| &o.status).collect();
+        assert_eq!(statuses[0], &Status::Pass);
+        assert_eq!(statuses[1], &Status::Pass);
+        assert!(matches!(*statuses[2], Status::Fail(_)));
+        assert!(matches!(*statuses[3], Status::Fail(_)));
+        assert_eq!(statuses[4], &Status::Pass);
+        for status in &statuses[5..] {
+            assert_eq!(*status, &Status::Pass);
+        }
+        assert_eq!(report.outcomes[1].line, 9);
+    }
+
+    #[test]
+    fn test_register() {
+        let report = run(r#"
+            (module $a
+              (global (export "g") i32 (i32.const 5))
+              (func (export "f") (param i32) (result i32) (i32.add (local.get 0) (global.get 0))))
+            (register "a" $a)
+            (module
+              (import "a" "f" (func $f (param i32) (result i32)))
+              (import "a" "g" (global i32))
+              (import "spectest" "global_i32" (global i32))
+              (func (export "h") (result i32) (call $f (i32.add (global.get 0) (global.get 1)))))
+            (assert_return (invoke "h") (i32.const 676))
+            (assert_unlinkable (module (import "a" "h" (func))) "unknown import")
+            (assert_unlinkable (module (import "a" "f" (func))) "incompatible import type")
+            (assert_invalid (module (func (result i32))) "type mismatch")
+            (assert_malformed (module (func (result i32))) "unexpected")
+            (module (import "spectest" "memory" (memory 1)))
+        "#).unwrap();
+
+        let statuses: Vec<_> = report.outcomes.iter().map(|o| &o.status).collect();
+        for status in &statuses[..7] {
+            assert_eq!(*status, &Status::Pass);
+        }
+        assert!(matches!(*statuses[7], Status::Fail(_)));
+        assert_eq!(statuses[8], &Status::Skip("table or memory import"));
+    }
+}
@@ -1,13 +1,15 @@
 use function::FunctionBody;
 use expr::InitExpr;
 use ty::{ValueType, ElementType};
-use error::CodecError;
+use error::{CodecError, ValidationError};
 use codec;
+use validate;
+use names::Names;
 
 use untrusted;
 
-#[derive(Debug)]
-enum ResizableLimits {
+#[derive(Debug, Clone)]
+pub enum ResizableLimits {
     Initial(u32),
     InitialMax(u32, u32),
 }
@@ -23,23 +25,51 @@ impl ResizableLimits {
             Ok(ResizableLimits::Initial(initial))
         }
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            ResizableLimits::Initial(initial) => {
+                codec::write_u8(out, 0);
+                codec::write_varu32(out, initial);
+            }
+            ResizableLimits::InitialMax(initial, max) => {
+                codec::write_u8(out, 1);
+                codec::write_varu32(out, initial);
+                codec::write_varu32(out, max);
+            }
+        }
+    }
+
+    pub fn initial(&self) -> u32 {
+        match *self {
+            ResizableLimits::Initial(initial) |
+                ResizableLimits::InitialMax(initial, _) => initial,
+        }
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        match *self {
+            ResizableLimits::Initial(_) => None,
+            ResizableLimits::InitialMax(_, max) => Some(max),
+        }
+    }
 }
 
 #[derive(Debug)]
-struct CustomSection<'a> {
-    name: &'a str,
-    payload: &'a [u8],
+pub struct CustomSection<'a> {
+    pub name: &'a str,
+    pub payload: &'a [u8],
 }
 
-#[derive(Debug)]
-struct FunctionType {
-    params: Vec<ValueType>,
-    ret: Option<ValueType>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionType {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
 }
 
 impl FunctionType {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<FunctionType, CodecError> {
-        let form = codec::read_varu7(rd)?;
+        let _form = codec::read_varu7(rd)?;
 
         let param_count = codec::read_varu32(rd)?;
         let mut params = vec![];
@@ -47,21 +77,34 @@ impl FunctionType {
             params.push(ValueType::decode(rd)?);
         }
 
-        let return_count = codec::read_varu1(rd)?;
-        let ret = if return_count == 1 {
-            Some(ValueType::decode(rd)?)
-        } else {
-            None
-        };
+        let result_count = codec::read_varu32(rd)?;
+        let mut results = vec![];
+        for _ in 0..result_count {
+            results.push(ValueType::decode(rd)?);
+        }
 
-        Ok(FunctionType { params, ret })
+        Ok(FunctionType { params, results })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_u8(out, 0x60);
+
+        codec::write_varu32(out, self.params.len() as u32);
+        for param in &self.params {
+            param.encode(out);
+        }
+
+        codec::write_varu32(out, self.results.len() as u32);
+        for result in &self.results {
+            result.encode(out);
+        }
     }
 }
 
-#[derive(Debug)]
-struct TableType {
-    element_ty: ElementType,
-    limits: ResizableLimits,
+#[derive(Debug, Clone)]
+pub struct TableType {
+    pub element_ty: ElementType,
+    pub limits: ResizableLimits,
 }
 
 impl TableType {
@@ -70,11 +113,16 @@ impl TableType {
         let limits = ResizableLimits::decode(rd)?;
         Ok(TableType { element_ty, limits })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.element_ty.encode(out);
+        self.limits.encode(out);
+    }
 }
 
-#[derive(Debug)]
-struct MemoryType {
-    limits: ResizableLimits,
+#[derive(Debug, Clone)]
+pub struct MemoryType {
+    pub limits: ResizableLimits,
 }
 
 impl MemoryType {
@@ -82,24 +130,33 @@ impl MemoryType {
         ResizableLimits::decode(rd)
             .map(|limits| MemoryType { limits })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.limits.encode(out);
+    }
 }
 
-#[derive(Debug)]
-struct GlobalType {
-    content: ValueType,
-    mutable: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalType {
+    pub content: ValueType,
+    pub mutable: bool,
 }
 
 impl GlobalType {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<GlobalType, CodecError> {
         let content = ValueType::decode(rd)?;
-        let mutable = if codec::read_varu1(rd)? == 1 { true } else { false };
+        let mutable = codec::read_varu1(rd)? == 1;
         Ok(GlobalType { content, mutable })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.content.encode(out);
+        codec::write_u8(out, self.mutable as u8);
+    }
 }
 
 #[derive(Debug)]
-enum ImportKind {
+pub enum ImportKind {
     Function(u32),
     Table(TableType),
     Memory(MemoryType),
@@ -125,13 +182,34 @@ impl ImportKind {
             }
         }
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            ImportKind::Function(index) => {
+                ExternalKind::Function.encode(out);
+                codec::write_varu32(out, index);
+            }
+            ImportKind::Table(ref ty) => {
+                ExternalKind::Table.encode(out);
+                ty.encode(out);
+            }
+            ImportKind::Memory(ref ty) => {
+                ExternalKind::Memory.encode(out);
+                ty.encode(out);
+            }
+            ImportKind::Global(ref ty) => {
+                ExternalKind::Global.encode(out);
+                ty.encode(out);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-struct ImportEntry<'a> {
-    module: &'a str,
-    field: &'a str,
-    kind: ImportKind,
+pub struct ImportEntry<'a> {
+    pub module: &'a str,
+    pub field: &'a str,
+    pub kind: ImportKind,
 }
 
 impl<'a> ImportEntry<'a> {
@@ -144,12 +222,18 @@ impl<'a> ImportEntry<'a> {
 
         Ok(ImportEntry { module, field, kind })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_utf8(out, self.module);
+        codec::write_utf8(out, self.field);
+        self.kind.encode(out);
+    }
 }
 
 #[derive(Debug)]
-struct GlobalVariable {
-    ty: GlobalType,
-    init: InitExpr,
+pub struct GlobalVariable {
+    pub ty: GlobalType,
+    pub init: InitExpr,
 }
 
 impl GlobalVariable {
@@ -158,10 +242,15 @@ impl GlobalVariable {
         let init = InitExpr::decode(rd)?;
         Ok(GlobalVariable { ty, init })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.ty.encode(out);
+        self.init.encode(out);
+    }
 }
 
-#[derive(Debug)]
-enum ExternalKind {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExternalKind {
     Function,
     Table,
     Memory,
@@ -180,13 +269,23 @@ impl ExternalKind {
             _ => Err(CodecError::BadType),
         }
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let k = match *self {
+            ExternalKind::Function => 0,
+            ExternalKind::Table => 1,
+            ExternalKind::Memory => 2,
+            ExternalKind::Global => 3,
+        };
+        codec::write_u8(out, k);
+    }
 }
 
 #[derive(Debug)]
-struct ExportEntry<'a> {
-    field: &'a str,
-    kind: ExternalKind,
-    index: u32,
+pub struct ExportEntry<'a> {
+    pub field: &'a str,
+    pub kind: ExternalKind,
+    pub index: u32,
 }
 
 impl<'a> ExportEntry<'a> {
@@ -198,13 +297,19 @@ impl<'a> ExportEntry<'a> {
 
         Ok(ExportEntry { field, kind, index })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_utf8(out, self.field);
+        self.kind.encode(out);
+        codec::write_varu32(out, self.index);
+    }
 }
 
 #[derive(Debug)]
-struct ElementSegment {
-    index: u32,
-    offset: InitExpr,
-    elems: Vec<u32>,
+pub struct ElementSegment {
+    pub index: u32,
+    pub offset: InitExpr,
+    pub elems: Vec<u32>,
 }
 
 impl ElementSegment {
@@ -219,13 +324,23 @@ impl ElementSegment {
         }
         Ok(ElementSegment { index, offset, elems })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_varu32(out, self.index);
+        self.offset.encode(out);
+
+        codec::write_varu32(out, self.elems.len() as u32);
+        for elem in &self.elems {
+            codec::write_varu32(out, *elem);
+        }
+    }
 }
 
 #[derive(Debug)]
-struct DataSegment<'a> {
-    index: u32,
-    init: InitExpr,
-    data: &'a [u8],
+pub struct DataSegment<'a> {
+    pub index: u32,
+    pub init: InitExpr,
+    pub data: &'a [u8],
 }
 
 impl<'a> DataSegment<'a> {
@@ -240,10 +355,16 @@ impl<'a> DataSegment<'a> {
 
         Ok(DataSegment { index, init, data })
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_varu32(out, self.index);
+        self.init.encode(out);
+        codec::write_bytes(out, self.data);
+    }
 }
 
 #[derive(Debug)]
-enum Section<'a> {
+pub enum Section<'a> {
     Custom(CustomSection<'a>),
     Type(Vec<FunctionType>),
     Import(Vec<ImportEntry<'a>>),
@@ -271,6 +392,16 @@ const SECTIONID_ELEMENT: u8 = 9;
 const SECTIONID_CODE: u8 = 10;
 const SECTIONID_DATA: u8 = 11;
 
+/// Write a count followed by each of `items`.
+fn encode_items<T, F>(out: &mut Vec<u8>, items: &[T], encode: F)
+    where F: Fn(&T, &mut Vec<u8>)
+{
+    codec::write_varu32(out, items.len() as u32);
+    for item in items {
+        encode(item, out);
+    }
+}
+
 impl<'a> Section<'a> {
     pub fn decode(rd: &mut untrusted::Reader<'a>) -> Result<Section<'a>, CodecError> {
         let id = codec::read_varu7(rd)?;
@@ -356,10 +487,15 @@ impl<'a> Section<'a> {
                 Ok(Section::Element(elements))
             }
             SECTIONID_CODE => {
-                let count = codec::read_varu32(&mut prd)?;
+                let (count, mut pos) = codec::measured(&mut prd, codec::read_varu32)?;
                 let mut funcs = vec![];
                 for _  in 0..count {
-                    funcs.push(FunctionBody::decode(&mut prd)?);
+                    let (mut func, len) = codec::measured(&mut prd, FunctionBody::decode)?;
+                    for offset in &mut func.offsets {
+                        *offset += pos as u32;
+                    }
+                    pos += len;
+                    funcs.push(func);
                 }
                 Ok(Section::Code(funcs))
             }
@@ -371,13 +507,89 @@ impl<'a> Section<'a> {
                 }
                 Ok(Section::Data(datas))
             }
-            _ => {
-                println!("sec type {:?} unimpl", id);
-                Err(CodecError::Unimpl)
-            }
+            _ => Err(CodecError::Unimpl),
         };
         section
     }
+
+    /// The section's id in the binary format.
+    pub fn id(&self) -> u8 {
+        match *self {
+            Section::Custom(_) => SECTIONID_CUSTOM,
+            Section::Type(_) => SECTIONID_TYPE,
+            Section::Import(_) => SECTIONID_IMPORT,
+            Section::Function(_) => SECTIONID_FUNCTION,
+            Section::Table(_) => SECTIONID_TABLE,
+            Section::Memory(_) => SECTIONID_MEMORY,
+            Section::Global(_) => SECTIONID_GLOBAL,
+            Section::Export(_) => SECTIONID_EXPORT,
+            Section::Start(_) => SECTIONID_START,
+            Section::Element(_) => SECTIONID_ELEMENT,
+            Section::Code(_) => SECTIONID_CODE,
+            Section::Data(_) => SECTIONID_DATA,
+        }
+    }
+
+    /// Encode the section: its id, then its size-prefixed payload.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut payload = vec![];
+
+        match *self {
+            Section::Custom(ref custom) => {
+                codec::write_utf8(&mut payload, custom.name);
+                payload.extend_from_slice(custom.payload);
+            }
+            Section::Type(ref func_types) => {
+                encode_items(&mut payload, func_types, FunctionType::encode);
+            }
+            Section::Import(ref imports) => {
+                encode_items(&mut payload, imports, ImportEntry::encode);
+            }
+            Section::Function(ref funcs) => {
+                encode_items(&mut payload, funcs, |f, out| codec::write_varu32(out, *f));
+            }
+            Section::Table(ref tables) => {
+                encode_items(&mut payload, tables, TableType::encode);
+            }
+            Section::Memory(ref memories) => {
+                encode_items(&mut payload, memories, MemoryType::encode);
+            }
+            Section::Global(ref globals) => {
+                encode_items(&mut payload, globals, GlobalVariable::encode);
+            }
+            Section::Export(ref exports) => {
+                encode_items(&mut payload, exports, ExportEntry::encode);
+            }
+            Section::Start(index) => {
+                codec::write_varu32(&mut payload, index);
+            }
+            Section::Element(ref elements) => {
+                encode_items(&mut payload, elements, ElementSegment::encode);
+            }
+            Section::Code(ref funcs) => {
+                encode_items(&mut payload, funcs, FunctionBody::encode);
+            }
+            Section::Data(ref datas) => {
+                encode_items(&mut payload, datas, DataSegment::encode);
+            }
+        }
+
+        codec::write_u8(out, self.id());
+        codec::write_bytes(out, &payload);
+    }
+}
+
+macro_rules! section_accessor {
+    ($name:ident, $variant:ident, $ty:ty) => (
+        pub fn $name(&self) -> &[$ty] {
+            for section in &self.sections {
+                if let Section::$variant(ref items) = *section {
+                    return items;
+                }
+            }
+            &[]
+        }
+    );
 }
 
 #[derive(Debug)]
@@ -396,9 +608,21 @@ impl<'a> Module<'a> {
         }
 
         let mut sections = vec![];
+        let mut last_id = SECTIONID_CUSTOM;
 
         while !rd.at_end() {
-            sections.push(Section::decode(rd)?);
+            let section = Section::decode(rd)?;
+
+            // Custom sections may go anywhere; the others appear at most
+            // once, in id order.
+            let id = section.id();
+            if id != SECTIONID_CUSTOM {
+                if id <= last_id {
+                    return Err(CodecError::BadSectionOrder);
+                }
+                last_id = id;
+            }
+            sections.push(section);
         }
 
         Ok(Module { sections })
@@ -411,4 +635,71 @@ impl<'a> Module<'a> {
             Module::decode
         )
     }
+
+    /// A module made of `sections`, in order.
+    pub(crate) fn from_sections(sections: Vec<Section<'a>>) -> Module<'a> {
+        Module { sections }
+    }
+
+    /// Serialize the module back to the binary format.  Sections are
+    /// written in their original order, and decoding then encoding a
+    /// module that uses minimal LEB128 encodings gives the same bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        codec::write_u32(&mut out, 0x6d736100);
+        codec::write_u32(&mut out, 1);
+
+        for section in &self.sections {
+            section.encode(&mut out);
+        }
+        out
+    }
+
+    section_accessor!(types, Type, FunctionType);
+    section_accessor!(imports, Import, ImportEntry<'a>);
+    section_accessor!(functions, Function, u32);
+    section_accessor!(tables, Table, TableType);
+    section_accessor!(memories, Memory, MemoryType);
+    section_accessor!(globals, Global, GlobalVariable);
+    section_accessor!(exports, Export, ExportEntry<'a>);
+    section_accessor!(elements, Element, ElementSegment);
+    section_accessor!(code, Code, FunctionBody);
+    section_accessor!(data, Data, DataSegment<'a>);
+
+    /// The payload of the first custom section called `name`.
+    pub fn custom(&self, name: &str) -> Option<&'a [u8]> {
+        for section in &self.sections {
+            if let Section::Custom(ref custom) = *section {
+                if custom.name == name {
+                    return Some(custom.payload);
+                }
+            }
+        }
+        None
+    }
+
+    /// Debug names from the "name" section.  Returns `None` both when
+    /// there is no "name" section and when it fails to decode: custom
+    /// sections are advisory, so a malformed one is ignored rather than
+    /// reported as an error.
+    pub fn names(&self) -> Option<Names<'a>> {
+        self.custom("name")
+            .and_then(|payload| untrusted::Input::from(payload)
+                      .read_all(CodecError::TrailingData, Names::decode)
+                      .ok())
+    }
+
+    pub fn start(&self) -> Option<u32> {
+        for section in &self.sections {
+            if let Section::Start(index) = *section {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// Check the module is well-typed.  Instantiation does this too.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate::validate(self)
+    }
 }
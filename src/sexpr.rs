@@ -0,0 +1,545 @@
+//! S-expression reader for the WebAssembly text formats.
+//!
+//! The `atoms` crate cannot read these: it parses a single expression
+//! where a script holds many, knows only `;` line comments and not
+//! `(; ... ;)` block comments, unescapes strings into UTF-8 text where
+//! `\hh` escapes must give arbitrary bytes, and turns numbers into
+//! `i64`/`f64`, losing hex floats and NaN payloads.
+
+use std::str;
+
+use error::SyntaxError;
+
+#[derive(Debug, PartialEq)]
+pub enum Sexpr {
+    List(Vec<Sexpr>),
+    /// A keyword, `$id`, number or other bare token.
+    Atom(String),
+    /// A string literal, with escapes already resolved.
+    Str(Vec<u8>),
+}
+
+impl Sexpr {
+    pub fn atom(&self) -> Option<&str> {
+        match *self {
+            Sexpr::Atom(ref a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self) -> Option<&[Sexpr]> {
+        match *self {
+            Sexpr::List(ref items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self) -> Option<&[u8]> {
+        match *self {
+            Sexpr::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The leading keyword of a list, eg. `module` in `(module ...)`.
+    pub fn head(&self) -> Option<&str> {
+        self.list()
+            .and_then(|items| items.first())
+            .and_then(Sexpr::atom)
+    }
+}
+
+struct Reader<'a> {
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+pub(crate) fn is_idchar(c: u8) -> bool {
+    matches!(c, b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' |
+             b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'/' |
+             b':' | b'<' | b'=' | b'>' | b'?' | b'@' | b'\\' | b'^' | b'_' | b'`' |
+             b'|' | b'~')
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.text.get(self.pos + offset).cloned()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek();
+        if c == Some(b'\n') {
+            self.line += 1;
+        }
+        self.pos += 1;
+        c
+    }
+
+    fn error<T>(&self) -> Result<T, SyntaxError> {
+        Err(SyntaxError { line: self.line })
+    }
+
+    /// Skip whitespace, line comments and (nested) block comments.
+    fn skip_space(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(b' '), _) | (Some(b'\t'), _) | (Some(b'\n'), _) | (Some(b'\r'), _) => {
+                    self.bump();
+                }
+                (Some(b';'), Some(b';')) => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.bump();
+                    }
+                }
+                (Some(b'('), Some(b';')) => {
+                    let mut depth = 0;
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some(b'('), Some(b';')) => {
+                                depth += 1;
+                                self.bump();
+                                self.bump();
+                            }
+                            (Some(b';'), Some(b')')) => {
+                                depth -= 1;
+                                self.bump();
+                                self.bump();
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            (Some(_), _) => {
+                                self.bump();
+                            }
+                            (None, _) => return self.error(),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, SyntaxError> {
+        let mut out = vec![];
+        self.bump();
+
+        loop {
+            match self.bump() {
+                Some(b'"') => return Ok(out),
+                Some(b'\\') => {
+                    match self.bump() {
+                        Some(b'n') => out.push(b'\n'),
+                        Some(b't') => out.push(b'\t'),
+                        Some(b'r') => out.push(b'\r'),
+                        Some(b'\\') => out.push(b'\\'),
+                        Some(b'\'') => out.push(b'\''),
+                        Some(b'"') => out.push(b'"'),
+                        Some(b'u') => {
+                            if self.bump() != Some(b'{') {
+                                return self.error();
+                            }
+                            let mut code = 0u32;
+                            loop {
+                                match self.bump() {
+                                    Some(b'}') => break,
+                                    Some(c) => match hex_digit(c) {
+                                        Some(d) if code < 0x11_0000 => {
+                                            code = code * 16 + d as u32
+                                        }
+                                        _ => return self.error(),
+                                    },
+                                    None => return self.error(),
+                                }
+                            }
+                            match ::std::char::from_u32(code) {
+                                Some(c) => {
+                                    let mut buf = [0u8; 4];
+                                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                                }
+                                None => return self.error(),
+                            }
+                        }
+                        Some(c) => {
+                            let hi = hex_digit(c);
+                            let lo = self.bump().and_then(hex_digit);
+                            match (hi, lo) {
+                                (Some(hi), Some(lo)) => out.push(hi << 4 | lo),
+                                _ => return self.error(),
+                            }
+                        }
+                        None => return self.error(),
+                    }
+                }
+                Some(b'\n') | None => return self.error(),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<String, SyntaxError> {
+        let start = self.pos;
+        while self.peek().is_some_and(is_idchar) {
+            self.bump();
+        }
+
+        if self.pos == start {
+            return self.error();
+        }
+
+        // idchars are all ASCII.
+        Ok(str::from_utf8(&self.text[start..self.pos]).unwrap().to_string())
+    }
+
+    fn expr(&mut self) -> Result<Sexpr, SyntaxError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.bump();
+                let mut items = vec![];
+                loop {
+                    self.skip_space()?;
+                    match self.peek() {
+                        Some(b')') => {
+                            self.bump();
+                            return Ok(Sexpr::List(items));
+                        }
+                        None => return self.error(),
+                        _ => items.push(self.expr()?),
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(Sexpr::Str),
+            _ => self.atom().map(Sexpr::Atom),
+        }
+    }
+}
+
+/// Read every top-level expression in `text`, with the line each
+/// starts on.
+pub fn parse(text: &str) -> Result<Vec<(usize, Sexpr)>, SyntaxError> {
+    let mut rd = Reader { text: text.as_bytes(), pos: 0, line: 1 };
+    let mut exprs = vec![];
+
+    loop {
+        rd.skip_space()?;
+        if rd.peek().is_none() {
+            return Ok(exprs);
+        }
+        let line = rd.line;
+        exprs.push((line, rd.expr()?));
+    }
+}
+
+/// Split an optional sign from a literal, returning whether it was `-`.
+fn sign(lit: &str) -> (bool, &str) {
+    if let Some(rest) = lit.strip_prefix('-') {
+        (true, rest)
+    } else if let Some(rest) = lit.strip_prefix('+') {
+        (false, rest)
+    } else {
+        (false, lit)
+    }
+}
+
+/// Whether `text` is a nonempty run of digits in `radix`, with single
+/// `_` separators allowed between digits.
+fn valid_digits(text: &str, radix: u32) -> bool {
+    !text.is_empty() &&
+        !text.starts_with('_') &&
+        !text.ends_with('_') &&
+        !text.contains("__") &&
+        text.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/// Parse unsigned digits in `radix`, failing on overflow.
+fn digits(text: &str, radix: u32) -> Option<u64> {
+    if !valid_digits(text, radix) {
+        return None;
+    }
+
+    let mut v = 0u64;
+    for c in text.chars().filter(|c| *c != '_') {
+        v = v.checked_mul(radix as u64)?
+            .checked_add(c.to_digit(radix)? as u64)?;
+    }
+    Some(v)
+}
+
+/// Split `text` at the first of `chars`, dropping that character.
+fn split<'a>(text: &'a str, chars: &[char]) -> (&'a str, Option<&'a str>) {
+    match text.find(|c| chars.contains(&c)) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    }
+}
+
+/// Whether `text` is a float body (after any `0x`) in `radix`, with an
+/// exponent introduced by one of `exp_chars`.
+fn valid_float(text: &str, radix: u32, exp_chars: &[char]) -> bool {
+    let (body, exp) = split(text, exp_chars);
+    let (int, frac) = split(body, &['.']);
+
+    valid_digits(int, radix) &&
+        frac.is_none_or(|f| f.is_empty() || valid_digits(f, radix)) &&
+        exp.is_none_or(|e| valid_digits(sign(e).1, 10))
+}
+
+/// Parse an integer literal of `bits` width.  Unsigned values up to
+/// 2^bits-1 and signed values down to -2^(bits-1) are accepted.
+fn int(lit: &str, bits: u32) -> Option<u64> {
+    let (neg, rest) = sign(lit);
+    let v = if let Some(hex) = rest.strip_prefix("0x") {
+        digits(hex, 16)?
+    } else {
+        digits(rest, 10)?
+    };
+
+    let max = if bits == 64 { u64::MAX } else { (1 << bits) - 1 };
+    if neg {
+        if v > 1 << (bits - 1) {
+            return None;
+        }
+        Some(v.wrapping_neg() & max)
+    } else if v > max {
+        None
+    } else {
+        Some(v)
+    }
+}
+
+pub fn parse_i32(lit: &str) -> Option<i32> {
+    int(lit, 32).map(|v| v as u32 as i32)
+}
+
+/// Parse an unsigned index literal.
+pub fn parse_u32(lit: &str) -> Option<u32> {
+    match lit.chars().next() {
+        Some('+') | Some('-') => None,
+        _ => int(lit, 32).map(|v| v as u32),
+    }
+}
+
+pub fn parse_i64(lit: &str) -> Option<i64> {
+    int(lit, 64).map(|v| v as i64)
+}
+
+/// Round `m * 2^exp` to the nearest float with `mant` explicit mantissa
+/// bits and an `expo`-bit exponent, ties to even.  `sticky` records
+/// whether nonzero bits were already discarded below `m`.  Returns the
+/// unsigned bit pattern, or `None` if the result overflows to infinity.
+fn round_float(mut m: u64, mut exp: i64, mut sticky: bool, mant: u32, expo: u32) -> Option<u64> {
+    if m == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (expo - 1)) - 1;
+    let top = 63 - m.leading_zeros() as i64;
+
+    // The exponent of the least significant mantissa bit we can keep.
+    let unit = (top + exp).max(1 - bias) - mant as i64;
+    let shift = unit - exp;
+
+    if shift > 0 {
+        let (kept, half, below) = if shift > 64 {
+            (0, false, true)
+        } else {
+            let kept = if shift == 64 { 0 } else { m >> shift };
+            let half = (m >> (shift - 1)) & 1 == 1;
+            let below = m & ((1u64 << (shift - 1)) - 1) != 0;
+            (kept, half, below)
+        };
+        sticky |= below;
+        m = kept;
+        if half && (sticky || m & 1 == 1) {
+            m += 1;
+        }
+        exp = unit;
+        if m >> (mant + 1) != 0 {
+            m >>= 1;
+            exp += 1;
+        }
+    } else {
+        m <<= -shift;
+        exp = unit;
+    }
+
+    let biased = if m >> mant != 0 { exp + mant as i64 + bias } else { 0 };
+    if biased >= (1 << expo) - 1 {
+        return None;
+    }
+
+    Some(((biased as u64) << mant) | (m & ((1 << mant) - 1)))
+}
+
+/// Parse a hexadecimal float body (after `0x`).
+fn hex_float(text: &str, mant: u32, expo: u32) -> Option<u64> {
+    if !valid_float(text, 16, &['p', 'P']) {
+        return None;
+    }
+
+    let (body, exp) = split(text, &['p', 'P']);
+    let (int, frac) = split(body, &['.']);
+
+    // Exponents beyond this range saturate to zero or infinity anyway.
+    let mut e = match exp.map(sign) {
+        Some((neg, e)) => {
+            let e = digits(e, 10).unwrap_or(u64::MAX).min(1 << 20) as i64;
+            if neg { -e } else { e }
+        }
+        None => 0,
+    };
+
+    let mut m = 0u64;
+    let mut sticky = false;
+
+    for c in int.chars().filter(|c| *c != '_') {
+        let d = c.to_digit(16)? as u64;
+        if m >> 59 == 0 {
+            m = m << 4 | d;
+        } else {
+            sticky |= d != 0;
+            e += 4;
+        }
+    }
+
+    for c in frac.unwrap_or("").chars().filter(|c| *c != '_') {
+        let d = c.to_digit(16)? as u64;
+        if m >> 59 == 0 {
+            m = m << 4 | d;
+            e -= 4;
+        } else {
+            sticky |= d != 0;
+        }
+    }
+
+    round_float(m, e, sticky, mant, expo)
+}
+
+/// Parse a float literal to the bit pattern of a float with `mant`
+/// mantissa bits and `expo` exponent bits.
+fn float(lit: &str, mant: u32, expo: u32) -> Option<u64> {
+    let (neg, rest) = sign(lit);
+    let sign_bit = if neg { 1 << (mant + expo) } else { 0 };
+    let inf = ((1 << expo) - 1) << mant;
+
+    let bits = if rest == "inf" {
+        inf
+    } else if rest == "nan" {
+        inf | 1 << (mant - 1)
+    } else if let Some(payload) = rest.strip_prefix("nan:0x") {
+        let payload = digits(payload, 16)?;
+        if payload == 0 || payload >> mant != 0 {
+            return None;
+        }
+        inf | payload
+    } else if let Some(hex) = rest.strip_prefix("0x") {
+        hex_float(hex, mant, expo)?
+    } else {
+        if !valid_float(rest, 10, &['e', 'E']) {
+            return None;
+        }
+        let clean: String = rest.chars().filter(|c| *c != '_').collect();
+        let bits = if mant == 23 {
+            (clean.parse::<f32>().ok()?.to_bits()) as u64
+        } else {
+            clean.parse::<f64>().ok()?.to_bits()
+        };
+        // Finite literals that round to infinity are out of range.
+        if bits == inf {
+            return None;
+        }
+        bits
+    };
+
+    Some(sign_bit | bits)
+}
+
+/// Parse an f32 literal, returning its bit pattern so NaN payloads are
+/// preserved exactly.
+pub fn parse_f32(lit: &str) -> Option<u32> {
+    float(lit, 23, 8).map(|bits| bits as u32)
+}
+
+/// Parse an f64 literal, returning its bit pattern.
+pub fn parse_f64(lit: &str) -> Option<u64> {
+    float(lit, 52, 11)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let exprs = parse("(module ;; comment\n  (; block (; nested ;) ;) (func $f \"a\\41\\n\"))\n(x)")
+            .unwrap();
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[0].0, 1);
+        assert_eq!(exprs[1].0, 3);
+        assert_eq!(exprs[0].1.head(), Some("module"));
+
+        let func = &exprs[0].1.list().unwrap()[1];
+        assert_eq!(func.list().unwrap()[1].atom(), Some("$f"));
+        assert_eq!(func.list().unwrap()[2].string(), Some(&b"aA\n"[..]));
+
+        assert_eq!(parse("(a"), Err(SyntaxError { line: 1 }));
+        assert_eq!(parse("(a\n\"b)"), Err(SyntaxError { line: 2 }));
+    }
+
+    #[test]
+    fn test_ints() {
+        assert_eq!(parse_i32("0"), Some(0));
+        assert_eq!(parse_i32("-1"), Some(-1));
+        assert_eq!(parse_i32("0xffffffff"), Some(-1));
+        assert_eq!(parse_i32("4294967295"), Some(-1));
+        assert_eq!(parse_i32("-0x80000000"), Some(i32::MIN));
+        assert_eq!(parse_i32("1_000"), Some(1000));
+        assert_eq!(parse_i32("0x100000000"), None);
+        assert_eq!(parse_i32("-2147483649"), None);
+        assert_eq!(parse_i64("-9223372036854775808"), Some(i64::MIN));
+        assert_eq!(parse_i64("0xffffffffffffffff"), Some(-1));
+        assert_eq!(parse_i64("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(parse_f32("1.5"), Some(1.5f32.to_bits()));
+        assert_eq!(parse_f32("-0"), Some(0x8000_0000));
+        assert_eq!(parse_f32("inf"), Some(0x7f80_0000));
+        assert_eq!(parse_f32("-nan"), Some(0xffc0_0000));
+        assert_eq!(parse_f32("nan:0x200000"), Some(0x7fa0_0000));
+        assert_eq!(parse_f32("0x1p-149"), Some(1));
+        assert_eq!(parse_f32("0x1p-150"), Some(0));
+        assert_eq!(parse_f32("0x1.8p-149"), Some(2));
+        assert_eq!(parse_f32("0x1.fffffefp127"), Some(0x7f7f_ffff));
+        assert_eq!(parse_f32("0x1p128"), None);
+        assert_eq!(parse_f32("0x1.ffffffp127"), None);
+        assert_eq!(parse_f32("1e39"), None);
+        assert_eq!(parse_f32("3.4028235e38"), Some(0x7f7f_ffff));
+        assert_eq!(parse_f32("0x1.000001p0"), Some(0x3f80_0000));
+        assert_eq!(parse_f32("0x1.000001000000001p0"), Some(0x3f80_0001));
+        assert_eq!(parse_f64("0x1p-1074"), Some(1));
+        assert_eq!(parse_f64("0x1.fffffffffffff8p0"), Some(0x4000_0000_0000_0000));
+        assert_eq!(parse_f64("1e308"), Some(1e308f64.to_bits()));
+        assert_eq!(parse_f64("1e309"), None);
+        assert_eq!(parse_f64("0x1p1024"), None);
+        assert_eq!(parse_f64("0x1.fffffffffffff8p1023"), None);
+        assert_eq!(parse_f64("0x1.921fb54442d18p+1"), Some(::std::f64::consts::PI.to_bits()));
+        assert_eq!(parse_f64("nan:0x0"), None);
+        assert_eq!(parse_f64("x"), None);
+    }
+}
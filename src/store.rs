@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Decides whether memories and tables may grow.  Allocating a memory
+/// or table at instantiation counts as growing it from zero.
+pub trait ResourceLimiter {
+    /// A memory is growing from `current` to `desired` bytes.  `maximum`
+    /// is the most it may ever hold, in bytes.  Return false to refuse.
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool;
+
+    /// A table is growing from `current` to `desired` elements.
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool;
+
+    /// A memory of `size` bytes was dropped, or growth by `size` bytes
+    /// failed after being allowed.
+    fn memory_released(&mut self, _size: usize) {}
+
+    /// A table of `size` elements was dropped.
+    fn table_released(&mut self, _size: u32) {}
+}
+
+pub type SharedLimiter = Rc<RefCell<dyn ResourceLimiter>>;
+
+/// Resources shared by every instance created in the store.
+#[derive(Clone)]
+pub struct Store {
+    limiter: Option<SharedLimiter>,
+}
+
+impl Store {
+    /// A store whose instances are bounded only by their modules'
+    /// declared limits.
+    pub fn new() -> Store {
+        Store { limiter: None }
+    }
+
+    /// A store that consults `limiter` before any memory or table in it
+    /// grows.  The embedder may keep a clone of `limiter` to inspect it.
+    pub fn with_limiter<L: ResourceLimiter + 'static>(limiter: Rc<RefCell<L>>) -> Store {
+        Store { limiter: Some(limiter) }
+    }
+
+    pub(crate) fn limiter(&self) -> Option<SharedLimiter> {
+        self.limiter.clone()
+    }
+}
+
+impl Default for Store {
+    fn default() -> Store {
+        Store::new()
+    }
+}
+
+/// A `ResourceLimiter` that bounds the total size of all memories and
+/// tables in a store.
+pub struct StoreLimits {
+    memory: usize,
+    table_elements: u32,
+    memory_used: usize,
+    table_elements_used: u32,
+}
+
+impl StoreLimits {
+    pub fn new(memory: usize, table_elements: u32) -> StoreLimits {
+        StoreLimits { memory, table_elements, memory_used: 0, table_elements_used: 0 }
+    }
+
+    /// Bytes of memory currently allocated.
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn table_elements_used(&self) -> u32 {
+        self.table_elements_used
+    }
+}
+
+/// No bounds, but usage is still tracked.
+impl Default for StoreLimits {
+    fn default() -> StoreLimits {
+        StoreLimits::new(usize::MAX, u32::MAX)
+    }
+}
+
+impl ResourceLimiter for StoreLimits {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        match self.memory_used.checked_add(desired - current) {
+            Some(used) if used <= self.memory => {
+                self.memory_used = used;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        match self.table_elements_used.checked_add(desired - current) {
+            Some(used) if used <= self.table_elements => {
+                self.table_elements_used = used;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn memory_released(&mut self, size: usize) {
+        self.memory_used -= size;
+    }
+
+    fn table_released(&mut self, size: u32) {
+        self.table_elements_used -= size;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use section::Module;
+    use linker::Linker;
+    use value::Value;
+    use error::InstantiationError;
+
+    // (module (memory 1 2)
+    //   (func (export "grow") (param i32) (result i32)
+    //     (grow_memory (get_local 0))))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x05, 0x04, 0x01, 0x01, 0x01, 0x02,
+        0x07, 0x08, 0x01, 0x04, 0x67, 0x72, 0x6f, 0x77, 0x00, 0x00,
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x20, 0x00, 0x40, 0x00, 0x0b,
+    ];
+
+    const PAGE: usize = 64 * 1024;
+
+    #[test]
+    fn test_store_limits() {
+        let module = Module::decode_from(MODULE).unwrap();
+        let linker = Linker::new();
+        let limits = Rc::new(RefCell::new(StoreLimits::new(3 * PAGE, 0)));
+        let store = Store::with_limiter(limits.clone());
+        let grow = |n| [Value::I32(n)];
+
+        let mut a = linker.instantiate_in(&store, &module).unwrap();
+        assert_eq!(a.invoke("grow", &grow(1)), Ok(vec![Value::I32(1)]));
+        assert_eq!(a.invoke("grow", &grow(1)), Ok(vec![Value::I32(-1)]));
+        assert_eq!(limits.borrow().memory_used(), 2 * PAGE);
+
+        let mut b = linker.instantiate_in(&store, &module).unwrap();
+        assert_eq!(linker.instantiate_in(&store, &module).err(),
+                   Some(InstantiationError::ResourceLimitExceeded));
+        assert_eq!(b.invoke("grow", &grow(1)), Ok(vec![Value::I32(-1)]));
+
+        drop(a);
+        assert_eq!(limits.borrow().memory_used(), PAGE);
+        assert_eq!(b.invoke("grow", &grow(1)), Ok(vec![Value::I32(1)]));
+        assert!(linker.instantiate_in(&store, &module).is_ok());
+    }
+}
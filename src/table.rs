@@ -0,0 +1,52 @@
+use store::SharedLimiter;
+
+pub struct Table {
+    elems: Vec<Option<u32>>,
+    limiter: Option<SharedLimiter>,
+}
+
+impl Table {
+    /// A table of `initial` elements, if `limiter` allows it.
+    pub(crate) fn limited(initial: u32, maximum: Option<u32>, limiter: Option<SharedLimiter>)
+        -> Option<Table>
+    {
+        if let Some(ref limiter) = limiter {
+            if !limiter.borrow_mut().table_growing(0, initial, maximum) {
+                return None;
+            }
+        }
+        Some(Table { elems: vec![None; initial as usize], limiter })
+    }
+
+    /// The function index at `index`: `None` if out of bounds,
+    /// `Some(None)` if the slot is empty.
+    pub fn get(&self, index: u32) -> Option<Option<u32>> {
+        self.elems.get(index as usize).cloned()
+    }
+
+    /// Whether `len` elements starting at `offset` are in bounds.
+    pub fn fits(&self, offset: u32, len: usize) -> bool {
+        (offset as usize).checked_add(len).is_some_and(|end| end <= self.elems.len())
+    }
+
+    /// Store function indices `funcs` starting at element `offset`.
+    pub fn init(&mut self, offset: u32, funcs: &[u32]) -> Option<()> {
+        if !self.fits(offset, funcs.len()) {
+            return None;
+        }
+
+        let start = offset as usize;
+        for (slot, func) in self.elems[start..start + funcs.len()].iter_mut().zip(funcs) {
+            *slot = Some(*func);
+        }
+        Some(())
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if let Some(ref limiter) = self.limiter {
+            limiter.borrow_mut().table_released(self.elems.len() as u32);
+        }
+    }
+}
@@ -2,7 +2,7 @@ use untrusted;
 use codec;
 use error::CodecError;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ValueType {
     I32,
     I64,
@@ -12,7 +12,10 @@ pub enum ValueType {
 
 impl ValueType {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<ValueType, CodecError> {
-        let ty = codec::read_vari7(rd)?;
+        ValueType::from_code(codec::read_vari7(rd)? as i64)
+    }
+
+    fn from_code(ty: i64) -> Result<ValueType, CodecError> {
         match ty {
             -0x01 => Ok(ValueType::I32),
             -0x02 => Ok(ValueType::I64),
@@ -21,26 +24,52 @@ impl ValueType {
             _ => Err(CodecError::BadType),
         }
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let ty = match *self {
+            ValueType::I32 => -0x01,
+            ValueType::I64 => -0x02,
+            ValueType::F32 => -0x03,
+            ValueType::F64 => -0x04,
+        };
+        codec::write_vari7(out, ty);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BlockType {
     Single(ValueType),
     Void,
+    /// The signature of a type section entry, for blocks that take
+    /// parameters or give several results.
+    Index(u32),
 }
 
 impl BlockType {
     pub fn decode(rd: &mut untrusted::Reader) -> Result<BlockType, CodecError> {
         if rd.peek(0x40) {
             let _ = rd.read_byte();
-            Ok(BlockType::Void)
+            return Ok(BlockType::Void);
+        }
+
+        let ty = codec::read_vari33(rd)?;
+        if ty < 0 {
+            Ok(BlockType::Single(ValueType::from_code(ty)?))
         } else {
-            Ok(BlockType::Single(ValueType::decode(rd)?))
+            Ok(BlockType::Index(ty as u32))
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            BlockType::Single(ref ty) => ty.encode(out),
+            BlockType::Void => codec::write_u8(out, 0x40),
+            BlockType::Index(index) => codec::write_vari(out, index as i64),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AnyFunction;
 
 impl AnyFunction {
@@ -52,6 +81,10 @@ impl AnyFunction {
             _ => Err(CodecError::BadType),
         }
     }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        codec::write_vari7(out, -0x10);
+    }
 }
 
 pub type ElementType = AnyFunction;
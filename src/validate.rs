@@ -0,0 +1,743 @@
+//! Module validation, following the algorithm in the appendix of the
+//! WebAssembly specification.
+
+use section::{Module, FunctionType, GlobalType, ImportKind, ExternalKind, ResizableLimits};
+use expr::{InitExpr, Op};
+use ty::{BlockType, ValueType};
+use ty::ValueType::{I32, I64, F32, F64};
+
+use error::{CodeError, ValidationError};
+
+use std::collections::HashSet;
+
+/// Functions may not declare more locals (including parameters) than
+/// this.  The specification leaves this limit to implementations.
+pub const MAX_LOCALS: u64 = 50_000;
+
+const MAX_PAGES: u32 = 65536;
+
+/// What the code in a module can refer to.
+struct Env<'m> {
+    funcs: Vec<&'m FunctionType>,
+    types: &'m [FunctionType],
+    globals: Vec<&'m GlobalType>,
+    imported_globals: usize,
+    tables: usize,
+    memories: usize,
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Ctrl {
+    kind: Kind,
+    start: Vec<ValueType>,
+    end: Vec<ValueType>,
+    height: usize,
+    unreachable: bool,
+}
+
+/// Operand types; `None` is the unknown type of values conjured by
+/// unreachable code.
+type Opd = Option<ValueType>;
+
+struct Checker<'e, 'm: 'e> {
+    env: &'e Env<'m>,
+    locals: Vec<ValueType>,
+    opds: Vec<Opd>,
+    ctrls: Vec<Ctrl>,
+}
+
+/// The operand and result types of simple numeric ops.
+fn numeric(op: &Op) -> Option<(&'static [ValueType], ValueType)> {
+    use expr::Op::*;
+
+    let sig: (&'static [ValueType], ValueType) = match *op {
+        I32Eqz => (&[I32], I32),
+        I64Eqz => (&[I64], I32),
+
+        I32Eq | I32Ne | I32LtSigned | I32LtUnsigned | I32GtSigned | I32GtUnsigned |
+            I32LeSigned | I32LeUnsigned | I32GeSigned | I32GeUnsigned => (&[I32, I32], I32),
+        I64Eq | I64Ne | I64LtSigned | I64LtUnsigned | I64GtSigned | I64GtUnsigned |
+            I64LeSigned | I64LeUnsigned | I64GeSigned | I64GeUnsigned => (&[I64, I64], I32),
+        F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge => (&[F32, F32], I32),
+        F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge => (&[F64, F64], I32),
+
+        I32Clz | I32Ctz | I32Popcnt => (&[I32], I32),
+        I32Add | I32Sub | I32Mul | I32DivSigned | I32DivUnsigned | I32RemSigned |
+            I32RemUnsigned | I32And | I32Or | I32Xor | I32Shl | I32ShrSigned |
+            I32ShrUnsigned | I32Rotl | I32Rotr => (&[I32, I32], I32),
+        I64Clz | I64Ctz | I64Popcnt => (&[I64], I64),
+        I64Add | I64Sub | I64Mul | I64DivSigned | I64DivUnsigned | I64RemSigned |
+            I64RemUnsigned | I64And | I64Or | I64Xor | I64Shl | I64ShrSigned |
+            I64ShrUnsigned | I64Rotl | I64Rotr => (&[I64, I64], I64),
+
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => (&[F32], F32),
+        F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => (&[F32, F32], F32),
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => (&[F64], F64),
+        F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => (&[F64, F64], F64),
+
+        I32WrapI64 => (&[I64], I32),
+        I32TruncSignedF32 | I32TruncUnsignedF32 => (&[F32], I32),
+        I32TruncSignedF64 | I32TruncUnsignedF64 => (&[F64], I32),
+        I64ExtendSignedI32 | I64ExtendUnsignedI32 => (&[I32], I64),
+        I64TruncSignedF32 | I64TruncUnsignedF32 => (&[F32], I64),
+        I64TruncSignedF64 | I64TruncUnsignedF64 => (&[F64], I64),
+        F32ConvertSignedI32 | F32ConvertUnsignedI32 => (&[I32], F32),
+        F32ConvertSignedI64 | F32ConvertUnsignedI64 => (&[I64], F32),
+        F32DemoteF64 => (&[F64], F32),
+        F64ConvertSignedI32 | F64ConvertUnsignedI32 => (&[I32], F64),
+        F64ConvertSignedI64 | F64ConvertUnsignedI64 => (&[I64], F64),
+        F64PromoteF32 => (&[F32], F64),
+        I32ReinterpretF32 => (&[F32], I32),
+        I64ReinterpretF64 => (&[F64], I64),
+        F32ReinterpretI32 => (&[I32], F32),
+        F64ReinterpretI64 => (&[I64], F64),
+        I32Extend8Signed | I32Extend16Signed => (&[I32], I32),
+        I64Extend8Signed | I64Extend16Signed | I64Extend32Signed => (&[I64], I64),
+
+        _ => return None,
+    };
+    Some(sig)
+}
+
+fn is_store(op: &Op) -> bool {
+    use expr::Op::*;
+
+    matches!(*op, I32Store(_) | I64Store(_) | F32Store(_) | F64Store(_) | I32Store8(_) |
+                  I32Store16(_) | I64Store8(_) | I64Store16(_) | I64Store32(_))
+}
+
+impl<'e, 'm> Checker<'e, 'm> {
+    fn push(&mut self, ty: Opd) {
+        self.opds.push(ty);
+    }
+
+    fn push_all(&mut self, types: &[ValueType]) {
+        for ty in types {
+            self.push(Some(*ty));
+        }
+    }
+
+    fn pop(&mut self) -> Result<Opd, CodeError> {
+        let (height, unreachable) = match self.ctrls.last() {
+            Some(ctrl) => (ctrl.height, ctrl.unreachable),
+            None => return Err(CodeError::Unbalanced),
+        };
+
+        if self.opds.len() == height {
+            return if unreachable { Ok(None) } else { Err(CodeError::TypeMismatch) };
+        }
+        Ok(self.opds.pop().unwrap())
+    }
+
+    fn pop_expect(&mut self, expect: ValueType) -> Result<(), CodeError> {
+        match self.pop()? {
+            Some(actual) if actual != expect => Err(CodeError::TypeMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValueType]) -> Result<(), CodeError> {
+        for ty in types.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+
+    fn push_ctrl(&mut self, kind: Kind, start: Vec<ValueType>, end: Vec<ValueType>) {
+        let height = self.opds.len();
+        self.push_all(&start);
+        self.ctrls.push(Ctrl { kind, start, end, height, unreachable: false });
+    }
+
+    fn pop_ctrl(&mut self) -> Result<Ctrl, CodeError> {
+        let (end, height) = match self.ctrls.last() {
+            Some(ctrl) => (ctrl.end.clone(), ctrl.height),
+            None => return Err(CodeError::Unbalanced),
+        };
+
+        self.pop_all(&end)?;
+        if self.opds.len() != height {
+            return Err(CodeError::TypeMismatch);
+        }
+        Ok(self.ctrls.pop().unwrap())
+    }
+
+    fn label_types(&self, depth: u32) -> Result<Vec<ValueType>, CodeError> {
+        let depth = depth as usize;
+        if depth >= self.ctrls.len() {
+            return Err(CodeError::BadLabel);
+        }
+
+        let ctrl = &self.ctrls[self.ctrls.len() - 1 - depth];
+        if ctrl.kind == Kind::Loop {
+            Ok(ctrl.start.clone())
+        } else {
+            Ok(ctrl.end.clone())
+        }
+    }
+
+    fn set_unreachable(&mut self) -> Result<(), CodeError> {
+        let ctrl = self.ctrls.last_mut()
+            .ok_or(CodeError::Unbalanced)?;
+        self.opds.truncate(ctrl.height);
+        ctrl.unreachable = true;
+        Ok(())
+    }
+
+    fn block_types(&self, ty: &BlockType) -> Result<(Vec<ValueType>, Vec<ValueType>), CodeError> {
+        match *ty {
+            BlockType::Single(ty) => Ok((vec![], vec![ty])),
+            BlockType::Void => Ok((vec![], vec![])),
+            BlockType::Index(index) => {
+                let ty = self.env.types.get(index as usize)
+                    .ok_or(CodeError::BadTypeIndex)?;
+                Ok((ty.params.clone(), ty.results.clone()))
+            }
+        }
+    }
+
+    fn local(&self, index: u32) -> Result<ValueType, CodeError> {
+        self.locals.get(index as usize)
+            .cloned()
+            .ok_or(CodeError::BadLocalIndex)
+    }
+
+    fn global(&self, index: u32) -> Result<&'m GlobalType, CodeError> {
+        self.env.globals.get(index as usize)
+            .cloned()
+            .ok_or(CodeError::BadGlobalIndex)
+    }
+
+    fn call(&mut self, ty: &FunctionType) -> Result<(), CodeError> {
+        self.pop_all(&ty.params)?;
+        self.push_all(&ty.results);
+        Ok(())
+    }
+
+    fn op(&mut self, op: &Op) -> Result<(), CodeError> {
+        use expr::Op::*;
+
+        if let Some((params, result)) = numeric(op) {
+            self.pop_all(params)?;
+            self.push(Some(result));
+            return Ok(());
+        }
+
+        if let Some((immed, size, ty)) = op.memory_access() {
+            if self.env.memories == 0 {
+                return Err(CodeError::NoMemory);
+            }
+            if immed.align > size {
+                return Err(CodeError::BadAlignment);
+            }
+            if is_store(op) {
+                self.pop_expect(ty)?;
+                self.pop_expect(I32)?;
+            } else {
+                self.pop_expect(I32)?;
+                self.push(Some(ty));
+            }
+            return Ok(());
+        }
+
+        match *op {
+            Unreachable => self.set_unreachable()?,
+            Nop => {}
+
+            Block(ref ty) => {
+                let (start, end) = self.block_types(ty)?;
+                self.pop_all(&start)?;
+                self.push_ctrl(Kind::Block, start, end);
+            }
+            Loop(ref ty) => {
+                let (start, end) = self.block_types(ty)?;
+                self.pop_all(&start)?;
+                self.push_ctrl(Kind::Loop, start, end);
+            }
+            If(ref ty) => {
+                let (start, end) = self.block_types(ty)?;
+                self.pop_expect(I32)?;
+                self.pop_all(&start)?;
+                self.push_ctrl(Kind::If, start, end);
+            }
+            Else => {
+                let ctrl = self.pop_ctrl()?;
+                if ctrl.kind != Kind::If {
+                    return Err(CodeError::Unbalanced);
+                }
+                self.push_ctrl(Kind::Else, ctrl.start, ctrl.end);
+            }
+            End => {
+                let ctrl = self.pop_ctrl()?;
+                if ctrl.kind == Kind::Function {
+                    return Err(CodeError::Unbalanced);
+                }
+                if ctrl.kind == Kind::If && ctrl.start != ctrl.end {
+                    return Err(CodeError::TypeMismatch);
+                }
+                self.push_all(&ctrl.end);
+            }
+
+            Branch(depth) => {
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                self.set_unreachable()?;
+            }
+            BranchIf(depth) => {
+                self.pop_expect(I32)?;
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                self.push_all(&types);
+            }
+            BranchTable(ref table) => {
+                self.pop_expect(I32)?;
+                let types = self.label_types(table.default)?;
+                for depth in &table.targets {
+                    if self.label_types(*depth)? != types {
+                        return Err(CodeError::TypeMismatch);
+                    }
+                }
+                self.pop_all(&types)?;
+                self.set_unreachable()?;
+            }
+            Return => {
+                let types = self.label_types(self.ctrls.len() as u32 - 1)?;
+                self.pop_all(&types)?;
+                self.set_unreachable()?;
+            }
+
+            Call(index) => {
+                let ty = *self.env.funcs.get(index as usize)
+                    .ok_or(CodeError::BadFunctionIndex)?;
+                self.call(ty)?;
+            }
+            CallIndirect(index) => {
+                let ty = self.env.types.get(index as usize)
+                    .ok_or(CodeError::BadTypeIndex)?;
+                if self.env.tables == 0 {
+                    return Err(CodeError::NoTable);
+                }
+                self.pop_expect(I32)?;
+                self.call(ty)?;
+            }
+
+            Drop => {
+                self.pop()?;
+            }
+            Select => {
+                self.pop_expect(I32)?;
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+                match (t1, t2) {
+                    (Some(a), Some(b)) if a != b => return Err(CodeError::TypeMismatch),
+                    _ => self.push(t1.or(t2)),
+                }
+            }
+
+            GetLocal(index) => {
+                let ty = self.local(index)?;
+                self.push(Some(ty));
+            }
+            SetLocal(index) => {
+                let ty = self.local(index)?;
+                self.pop_expect(ty)?;
+            }
+            TeeLocal(index) => {
+                let ty = self.local(index)?;
+                self.pop_expect(ty)?;
+                self.push(Some(ty));
+            }
+            GetGlobal(index) => {
+                let global = self.global(index)?;
+                self.push(Some(global.content));
+            }
+            SetGlobal(index) => {
+                let global = self.global(index)?;
+                if !global.mutable {
+                    return Err(CodeError::ImmutableGlobal);
+                }
+                self.pop_expect(global.content)?;
+            }
+
+            I32Const(_) => self.push(Some(I32)),
+            I64Const(_) => self.push(Some(I64)),
+            F32Const(_) => self.push(Some(F32)),
+            F64Const(_) => self.push(Some(F64)),
+
+            CurrentMemory(reserved) => {
+                if self.env.memories == 0 {
+                    return Err(CodeError::NoMemory);
+                }
+                if reserved != 0 {
+                    return Err(CodeError::BadImmediate);
+                }
+                self.push(Some(I32));
+            }
+            GrowMemory(reserved) => {
+                if self.env.memories == 0 {
+                    return Err(CodeError::NoMemory);
+                }
+                if reserved != 0 {
+                    return Err(CodeError::BadImmediate);
+                }
+                self.pop_expect(I32)?;
+                self.push(Some(I32));
+            }
+
+            // Handled by `numeric` and `memory_access` above; listed so
+            // that a new op cannot be missed silently.
+            I32Eqz | I32Eq | I32Ne | I32LtSigned | I32LtUnsigned | I32GtSigned | I32GtUnsigned |
+                I32LeSigned | I32LeUnsigned | I32GeSigned | I32GeUnsigned | I64Eqz | I64Eq |
+                I64Ne | I64LtSigned | I64LtUnsigned | I64GtSigned | I64GtUnsigned | I64LeSigned |
+                I64LeUnsigned | I64GeSigned | I64GeUnsigned | F32Eq | F32Ne | F32Lt | F32Gt |
+                F32Le | F32Ge | F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge | I32Clz | I32Ctz |
+                I32Popcnt | I32Add | I32Sub | I32Mul | I32DivSigned | I32DivUnsigned |
+                I32RemSigned | I32RemUnsigned | I32And | I32Or | I32Xor | I32Shl | I32ShrSigned |
+                I32ShrUnsigned | I32Rotl | I32Rotr | I64Clz | I64Ctz | I64Popcnt | I64Add |
+                I64Sub | I64Mul | I64DivSigned | I64DivUnsigned | I64RemSigned | I64RemUnsigned |
+                I64And | I64Or | I64Xor | I64Shl | I64ShrSigned | I64ShrUnsigned | I64Rotl |
+                I64Rotr | F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest |
+                F32Sqrt | F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign |
+                F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | F64Add |
+                F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign | I32WrapI64 |
+                I32TruncSignedF32 | I32TruncUnsignedF32 | I32TruncSignedF64 |
+                I32TruncUnsignedF64 | I64ExtendSignedI32 | I64ExtendUnsignedI32 |
+                I64TruncSignedF32 | I64TruncUnsignedF32 | I64TruncSignedF64 |
+                I64TruncUnsignedF64 | F32ConvertSignedI32 | F32ConvertUnsignedI32 |
+                F32ConvertSignedI64 | F32ConvertUnsignedI64 | F32DemoteF64 |
+                F64ConvertSignedI32 | F64ConvertUnsignedI32 | F64ConvertSignedI64 |
+                F64ConvertUnsignedI64 | F64PromoteF32 | I32ReinterpretF32 | I64ReinterpretF64 |
+                F32ReinterpretI32 | F64ReinterpretI64 | I32Extend8Signed | I32Extend16Signed |
+                I64Extend8Signed | I64Extend16Signed | I64Extend32Signed | I32Load(_) |
+                I64Load(_) | F32Load(_) | F64Load(_) | I32Load8Signed(_) | I32Load8Unsigned(_) |
+                I32Load16Signed(_) | I32Load16Unsigned(_) | I64Load8Signed(_) |
+                I64Load8Unsigned(_) | I64Load16Signed(_) | I64Load16Unsigned(_) |
+                I64Load32Signed(_) | I64Load32Unsigned(_) | I32Store(_) | I64Store(_) |
+                F32Store(_) | F64Store(_) | I32Store8(_) | I32Store16(_) | I64Store8(_) |
+                I64Store16(_) | I64Store32(_) => {
+                return Err(CodeError::UnknownOp);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Type-check a function body.  On failure, returns the position of
+/// the offending op with the error.
+fn check_function(env: &Env, ty: &FunctionType, locals: &[::function::Local], ops: &[Op])
+    -> Result<(), (usize, CodeError)>
+{
+    let count = locals.iter().fold(ty.params.len() as u64, |n, l| n + l.count as u64);
+    if count > MAX_LOCALS {
+        return Err((0, CodeError::TooManyLocals));
+    }
+
+    let mut all_locals = ty.params.clone();
+    for local in locals {
+        for _ in 0..local.count {
+            all_locals.push(local.ty);
+        }
+    }
+
+    let mut checker = Checker { env, locals: all_locals, opds: vec![], ctrls: vec![] };
+    checker.push_ctrl(Kind::Function, vec![], ty.results.clone());
+
+    for (pos, op) in ops.iter().enumerate() {
+        checker.op(op)
+            .map_err(|e| (pos, e))?;
+    }
+
+    // The final `end` is not included in `ops`.
+    checker.pop_ctrl()
+        .map_err(|e| (ops.len(), e))?;
+    if !checker.ctrls.is_empty() {
+        return Err((ops.len(), CodeError::Unbalanced));
+    }
+    Ok(())
+}
+
+fn check_limits(limits: &ResizableLimits, ceiling: Option<u32>) -> Result<(), ValidationError> {
+    let (initial, max) = match *limits {
+        ResizableLimits::Initial(initial) => (initial, None),
+        ResizableLimits::InitialMax(initial, max) => (initial, Some(max)),
+    };
+
+    if max.is_some_and(|max| max < initial) {
+        return Err(ValidationError::BadLimits);
+    }
+    if let Some(ceiling) = ceiling {
+        if initial > ceiling || max.is_some_and(|max| max > ceiling) {
+            return Err(ValidationError::BadLimits);
+        }
+    }
+    Ok(())
+}
+
+/// The type of a constant expression.  Only imported, immutable globals
+/// may be read.
+fn check_init_expr(env: &Env, init: &InitExpr) -> Result<ValueType, ValidationError> {
+    if init.ops.len() != 1 {
+        return Err(ValidationError::BadInitExpr);
+    }
+
+    match init.ops[0] {
+        Op::I32Const(_) => Ok(I32),
+        Op::I64Const(_) => Ok(I64),
+        Op::F32Const(_) => Ok(F32),
+        Op::F64Const(_) => Ok(F64),
+        Op::GetGlobal(index) => {
+            if index as usize >= env.imported_globals {
+                return Err(ValidationError::BadGlobalIndex);
+            }
+            let global = env.globals[index as usize];
+            if global.mutable {
+                return Err(ValidationError::BadInitExpr);
+            }
+            Ok(global.content)
+        }
+        _ => Err(ValidationError::BadInitExpr),
+    }
+}
+
+pub fn validate(module: &Module) -> Result<(), ValidationError> {
+    let types = module.types();
+    let mut env = Env {
+        funcs: vec![],
+        types,
+        globals: vec![],
+        imported_globals: 0,
+        tables: 0,
+        memories: 0,
+    };
+
+    for import in module.imports() {
+        match import.kind {
+            ImportKind::Function(index) => {
+                let ty = types.get(index as usize)
+                    .ok_or(ValidationError::BadTypeIndex)?;
+                env.funcs.push(ty);
+            }
+            ImportKind::Table(ref table) => {
+                check_limits(&table.limits, None)?;
+                env.tables += 1;
+            }
+            ImportKind::Memory(ref memory) => {
+                check_limits(&memory.limits, Some(MAX_PAGES))?;
+                env.memories += 1;
+            }
+            ImportKind::Global(ref global) => {
+                env.globals.push(global);
+                env.imported_globals += 1;
+            }
+        }
+    }
+
+    let imported_funcs = env.funcs.len();
+    for index in module.functions() {
+        let ty = types.get(*index as usize)
+            .ok_or(ValidationError::BadTypeIndex)?;
+        env.funcs.push(ty);
+    }
+
+    for table in module.tables() {
+        check_limits(&table.limits, None)?;
+        env.tables += 1;
+    }
+    if env.tables > 1 {
+        return Err(ValidationError::MultipleTables);
+    }
+
+    for memory in module.memories() {
+        check_limits(&memory.limits, Some(MAX_PAGES))?;
+        env.memories += 1;
+    }
+    if env.memories > 1 {
+        return Err(ValidationError::MultipleMemories);
+    }
+
+    for global in module.globals() {
+        if check_init_expr(&env, &global.init)? != global.ty.content {
+            return Err(ValidationError::BadInitExpr);
+        }
+        env.globals.push(&global.ty);
+    }
+
+    let mut names = HashSet::new();
+    for export in module.exports() {
+        if !names.insert(export.field) {
+            return Err(ValidationError::DuplicateExport);
+        }
+
+        let index = export.index as usize;
+        match export.kind {
+            ExternalKind::Function if index >= env.funcs.len() => {
+                return Err(ValidationError::BadFunctionIndex)
+            }
+            ExternalKind::Table if index >= env.tables => {
+                return Err(ValidationError::BadTableIndex)
+            }
+            ExternalKind::Memory if index >= env.memories => {
+                return Err(ValidationError::BadMemoryIndex)
+            }
+            ExternalKind::Global if index >= env.globals.len() => {
+                return Err(ValidationError::BadGlobalIndex)
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = module.start() {
+        let ty = env.funcs.get(start as usize)
+            .ok_or(ValidationError::BadFunctionIndex)?;
+        if !ty.params.is_empty() || !ty.results.is_empty() {
+            return Err(ValidationError::BadStartFunction);
+        }
+    }
+
+    for elem in module.elements() {
+        if elem.index as usize >= env.tables {
+            return Err(ValidationError::BadTableIndex);
+        }
+        if check_init_expr(&env, &elem.offset)? != I32 {
+            return Err(ValidationError::BadInitExpr);
+        }
+        if elem.elems.iter().any(|f| *f as usize >= env.funcs.len()) {
+            return Err(ValidationError::BadFunctionIndex);
+        }
+    }
+
+    for data in module.data() {
+        if data.index as usize >= env.memories {
+            return Err(ValidationError::BadMemoryIndex);
+        }
+        if check_init_expr(&env, &data.init)? != I32 {
+            return Err(ValidationError::BadInitExpr);
+        }
+    }
+
+    let code = module.code();
+    if code.len() != module.functions().len() {
+        return Err(ValidationError::FunctionCodeMismatch);
+    }
+
+    for (i, body) in code.iter().enumerate() {
+        let func = imported_funcs + i;
+        check_function(&env, env.funcs[func], &body.locals, &body.ops)
+            .map_err(|(pos, error)| ValidationError::Code { func: func as u32, pos, error })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use expr::MemoryImmed;
+    use expr::Op::*;
+    use function::Local;
+
+    fn env<'m>(funcs: Vec<&'m FunctionType>, globals: Vec<&'m GlobalType>) -> Env<'m> {
+        Env { funcs, types: &[], globals, imported_globals: 0, tables: 0, memories: 1 }
+    }
+
+    fn check(ret: Option<ValueType>, ops: Vec<Op>) -> Result<(), (usize, CodeError)> {
+        let ty = FunctionType { params: vec![I32], results: ret.into_iter().collect() };
+        let locals = [Local { count: 1, ty: F64 }];
+        check_function(&env(vec![&ty], vec![]), &ty, &locals, &ops)
+    }
+
+    #[test]
+    fn test_check_function() {
+        let block = |ty| Block(BlockType::Single(ty));
+
+        assert_eq!(check(Some(I32), vec![GetLocal(0)]), Ok(()));
+        assert_eq!(check(Some(I32), vec![]), Err((0, CodeError::TypeMismatch)));
+        assert_eq!(check(None, vec![GetLocal(0)]), Err((1, CodeError::TypeMismatch)));
+        assert_eq!(check(None, vec![GetLocal(1), SetLocal(0)]), Err((1, CodeError::TypeMismatch)));
+        assert_eq!(check(None, vec![GetLocal(2)]), Err((0, CodeError::BadLocalIndex)));
+        assert_eq!(check(Some(I32), vec![GetLocal(0), Call(0)]), Ok(()));
+        assert_eq!(check(None, vec![Call(1)]), Err((0, CodeError::BadFunctionIndex)));
+        assert_eq!(check(None, vec![GetGlobal(0), Drop]), Err((0, CodeError::BadGlobalIndex)));
+
+        let ty = FunctionType { params: vec![], results: vec![] };
+        let global = GlobalType { content: I32, mutable: false };
+        assert_eq!(check_function(&env(vec![], vec![&global]), &ty, &[],
+                                  &[I32Const(1), SetGlobal(0)]),
+                   Err((1, CodeError::ImmutableGlobal)));
+
+        assert_eq!(check(Some(I32), vec![block(I32), I32Const(1), Branch(0), End]), Ok(()));
+        assert_eq!(check(Some(I32), vec![block(I32), Branch(0), End]),
+                   Err((1, CodeError::TypeMismatch)));
+        assert_eq!(check(Some(I32), vec![block(I32), I32Const(1), Branch(2), End]),
+                   Err((2, CodeError::BadLabel)));
+        assert_eq!(check(Some(I64), vec![Unreachable, I32Add, Drop, I64Const(1)]), Ok(()));
+        assert_eq!(check(None, vec![Unreachable, F32Const(0.0), I32Add]),
+                   Err((2, CodeError::TypeMismatch)));
+        assert_eq!(check(None, vec![GetLocal(0), If(BlockType::Single(I32)), I32Const(1), End, Drop]),
+                   Err((3, CodeError::TypeMismatch)));
+        assert_eq!(check(None, vec![Block(BlockType::Void)]), Err((1, CodeError::Unbalanced)));
+        assert_eq!(check(None, vec![End]), Err((0, CodeError::Unbalanced)));
+        assert_eq!(check(None, vec![Else]), Err((0, CodeError::Unbalanced)));
+
+        assert_eq!(check(Some(I32), vec![GetLocal(0), I32Load(MemoryImmed { align: 2, offset: 0 })]),
+                   Ok(()));
+        assert_eq!(check(Some(I32), vec![GetLocal(0), I32Load(MemoryImmed { align: 3, offset: 0 })]),
+                   Err((1, CodeError::BadAlignment)));
+        assert_eq!(check(Some(I32), vec![GetLocal(0), I32Load(MemoryImmed { align: 0x102, offset: 0 })]),
+                   Err((1, CodeError::BadAlignment)));
+        assert_eq!(check(Some(I32), vec![I32Const(0), GetLocal(0), GetLocal(1), Select]),
+                   Err((3, CodeError::TypeMismatch)));
+    }
+
+    #[test]
+    fn test_multi_value() {
+        let pair = FunctionType { params: vec![I32], results: vec![I32, I32] };
+        let types = [pair.clone()];
+        let env = Env { types: &types, ..env(vec![&pair], vec![]) };
+        let check = |ops: &[Op]| check_function(&env, &pair, &[], ops);
+
+        assert_eq!(check(&[GetLocal(0), GetLocal(0)]), Ok(()));
+        assert_eq!(check(&[GetLocal(0), Call(0)]), Ok(()));
+        assert_eq!(check(&[GetLocal(0)]), Err((1, CodeError::TypeMismatch)));
+        assert_eq!(check(&[GetLocal(0), Block(BlockType::Index(0)), GetLocal(0), End]), Ok(()));
+        assert_eq!(check(&[Block(BlockType::Index(0)), GetLocal(0), End]),
+                   Err((0, CodeError::TypeMismatch)));
+        assert_eq!(check(&[GetLocal(0), Block(BlockType::Index(1)), End]),
+                   Err((1, CodeError::BadTypeIndex)));
+        assert_eq!(check(&[GetLocal(0), GetLocal(0), If(BlockType::Index(0)),
+                           GetLocal(0), Else, I32Const(1), End]),
+                   Ok(()));
+
+        // A branch to a loop carries the loop's parameters.
+        assert_eq!(check(&[GetLocal(0), Loop(BlockType::Index(0)), Branch(0), End]), Ok(()));
+        assert_eq!(check(&[GetLocal(0), Loop(BlockType::Index(0)), I64Const(0), Branch(0), End]),
+                   Err((3, CodeError::TypeMismatch)));
+    }
+
+    #[test]
+    fn test_validate_module() {
+        // (module (func) (start 0) (func (param i32)) ...) with start
+        // pointing at the function that takes a parameter.
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x05, 0x01, 0x60, 0x01, 0x7f, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            0x08, 0x01, 0x00,
+            0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+        ];
+        let module = Module::decode_from(&bytes).unwrap();
+        assert_eq!(validate(&module), Err(ValidationError::BadStartFunction));
+
+        let input = include_bytes!("../webdsp_c.wasm").as_ref();
+        let module = Module::decode_from(input).unwrap();
+        assert_eq!(validate(&module), Ok(()));
+    }
+}
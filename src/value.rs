@@ -1,7 +1,30 @@
-#[derive(Debug, PartialEq)]
+use ty::ValueType;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Value {
   I32(i32),
   I64(i64),
   F32(f32),
   F64(f64),
 }
+
+impl Value {
+    /// The default value of a local of type `ty`.
+    pub fn zero(ty: ValueType) -> Value {
+        match ty {
+            ValueType::I32 => Value::I32(0),
+            ValueType::I64 => Value::I64(0),
+            ValueType::F32 => Value::F32(0.0),
+            ValueType::F64 => Value::F64(0.0),
+        }
+    }
+
+    pub fn ty(&self) -> ValueType {
+        match *self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+        }
+    }
+}
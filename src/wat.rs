@@ -0,0 +1,1370 @@
+//! Parser for the WebAssembly text format.
+//!
+//! Text modules are lowered to the same sections the binary decoder
+//! produces and then encoded, so they are decoded and validated exactly
+//! like binary ones.  Instructions may be flat or folded, and both the
+//! old (`get_local`, `i32.trunc_s/f32`) and current (`local.get`,
+//! `i32.trunc_f32_s`) instruction names are accepted.
+
+use sexpr::{self, Sexpr};
+use section::{Module, Section, FunctionType, ImportEntry, ImportKind, ExportEntry,
+              ExternalKind, TableType, MemoryType, GlobalType, GlobalVariable,
+              ElementSegment, DataSegment, ResizableLimits};
+use function::{FunctionBody, Local};
+use expr::{Op, InitExpr, MemoryImmed, BranchTable};
+use ty::{ValueType, BlockType, AnyFunction};
+use mem::PAGE_SIZE;
+use error::WatError;
+
+use std::collections::HashMap;
+use std::str;
+
+/// The items of a list, consumed from the front.
+struct Cursor<'a> {
+    items: &'a [Sexpr],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(items: &'a [Sexpr]) -> Cursor<'a> {
+        Cursor { items, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&'a Sexpr> {
+        self.items.get(self.pos)
+    }
+
+    fn peek_head(&self) -> Option<&'a str> {
+        self.peek().and_then(Sexpr::head)
+    }
+
+    fn next(&mut self) -> Option<&'a Sexpr> {
+        let item = self.peek();
+        if item.is_some() {
+            self.pos += 1;
+        }
+        item
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.items.len()
+    }
+
+    fn end(&self) -> Result<(), WatError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(WatError::Malformed("unexpected item"))
+        }
+    }
+
+    /// Take an optional `$id`.
+    fn id(&mut self) -> Option<&'a str> {
+        match self.peek().and_then(Sexpr::atom) {
+            Some(id) if id.starts_with('$') => {
+                self.pos += 1;
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    fn atom(&mut self) -> Result<&'a str, WatError> {
+        self.next()
+            .and_then(Sexpr::atom)
+            .ok_or(WatError::Malformed("expected an atom"))
+    }
+
+    /// Take a list, returning the cursor over its items after the
+    /// leading keyword.
+    fn list(&mut self) -> Result<Cursor<'a>, WatError> {
+        match self.next() {
+            Some(Sexpr::List(items)) if !items.is_empty() => Ok(Cursor::new(&items[1..])),
+            _ => Err(WatError::Malformed("expected a list")),
+        }
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], WatError> {
+        self.next()
+            .and_then(Sexpr::string)
+            .ok_or(WatError::Malformed("expected a string"))
+    }
+
+    fn name(&mut self) -> Result<&'a str, WatError> {
+        str::from_utf8(self.string()?)
+            .map_err(|_| WatError::Malformed("names must be UTF-8"))
+    }
+
+    fn u32(&mut self) -> Result<u32, WatError> {
+        let lit = self.atom()?;
+        sexpr::parse_u32(lit)
+            .ok_or_else(|| WatError::BadLiteral(lit.to_string()))
+    }
+}
+
+fn value_type(name: &str) -> Option<ValueType> {
+    match name {
+        "i32" => Some(ValueType::I32),
+        "i64" => Some(ValueType::I64),
+        "f32" => Some(ValueType::F32),
+        "f64" => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+fn take_value_type(cur: &mut Cursor) -> Result<ValueType, WatError> {
+    value_type(cur.atom()?)
+        .ok_or(WatError::Malformed("expected a value type"))
+}
+
+/// Whether `atom` is a `$name` or numeric index rather than the next
+/// instruction.
+fn is_var(atom: &str) -> bool {
+    atom.starts_with('$') || atom.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Parse `n m?`.
+fn limits(cur: &mut Cursor) -> Result<ResizableLimits, WatError> {
+    let initial = cur.u32()?;
+    if cur.peek().and_then(Sexpr::atom).and_then(sexpr::parse_u32).is_some() {
+        Ok(ResizableLimits::InitialMax(initial, cur.u32()?))
+    } else {
+        Ok(ResizableLimits::Initial(initial))
+    }
+}
+
+fn elem_type(cur: &mut Cursor) -> Result<AnyFunction, WatError> {
+    match cur.atom()? {
+        "anyfunc" | "funcref" => Ok(AnyFunction),
+        _ => Err(WatError::Malformed("expected an element type")),
+    }
+}
+
+fn table_type(cur: &mut Cursor) -> Result<TableType, WatError> {
+    let limits = limits(cur)?;
+    let element_ty = elem_type(cur)?;
+    Ok(TableType { element_ty, limits })
+}
+
+/// Parse `t` or `(mut t)`.
+fn global_type(cur: &mut Cursor) -> Result<GlobalType, WatError> {
+    if cur.peek_head() == Some("mut") {
+        let mut inner = cur.list()?;
+        let content = take_value_type(&mut inner)?;
+        inner.end()?;
+        Ok(GlobalType { content, mutable: true })
+    } else {
+        Ok(GlobalType { content: take_value_type(cur)?, mutable: false })
+    }
+}
+
+/// Run-length encode local declarations.
+fn local_runs(types: &[ValueType]) -> Vec<Local> {
+    let mut locals: Vec<Local> = vec![];
+    for ty in types {
+        match locals.last_mut() {
+            Some(local) if local.ty == *ty => local.count += 1,
+            _ => locals.push(Local { count: 1, ty: *ty }),
+        }
+    }
+    locals
+}
+
+/// Ops that take no immediates.
+fn simple_op(name: &str) -> Option<Op> {
+    let op = match name {
+        "unreachable" => Op::Unreachable,
+        "nop" => Op::Nop,
+        "return" => Op::Return,
+        "drop" => Op::Drop,
+        "select" => Op::Select,
+        "i32.eqz" => Op::I32Eqz,
+        "i32.eq" => Op::I32Eq,
+        "i32.ne" => Op::I32Ne,
+        "i32.lt_s" => Op::I32LtSigned,
+        "i32.lt_u" => Op::I32LtUnsigned,
+        "i32.gt_s" => Op::I32GtSigned,
+        "i32.gt_u" => Op::I32GtUnsigned,
+        "i32.le_s" => Op::I32LeSigned,
+        "i32.le_u" => Op::I32LeUnsigned,
+        "i32.ge_s" => Op::I32GeSigned,
+        "i32.ge_u" => Op::I32GeUnsigned,
+        "i64.eqz" => Op::I64Eqz,
+        "i64.eq" => Op::I64Eq,
+        "i64.ne" => Op::I64Ne,
+        "i64.lt_s" => Op::I64LtSigned,
+        "i64.lt_u" => Op::I64LtUnsigned,
+        "i64.gt_s" => Op::I64GtSigned,
+        "i64.gt_u" => Op::I64GtUnsigned,
+        "i64.le_s" => Op::I64LeSigned,
+        "i64.le_u" => Op::I64LeUnsigned,
+        "i64.ge_s" => Op::I64GeSigned,
+        "i64.ge_u" => Op::I64GeUnsigned,
+        "f32.eq" => Op::F32Eq,
+        "f32.ne" => Op::F32Ne,
+        "f32.lt" => Op::F32Lt,
+        "f32.gt" => Op::F32Gt,
+        "f32.le" => Op::F32Le,
+        "f32.ge" => Op::F32Ge,
+        "f64.eq" => Op::F64Eq,
+        "f64.ne" => Op::F64Ne,
+        "f64.lt" => Op::F64Lt,
+        "f64.gt" => Op::F64Gt,
+        "f64.le" => Op::F64Le,
+        "f64.ge" => Op::F64Ge,
+        "i32.clz" => Op::I32Clz,
+        "i32.ctz" => Op::I32Ctz,
+        "i32.popcnt" => Op::I32Popcnt,
+        "i32.add" => Op::I32Add,
+        "i32.sub" => Op::I32Sub,
+        "i32.mul" => Op::I32Mul,
+        "i32.div_s" => Op::I32DivSigned,
+        "i32.div_u" => Op::I32DivUnsigned,
+        "i32.rem_s" => Op::I32RemSigned,
+        "i32.rem_u" => Op::I32RemUnsigned,
+        "i32.and" => Op::I32And,
+        "i32.or" => Op::I32Or,
+        "i32.xor" => Op::I32Xor,
+        "i32.shl" => Op::I32Shl,
+        "i32.shr_s" => Op::I32ShrSigned,
+        "i32.shr_u" => Op::I32ShrUnsigned,
+        "i32.rotl" => Op::I32Rotl,
+        "i32.rotr" => Op::I32Rotr,
+        "i64.clz" => Op::I64Clz,
+        "i64.ctz" => Op::I64Ctz,
+        "i64.popcnt" => Op::I64Popcnt,
+        "i64.add" => Op::I64Add,
+        "i64.sub" => Op::I64Sub,
+        "i64.mul" => Op::I64Mul,
+        "i64.div_s" => Op::I64DivSigned,
+        "i64.div_u" => Op::I64DivUnsigned,
+        "i64.rem_s" => Op::I64RemSigned,
+        "i64.rem_u" => Op::I64RemUnsigned,
+        "i64.and" => Op::I64And,
+        "i64.or" => Op::I64Or,
+        "i64.xor" => Op::I64Xor,
+        "i64.shl" => Op::I64Shl,
+        "i64.shr_s" => Op::I64ShrSigned,
+        "i64.shr_u" => Op::I64ShrUnsigned,
+        "i64.rotl" => Op::I64Rotl,
+        "i64.rotr" => Op::I64Rotr,
+        "f32.abs" => Op::F32Abs,
+        "f32.neg" => Op::F32Neg,
+        "f32.ceil" => Op::F32Ceil,
+        "f32.floor" => Op::F32Floor,
+        "f32.trunc" => Op::F32Trunc,
+        "f32.nearest" => Op::F32Nearest,
+        "f32.sqrt" => Op::F32Sqrt,
+        "f32.add" => Op::F32Add,
+        "f32.sub" => Op::F32Sub,
+        "f32.mul" => Op::F32Mul,
+        "f32.div" => Op::F32Div,
+        "f32.min" => Op::F32Min,
+        "f32.max" => Op::F32Max,
+        "f32.copysign" => Op::F32Copysign,
+        "f64.abs" => Op::F64Abs,
+        "f64.neg" => Op::F64Neg,
+        "f64.ceil" => Op::F64Ceil,
+        "f64.floor" => Op::F64Floor,
+        "f64.trunc" => Op::F64Trunc,
+        "f64.nearest" => Op::F64Nearest,
+        "f64.sqrt" => Op::F64Sqrt,
+        "f64.add" => Op::F64Add,
+        "f64.sub" => Op::F64Sub,
+        "f64.mul" => Op::F64Mul,
+        "f64.div" => Op::F64Div,
+        "f64.min" => Op::F64Min,
+        "f64.max" => Op::F64Max,
+        "f64.copysign" => Op::F64Copysign,
+        "i32.wrap/i64" | "i32.wrap_i64" => Op::I32WrapI64,
+        "i32.trunc_s/f32" | "i32.trunc_f32_s" => Op::I32TruncSignedF32,
+        "i32.trunc_u/f32" | "i32.trunc_f32_u" => Op::I32TruncUnsignedF32,
+        "i32.trunc_s/f64" | "i32.trunc_f64_s" => Op::I32TruncSignedF64,
+        "i32.trunc_u/f64" | "i32.trunc_f64_u" => Op::I32TruncUnsignedF64,
+        "i64.extend_s/i32" | "i64.extend_i32_s" => Op::I64ExtendSignedI32,
+        "i64.extend_u/i32" | "i64.extend_i32_u" => Op::I64ExtendUnsignedI32,
+        "i64.trunc_s/f32" | "i64.trunc_f32_s" => Op::I64TruncSignedF32,
+        "i64.trunc_u/f32" | "i64.trunc_f32_u" => Op::I64TruncUnsignedF32,
+        "i64.trunc_s/f64" | "i64.trunc_f64_s" => Op::I64TruncSignedF64,
+        "i64.trunc_u/f64" | "i64.trunc_f64_u" => Op::I64TruncUnsignedF64,
+        "f32.convert_s/i32" | "f32.convert_i32_s" => Op::F32ConvertSignedI32,
+        "f32.convert_u/i32" | "f32.convert_i32_u" => Op::F32ConvertUnsignedI32,
+        "f32.convert_s/i64" | "f32.convert_i64_s" => Op::F32ConvertSignedI64,
+        "f32.convert_u/i64" | "f32.convert_i64_u" => Op::F32ConvertUnsignedI64,
+        "f32.demote/f64" | "f32.demote_f64" => Op::F32DemoteF64,
+        "f64.convert_s/i32" | "f64.convert_i32_s" => Op::F64ConvertSignedI32,
+        "f64.convert_u/i32" | "f64.convert_i32_u" => Op::F64ConvertUnsignedI32,
+        "f64.convert_s/i64" | "f64.convert_i64_s" => Op::F64ConvertSignedI64,
+        "f64.convert_u/i64" | "f64.convert_i64_u" => Op::F64ConvertUnsignedI64,
+        "f64.promote/f32" | "f64.promote_f32" => Op::F64PromoteF32,
+        "i32.reinterpret/f32" | "i32.reinterpret_f32" => Op::I32ReinterpretF32,
+        "i64.reinterpret/f64" | "i64.reinterpret_f64" => Op::I64ReinterpretF64,
+        "f32.reinterpret/i32" | "f32.reinterpret_i32" => Op::F32ReinterpretI32,
+        "f64.reinterpret/i64" | "f64.reinterpret_i64" => Op::F64ReinterpretI64,
+        "i32.extend8_s" => Op::I32Extend8Signed,
+        "i32.extend16_s" => Op::I32Extend16Signed,
+        "i64.extend8_s" => Op::I64Extend8Signed,
+        "i64.extend16_s" => Op::I64Extend16Signed,
+        "i64.extend32_s" => Op::I64Extend32Signed,
+        "current_memory" | "memory.size" => Op::CurrentMemory(0),
+        "grow_memory" | "memory.grow" => Op::GrowMemory(0),
+        _ => return None,
+    };
+    Some(op)
+}
+
+type MemoryOp = fn(MemoryImmed) -> Op;
+
+/// Loads and stores, with their natural alignment as a power of two.
+fn memory_op(name: &str) -> Option<(MemoryOp, u32)> {
+    let op: (MemoryOp, u32) = match name {
+        "i32.load" => (Op::I32Load, 2),
+        "i64.load" => (Op::I64Load, 3),
+        "f32.load" => (Op::F32Load, 2),
+        "f64.load" => (Op::F64Load, 3),
+        "i32.load8_s" => (Op::I32Load8Signed, 0),
+        "i32.load8_u" => (Op::I32Load8Unsigned, 0),
+        "i32.load16_s" => (Op::I32Load16Signed, 1),
+        "i32.load16_u" => (Op::I32Load16Unsigned, 1),
+        "i64.load8_s" => (Op::I64Load8Signed, 0),
+        "i64.load8_u" => (Op::I64Load8Unsigned, 0),
+        "i64.load16_s" => (Op::I64Load16Signed, 1),
+        "i64.load16_u" => (Op::I64Load16Unsigned, 1),
+        "i64.load32_s" => (Op::I64Load32Signed, 2),
+        "i64.load32_u" => (Op::I64Load32Unsigned, 2),
+        "i32.store" => (Op::I32Store, 2),
+        "i64.store" => (Op::I64Store, 3),
+        "f32.store" => (Op::F32Store, 2),
+        "f64.store" => (Op::F64Store, 3),
+        "i32.store8" => (Op::I32Store8, 0),
+        "i32.store16" => (Op::I32Store16, 1),
+        "i64.store8" => (Op::I64Store8, 0),
+        "i64.store16" => (Op::I64Store16, 1),
+        "i64.store32" => (Op::I64Store32, 2),
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Parse `offset=n? align=n?`.
+fn memory_immed(cur: &mut Cursor, natural: u32) -> Result<MemoryImmed, WatError> {
+    let mut immed = MemoryImmed { align: natural, offset: 0 };
+
+    if let Some(lit) = cur.peek().and_then(Sexpr::atom).filter(|a| a.starts_with("offset=")) {
+        cur.next();
+        immed.offset = sexpr::parse_u32(&lit[7..])
+            .ok_or_else(|| WatError::BadLiteral(lit.to_string()))?;
+    }
+
+    if let Some(lit) = cur.peek().and_then(Sexpr::atom).filter(|a| a.starts_with("align=")) {
+        cur.next();
+        match sexpr::parse_u32(&lit[6..]) {
+            Some(align) if align.is_power_of_two() => {
+                immed.align = align.trailing_zeros();
+            }
+            _ => return Err(WatError::BadLiteral(lit.to_string())),
+        }
+    }
+    Ok(immed)
+}
+
+/// The names defined in one index space.
+#[derive(Default)]
+struct Space<'a> {
+    names: HashMap<&'a str, u32>,
+    len: u32,
+}
+
+impl<'a> Space<'a> {
+    /// Allocate the next index, naming it `id`.
+    fn define(&mut self, id: Option<&'a str>) -> Result<u32, WatError> {
+        let index = self.len;
+        if let Some(id) = id {
+            if self.names.insert(id, index).is_some() {
+                return Err(WatError::DuplicateId(id.to_string()));
+            }
+        }
+        self.len += 1;
+        Ok(index)
+    }
+
+    /// Resolve a `$name` or numeric index.  Numeric indices are not
+    /// range-checked; validation does that.
+    fn resolve(&self, var: &str) -> Result<u32, WatError> {
+        if var.starts_with('$') {
+            self.names.get(var)
+                .cloned()
+                .ok_or_else(|| WatError::UnknownId(var.to_string()))
+        } else {
+            sexpr::parse_u32(var)
+                .ok_or_else(|| WatError::BadLiteral(var.to_string()))
+        }
+    }
+}
+
+/// Whether a module field is an import, either explicitly or through
+/// an inline `(import "module" "field")`.
+fn is_import(field: &Sexpr) -> bool {
+    match field.head() {
+        Some("import") => true,
+        Some("func") | Some("table") | Some("memory") | Some("global") => {
+            let mut cur = Cursor::new(&field.list().unwrap()[1..]);
+            cur.id();
+            while cur.peek_head() == Some("export") {
+                cur.next();
+            }
+            cur.peek_head() == Some("import")
+        }
+        _ => false,
+    }
+}
+
+/// Take inline `(export "name")` abbreviations.
+fn inline_exports<'a>(cur: &mut Cursor<'a>) -> Result<Vec<&'a str>, WatError> {
+    let mut names = vec![];
+    while cur.peek_head() == Some("export") {
+        let mut inner = cur.list()?;
+        names.push(inner.name()?);
+        inner.end()?;
+    }
+    Ok(names)
+}
+
+/// Take an inline `(import "module" "field")`.
+fn inline_import<'a>(cur: &mut Cursor<'a>) -> Result<Option<(&'a str, &'a str)>, WatError> {
+    if cur.peek_head() != Some("import") {
+        return Ok(None);
+    }
+
+    let mut inner = cur.list()?;
+    let module = inner.name()?;
+    let field = inner.name()?;
+    inner.end()?;
+    Ok(Some((module, field)))
+}
+
+/// A module being built from its fields.
+#[derive(Default)]
+struct Parser<'a> {
+    types: Vec<FunctionType>,
+    type_names: Space<'a>,
+    funcs: Space<'a>,
+    tables: Space<'a>,
+    memories: Space<'a>,
+    globals: Space<'a>,
+
+    /// How many of each kind the fields processed so far define.
+    seen: [u32; 4],
+
+    imports: Vec<ImportEntry<'a>>,
+    functions: Vec<u32>,
+    table_types: Vec<TableType>,
+    memory_types: Vec<MemoryType>,
+    global_vars: Vec<GlobalVariable>,
+    exports: Vec<ExportEntry<'a>>,
+    start: Option<u32>,
+    elements: Vec<ElementSegment>,
+    code: Vec<FunctionBody>,
+    data: Vec<(u32, InitExpr, Vec<u8>)>,
+}
+
+impl<'a> Parser<'a> {
+    fn space(&mut self, kind: ExternalKind) -> &mut Space<'a> {
+        match kind {
+            ExternalKind::Function => &mut self.funcs,
+            ExternalKind::Table => &mut self.tables,
+            ExternalKind::Memory => &mut self.memories,
+            ExternalKind::Global => &mut self.globals,
+        }
+    }
+
+    /// The index of the next field of `kind`.
+    fn next_index(&mut self, kind: ExternalKind) -> u32 {
+        let seen = &mut self.seen[kind as usize];
+        *seen += 1;
+        *seen - 1
+    }
+
+    /// The index of `ty`, adding it to the type section if it is new.
+    fn intern(&mut self, ty: FunctionType) -> u32 {
+        match self.types.iter().position(|t| *t == ty) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(ty);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Parse `(param ...)* (result ...)*`, returning the signature and
+    /// the parameter names.
+    fn signature(cur: &mut Cursor<'a>)
+        -> Result<(FunctionType, Vec<Option<&'a str>>), WatError>
+    {
+        let mut params = vec![];
+        let mut names = vec![];
+        let mut results = vec![];
+
+        while cur.peek_head() == Some("param") {
+            let mut inner = cur.list()?;
+            if let Some(id) = inner.id() {
+                params.push(take_value_type(&mut inner)?);
+                names.push(Some(id));
+                inner.end()?;
+            } else {
+                while !inner.at_end() {
+                    params.push(take_value_type(&mut inner)?);
+                    names.push(None);
+                }
+            }
+        }
+
+        while cur.peek_head() == Some("result") {
+            let mut inner = cur.list()?;
+            while !inner.at_end() {
+                results.push(take_value_type(&mut inner)?);
+            }
+        }
+
+        Ok((FunctionType { params, results }, names))
+    }
+
+    /// Parse `(type x)? (param ...)* (result ...)*`, returning the type
+    /// index and one name slot per parameter.
+    fn type_use(&mut self, cur: &mut Cursor<'a>)
+        -> Result<(u32, Vec<Option<&'a str>>), WatError>
+    {
+        let explicit = if cur.peek_head() == Some("type") {
+            let mut inner = cur.list()?;
+            let index = self.type_names.resolve(inner.atom()?)?;
+            inner.end()?;
+            Some(index)
+        } else {
+            None
+        };
+
+        let inline = cur.peek_head() == Some("param") || cur.peek_head() == Some("result");
+        let (ty, names) = Parser::signature(cur)?;
+
+        match explicit {
+            Some(index) => {
+                let declared = self.types.get(index as usize);
+                if inline {
+                    if declared.is_some_and(|d| *d != ty) {
+                        return Err(WatError::TypeMismatch);
+                    }
+                    Ok((index, names))
+                } else {
+                    let params = declared.map_or(0, |d| d.params.len());
+                    Ok((index, vec![None; params]))
+                }
+            }
+            None => Ok((self.intern(ty), names)),
+        }
+    }
+
+    fn export(&mut self, names: Vec<&'a str>, kind: ExternalKind, index: u32) {
+        for field in names {
+            self.exports.push(ExportEntry { field, kind, index });
+        }
+    }
+
+    /// Parse instructions, with `locals` in scope.
+    fn expr(&mut self, cur: &mut Cursor<'a>, locals: Space<'a>) -> Result<Vec<Op>, WatError> {
+        let mut body = Body { module: self, locals, labels: vec![], ops: vec![] };
+        while !cur.at_end() {
+            body.instr(cur)?;
+        }
+
+        if !body.labels.is_empty() {
+            return Err(WatError::Malformed("unclosed block"));
+        }
+        Ok(body.ops)
+    }
+
+    /// Parse `(offset instr*)`, or a single folded instruction.
+    fn offset(&mut self, cur: &mut Cursor<'a>) -> Result<InitExpr, WatError> {
+        let ops = if cur.peek_head() == Some("offset") {
+            let mut inner = cur.list()?;
+            self.expr(&mut inner, Space::default())?
+        } else {
+            let item = cur.next()
+                .ok_or(WatError::Malformed("expected an offset"))?;
+            self.expr(&mut Cursor::new(::std::slice::from_ref(item)), Space::default())?
+        };
+        Ok(InitExpr { ops })
+    }
+
+    fn type_field(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        let id = cur.id();
+        if cur.peek_head() != Some("func") {
+            return Err(WatError::Malformed("expected (func ...)"));
+        }
+        let mut func = cur.list()?;
+        let (ty, _) = Parser::signature(&mut func)?;
+        func.end()?;
+        cur.end()?;
+
+        self.type_names.define(id)?;
+        self.types.push(ty);
+        Ok(())
+    }
+
+    /// Assign indices to every function, table, memory and global, so
+    /// that fields may refer to ones defined after them.
+    fn define(&mut self, field: &'a Sexpr) -> Result<(), WatError> {
+        let items = match field.list() {
+            Some(items) => items,
+            None => return Ok(()),
+        };
+
+        // An import's description is its fourth item.
+        let desc = if field.head() == Some("import") { items.get(3) } else { Some(field) };
+        let (head, id) = match desc {
+            Some(desc) => (desc.head(), desc.list().and_then(|items| items.get(1))),
+            None => return Ok(()),
+        };
+
+        let kind = match head {
+            Some("func") => ExternalKind::Function,
+            Some("table") => ExternalKind::Table,
+            Some("memory") => ExternalKind::Memory,
+            Some("global") => ExternalKind::Global,
+            _ => return Ok(()),
+        };
+        let id = id.and_then(Sexpr::atom).filter(|a| a.starts_with('$'));
+        self.space(kind).define(id)?;
+        Ok(())
+    }
+
+    fn import(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        let module = cur.name()?;
+        let field = cur.name()?;
+        let head = cur.peek_head();
+        let mut desc = cur.list()?;
+        cur.end()?;
+        desc.id();
+
+        let kind = match head {
+            Some("func") => {
+                self.next_index(ExternalKind::Function);
+                ImportKind::Function(self.type_use(&mut desc)?.0)
+            }
+            Some("table") => {
+                self.next_index(ExternalKind::Table);
+                ImportKind::Table(table_type(&mut desc)?)
+            }
+            Some("memory") => {
+                self.next_index(ExternalKind::Memory);
+                ImportKind::Memory(MemoryType { limits: limits(&mut desc)? })
+            }
+            Some("global") => {
+                self.next_index(ExternalKind::Global);
+                ImportKind::Global(global_type(&mut desc)?)
+            }
+            _ => return Err(WatError::Malformed("expected an import description")),
+        };
+        desc.end()?;
+
+        self.imports.push(ImportEntry { module, field, kind });
+        Ok(())
+    }
+
+    fn func(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        cur.id();
+        let index = self.next_index(ExternalKind::Function);
+        let exports = inline_exports(cur)?;
+        self.export(exports, ExternalKind::Function, index);
+
+        if let Some((module, field)) = inline_import(cur)? {
+            let (ty, _) = self.type_use(cur)?;
+            cur.end()?;
+            self.imports.push(ImportEntry { module, field, kind: ImportKind::Function(ty) });
+            return Ok(());
+        }
+
+        let (ty, params) = self.type_use(cur)?;
+        let mut locals = Space::default();
+        for id in params {
+            locals.define(id)?;
+        }
+
+        let mut local_types = vec![];
+        while cur.peek_head() == Some("local") {
+            let mut inner = cur.list()?;
+            if let Some(id) = inner.id() {
+                local_types.push(take_value_type(&mut inner)?);
+                locals.define(Some(id))?;
+                inner.end()?;
+            } else {
+                while !inner.at_end() {
+                    local_types.push(take_value_type(&mut inner)?);
+                    locals.define(None)?;
+                }
+            }
+        }
+
+        let ops = self.expr(cur, locals)?;
+        self.functions.push(ty);
+        self.code.push(FunctionBody { locals: local_runs(&local_types), ops, offsets: vec![] });
+        Ok(())
+    }
+
+    fn table(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        cur.id();
+        let index = self.next_index(ExternalKind::Table);
+        let exports = inline_exports(cur)?;
+        self.export(exports, ExternalKind::Table, index);
+
+        if let Some((module, field)) = inline_import(cur)? {
+            let ty = table_type(cur)?;
+            cur.end()?;
+            self.imports.push(ImportEntry { module, field, kind: ImportKind::Table(ty) });
+            return Ok(());
+        }
+
+        if cur.peek().and_then(Sexpr::atom).and_then(sexpr::parse_u32).is_some() {
+            let ty = table_type(cur)?;
+            cur.end()?;
+            self.table_types.push(ty);
+            return Ok(());
+        }
+
+        // `(table anyfunc (elem ...))` sizes the table to fit.
+        let element_ty = elem_type(cur)?;
+        let mut elem = cur.list()?;
+        cur.end()?;
+
+        let mut elems = vec![];
+        while !elem.at_end() {
+            elems.push(self.funcs.resolve(elem.atom()?)?);
+        }
+
+        let len = elems.len() as u32;
+        self.table_types.push(TableType { element_ty, limits: ResizableLimits::InitialMax(len, len) });
+        self.elements.push(ElementSegment {
+            index,
+            offset: InitExpr { ops: vec![Op::I32Const(0)] },
+            elems,
+        });
+        Ok(())
+    }
+
+    fn memory(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        cur.id();
+        let index = self.next_index(ExternalKind::Memory);
+        let exports = inline_exports(cur)?;
+        self.export(exports, ExternalKind::Memory, index);
+
+        if let Some((module, field)) = inline_import(cur)? {
+            let limits = limits(cur)?;
+            cur.end()?;
+            self.imports.push(ImportEntry {
+                module,
+                field,
+                kind: ImportKind::Memory(MemoryType { limits }),
+            });
+            return Ok(());
+        }
+
+        if cur.peek_head() != Some("data") {
+            let limits = limits(cur)?;
+            cur.end()?;
+            self.memory_types.push(MemoryType { limits });
+            return Ok(());
+        }
+
+        // `(memory (data ...))` sizes the memory to fit.
+        let mut inner = cur.list()?;
+        cur.end()?;
+
+        let mut data = vec![];
+        while !inner.at_end() {
+            data.extend_from_slice(inner.string()?);
+        }
+
+        let pages = data.len().div_ceil(PAGE_SIZE) as u32;
+        self.memory_types.push(MemoryType { limits: ResizableLimits::InitialMax(pages, pages) });
+        self.data.push((index, InitExpr { ops: vec![Op::I32Const(0)] }, data));
+        Ok(())
+    }
+
+    fn global(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        cur.id();
+        let index = self.next_index(ExternalKind::Global);
+        let exports = inline_exports(cur)?;
+        self.export(exports, ExternalKind::Global, index);
+
+        if let Some((module, field)) = inline_import(cur)? {
+            let ty = global_type(cur)?;
+            cur.end()?;
+            self.imports.push(ImportEntry { module, field, kind: ImportKind::Global(ty) });
+            return Ok(());
+        }
+
+        let ty = global_type(cur)?;
+        let ops = self.expr(cur, Space::default())?;
+        self.global_vars.push(GlobalVariable { ty, init: InitExpr { ops } });
+        Ok(())
+    }
+
+    fn export_field(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        let field = cur.name()?;
+        let head = cur.peek_head();
+        let mut desc = cur.list()?;
+        cur.end()?;
+
+        let kind = match head {
+            Some("func") => ExternalKind::Function,
+            Some("table") => ExternalKind::Table,
+            Some("memory") => ExternalKind::Memory,
+            Some("global") => ExternalKind::Global,
+            _ => return Err(WatError::Malformed("expected an export description")),
+        };
+        let index = self.space(kind).resolve(desc.atom()?)?;
+        desc.end()?;
+
+        self.exports.push(ExportEntry { field, kind, index });
+        Ok(())
+    }
+
+    fn elem(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        let index = match cur.peek().and_then(Sexpr::atom) {
+            Some(var) => {
+                cur.next();
+                self.tables.resolve(var)?
+            }
+            None => 0,
+        };
+        let offset = self.offset(cur)?;
+
+        let mut elems = vec![];
+        while !cur.at_end() {
+            elems.push(self.funcs.resolve(cur.atom()?)?);
+        }
+
+        self.elements.push(ElementSegment { index, offset, elems });
+        Ok(())
+    }
+
+    fn data_field(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        let index = match cur.peek().and_then(Sexpr::atom) {
+            Some(var) => {
+                cur.next();
+                self.memories.resolve(var)?
+            }
+            None => 0,
+        };
+        let offset = self.offset(cur)?;
+
+        let mut data = vec![];
+        while !cur.at_end() {
+            data.extend_from_slice(cur.string()?);
+        }
+
+        self.data.push((index, offset, data));
+        Ok(())
+    }
+
+    fn field(&mut self, field: &'a Sexpr) -> Result<(), WatError> {
+        let mut cur = match field.list() {
+            Some(items) if !items.is_empty() => Cursor::new(&items[1..]),
+            _ => return Err(WatError::Malformed("expected a module field")),
+        };
+
+        match field.head() {
+            Some("type") => Ok(()),
+            Some("import") => self.import(&mut cur),
+            Some("func") => self.func(&mut cur),
+            Some("table") => self.table(&mut cur),
+            Some("memory") => self.memory(&mut cur),
+            Some("global") => self.global(&mut cur),
+            Some("export") => self.export_field(&mut cur),
+            Some("start") => {
+                let index = self.funcs.resolve(cur.atom()?)?;
+                cur.end()?;
+                if self.start.replace(index).is_some() {
+                    return Err(WatError::Malformed("multiple start functions"));
+                }
+                Ok(())
+            }
+            Some("elem") => self.elem(&mut cur),
+            Some("data") => self.data_field(&mut cur),
+            _ => Err(WatError::Malformed("expected a module field")),
+        }
+    }
+
+    fn encode(self) -> Vec<u8> {
+        let data = self.data.iter()
+            .map(|&(index, ref init, ref data)| DataSegment { index, init: init.clone(), data })
+            .collect::<Vec<_>>();
+
+        let mut sections = vec![];
+        if !self.types.is_empty() {
+            sections.push(Section::Type(self.types));
+        }
+        if !self.imports.is_empty() {
+            sections.push(Section::Import(self.imports));
+        }
+        if !self.functions.is_empty() {
+            sections.push(Section::Function(self.functions));
+        }
+        if !self.table_types.is_empty() {
+            sections.push(Section::Table(self.table_types));
+        }
+        if !self.memory_types.is_empty() {
+            sections.push(Section::Memory(self.memory_types));
+        }
+        if !self.global_vars.is_empty() {
+            sections.push(Section::Global(self.global_vars));
+        }
+        if !self.exports.is_empty() {
+            sections.push(Section::Export(self.exports));
+        }
+        if let Some(start) = self.start {
+            sections.push(Section::Start(start));
+        }
+        if !self.elements.is_empty() {
+            sections.push(Section::Element(self.elements));
+        }
+        if !self.code.is_empty() {
+            sections.push(Section::Code(self.code));
+        }
+        if !data.is_empty() {
+            sections.push(Section::Data(data));
+        }
+
+        Module::from_sections(sections).encode()
+    }
+}
+
+/// A function body or constant expression being parsed.
+struct Body<'p, 'a: 'p> {
+    module: &'p mut Parser<'a>,
+    locals: Space<'a>,
+    /// Enclosing block labels, innermost last.
+    labels: Vec<Option<&'a str>>,
+    ops: Vec<Op>,
+}
+
+impl<'p, 'a> Body<'p, 'a> {
+    /// Parse a block type: a type use, or a bare value type as older
+    /// scripts write it.  Only blocks with parameters or several results
+    /// need a type index.
+    fn block_type(&mut self, cur: &mut Cursor<'a>) -> Result<BlockType, WatError> {
+        if cur.peek_head() == Some("type") {
+            let (index, _) = self.module.type_use(cur)?;
+            return Ok(BlockType::Index(index));
+        }
+
+        if cur.peek_head() == Some("param") || cur.peek_head() == Some("result") {
+            let (ty, names) = Parser::signature(cur)?;
+            if names.iter().any(Option::is_some) {
+                return Err(WatError::Malformed("block parameters cannot be named"));
+            }
+            return Ok(match (ty.params.len(), ty.results.len()) {
+                (0, 0) => BlockType::Void,
+                (0, 1) => BlockType::Single(ty.results[0]),
+                _ => BlockType::Index(self.module.intern(ty)),
+            });
+        }
+
+        match cur.peek().and_then(Sexpr::atom).and_then(value_type) {
+            Some(ty) => {
+                cur.next();
+                Ok(BlockType::Single(ty))
+            }
+            None => Ok(BlockType::Void),
+        }
+    }
+
+    fn label(&self, var: &str) -> Result<u32, WatError> {
+        if var.starts_with('$') {
+            self.labels.iter()
+                .rev()
+                .position(|l| *l == Some(var))
+                .map(|depth| depth as u32)
+                .ok_or_else(|| WatError::UnknownId(var.to_string()))
+        } else {
+            sexpr::parse_u32(var)
+                .ok_or_else(|| WatError::BadLiteral(var.to_string()))
+        }
+    }
+
+    fn instr(&mut self, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        match cur.next() {
+            Some(Sexpr::List(items)) if !items.is_empty() => self.folded(items),
+            Some(Sexpr::Atom(name)) => self.flat(name, cur),
+            _ => Err(WatError::Malformed("expected an instruction")),
+        }
+    }
+
+    /// A flat instruction, whose name has been taken from `cur`.
+    fn flat(&mut self, name: &str, cur: &mut Cursor<'a>) -> Result<(), WatError> {
+        match name {
+            "block" | "loop" | "if" => {
+                self.labels.push(cur.id());
+                let ty = self.block_type(cur)?;
+                self.ops.push(match name {
+                    "block" => Op::Block(ty),
+                    "loop" => Op::Loop(ty),
+                    _ => Op::If(ty),
+                });
+            }
+            "else" | "end" => {
+                let label = match self.labels.last() {
+                    Some(label) => *label,
+                    None => return Err(WatError::Malformed("unbalanced block")),
+                };
+                if let Some(id) = cur.id() {
+                    if label != Some(id) {
+                        return Err(WatError::Malformed("mismatched label"));
+                    }
+                }
+
+                if name == "else" {
+                    self.ops.push(Op::Else);
+                } else {
+                    self.labels.pop();
+                    self.ops.push(Op::End);
+                }
+            }
+            _ => {
+                let op = self.plain(name, cur)?;
+                self.ops.push(op);
+            }
+        }
+        Ok(())
+    }
+
+    /// A folded instruction: `(op immediates operands*)`, or a folded
+    /// block, loop or if.
+    fn folded(&mut self, items: &'a [Sexpr]) -> Result<(), WatError> {
+        let name = items[0].atom()
+            .ok_or(WatError::Malformed("expected an instruction"))?;
+        let mut cur = Cursor::new(&items[1..]);
+
+        match name {
+            "block" | "loop" => {
+                let label = cur.id();
+                let ty = self.block_type(&mut cur)?;
+                self.ops.push(if name == "block" { Op::Block(ty) } else { Op::Loop(ty) });
+                self.labels.push(label);
+                while !cur.at_end() {
+                    self.instr(&mut cur)?;
+                }
+            }
+            "if" => {
+                let label = cur.id();
+                let ty = self.block_type(&mut cur)?;
+
+                while !cur.at_end() && cur.peek_head() != Some("then") {
+                    match cur.next() {
+                        Some(Sexpr::List(cond)) if !cond.is_empty() => self.folded(cond)?,
+                        _ => return Err(WatError::Malformed("expected a folded condition")),
+                    }
+                }
+
+                self.ops.push(Op::If(ty));
+                self.labels.push(label);
+
+                let mut then = cur.list()
+                    .map_err(|_| WatError::Malformed("expected (then ...)"))?;
+                while !then.at_end() {
+                    self.instr(&mut then)?;
+                }
+
+                if cur.peek_head() == Some("else") {
+                    let mut els = cur.list()?;
+                    self.ops.push(Op::Else);
+                    while !els.at_end() {
+                        self.instr(&mut els)?;
+                    }
+                }
+                cur.end()?;
+            }
+            _ => {
+                let op = self.plain(name, &mut cur)?;
+                while !cur.at_end() {
+                    self.instr(&mut cur)?;
+                }
+                self.ops.push(op);
+                return Ok(());
+            }
+        }
+
+        self.labels.pop();
+        self.ops.push(Op::End);
+        Ok(())
+    }
+
+    /// An instruction other than a block, taking its immediates from
+    /// `cur`.
+    fn plain(&mut self, name: &str, cur: &mut Cursor<'a>) -> Result<Op, WatError> {
+        if let Some(op) = simple_op(name) {
+            return Ok(op);
+        }
+
+        if let Some((op, natural)) = memory_op(name) {
+            return Ok(op(memory_immed(cur, natural)?));
+        }
+
+        let op = match name {
+            "br" => Op::Branch(self.label(cur.atom()?)?),
+            "br_if" => Op::BranchIf(self.label(cur.atom()?)?),
+            "br_table" => {
+                let mut targets = vec![];
+                while let Some(var) = cur.peek().and_then(Sexpr::atom).filter(|a| is_var(a)) {
+                    cur.next();
+                    targets.push(self.label(var)?);
+                }
+                let default = targets.pop()
+                    .ok_or(WatError::Malformed("br_table needs a default label"))?;
+                Op::BranchTable(BranchTable { targets, default })
+            }
+            "call" => Op::Call(self.module.funcs.resolve(cur.atom()?)?),
+            "call_indirect" => {
+                // Older scripts write the type index bare.
+                match cur.peek().and_then(Sexpr::atom).filter(|a| is_var(a)) {
+                    Some(var) => {
+                        cur.next();
+                        Op::CallIndirect(self.module.type_names.resolve(var)?)
+                    }
+                    None => {
+                        let (ty, params) = self.module.type_use(cur)?;
+                        if params.iter().any(Option::is_some) {
+                            return Err(WatError::Malformed("call_indirect params are unnamed"));
+                        }
+                        Op::CallIndirect(ty)
+                    }
+                }
+            }
+            "get_local" | "local.get" => Op::GetLocal(self.locals.resolve(cur.atom()?)?),
+            "set_local" | "local.set" => Op::SetLocal(self.locals.resolve(cur.atom()?)?),
+            "tee_local" | "local.tee" => Op::TeeLocal(self.locals.resolve(cur.atom()?)?),
+            "get_global" | "global.get" => Op::GetGlobal(self.module.globals.resolve(cur.atom()?)?),
+            "set_global" | "global.set" => Op::SetGlobal(self.module.globals.resolve(cur.atom()?)?),
+            "i32.const" => {
+                let lit = cur.atom()?;
+                Op::I32Const(sexpr::parse_i32(lit)
+                             .ok_or_else(|| WatError::BadLiteral(lit.to_string()))?)
+            }
+            "i64.const" => {
+                let lit = cur.atom()?;
+                Op::I64Const(sexpr::parse_i64(lit)
+                             .ok_or_else(|| WatError::BadLiteral(lit.to_string()))?)
+            }
+            "f32.const" => {
+                let lit = cur.atom()?;
+                Op::F32Const(sexpr::parse_f32(lit)
+                             .map(f32::from_bits)
+                             .ok_or_else(|| WatError::BadLiteral(lit.to_string()))?)
+            }
+            "f64.const" => {
+                let lit = cur.atom()?;
+                Op::F64Const(sexpr::parse_f64(lit)
+                             .map(f64::from_bits)
+                             .ok_or_else(|| WatError::BadLiteral(lit.to_string()))?)
+            }
+            _ => return Err(WatError::UnknownOperator(name.to_string())),
+        };
+        Ok(op)
+    }
+}
+
+/// Translate the items of a `(module ...)` form to the binary format.
+/// `(module binary ...)` and `(module quote ...)` are also accepted.
+pub(crate) fn module(items: &[Sexpr]) -> Result<Vec<u8>, WatError> {
+    let mut cur = Cursor::new(&items[1..]);
+    cur.id();
+
+    match cur.peek().and_then(Sexpr::atom) {
+        Some("binary") => {
+            cur.next();
+            let mut bytes = vec![];
+            while !cur.at_end() {
+                bytes.extend_from_slice(cur.string()?);
+            }
+            return Ok(bytes);
+        }
+        Some("quote") => {
+            cur.next();
+            let mut text = vec![];
+            while !cur.at_end() {
+                text.extend_from_slice(cur.string()?);
+                text.push(b' ');
+            }
+            let text = str::from_utf8(&text)
+                .map_err(|_| WatError::Malformed("quoted module must be UTF-8"))?;
+            return parse_wat(&format!("(module {})", text));
+        }
+        _ => {}
+    }
+
+    let fields = &cur.items[cur.pos..];
+    let mut parser = Parser::default();
+
+    for field in fields {
+        if field.head() == Some("type") {
+            let mut cur = Cursor::new(&field.list().unwrap()[1..]);
+            parser.type_field(&mut cur)?;
+        }
+    }
+
+    let mut defined = false;
+    for field in fields {
+        if is_import(field) {
+            if defined {
+                return Err(WatError::ImportAfterDefinition);
+            }
+        } else {
+            match field.head() {
+                Some("func") | Some("table") | Some("memory") | Some("global") => defined = true,
+                _ => {}
+            }
+        }
+        parser.define(field)?;
+    }
+
+    for field in fields {
+        parser.field(field)?;
+    }
+
+    Ok(parser.encode())
+}
+
+/// Translate a text module to the binary format, ready for
+/// `Module::decode_from`.  The text may be a `(module ...)` form or
+/// just the fields of one.
+pub fn parse_wat(text: &str) -> Result<Vec<u8>, WatError> {
+    let mut exprs: Vec<Sexpr> = sexpr::parse(text)?
+        .into_iter()
+        .map(|(_, expr)| expr)
+        .collect();
+
+    if exprs.len() == 1 && exprs[0].head() == Some("module") {
+        return module(exprs[0].list().unwrap());
+    }
+
+    exprs.insert(0, Sexpr::Atom("module".to_string()));
+    module(&exprs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instance::Instance;
+    use value::Value;
+
+    fn instantiate(text: &str) -> Instance {
+        let bytes = parse_wat(text).unwrap();
+        let module = Module::decode_from(&bytes).unwrap();
+        Instance::new(&module).unwrap()
+    }
+
+    #[test]
+    fn test_same_as_binary() {
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+            0x03, 0x02, 0x01, 0x00,
+            0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64, 0x00, 0x00,
+            0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+
+        let folded = r#"(module (func (export "add") (param i32 i32) (result i32)
+                          (i32.add (get_local 0) (get_local 1))))"#;
+        let flat = r#"(type $t (func (param i32 i32) (result i32)))
+                      (func $add (type $t) (param $a i32) (param $b i32) (result i32)
+                        local.get $a
+                        local.get $b
+                        i32.add)
+                      (export "add" (func $add))"#;
+
+        assert_eq!(parse_wat(folded), Ok(bytes.to_vec()));
+        assert_eq!(parse_wat(flat), Ok(bytes.to_vec()));
+    }
+
+    #[test]
+    fn test_control() {
+        let mut inst = instantiate(r#"
+            (module
+              (func $pick (export "pick") (param $i i32) (result i32)
+                (block $c
+                  (block $b
+                    (block $a
+                      (br_table $a $b $c (get_local $i)))
+                    (return (i32.const 10)))
+                  i32.const 11
+                  return)
+                (i32.const 12))
+              (func (export "max") (param $x i32) (param $y i32) (result i32)
+                (if (result i32) (i32.gt_s (get_local $x) (get_local $y))
+                  (then (get_local $x))
+                  (else (get_local $y))))
+              (func (export "count") (param $n i32) (result i32) (local $i i32)
+                loop $top
+                  get_local $i
+                  i32.const 1
+                  i32.add
+                  tee_local $i
+                  get_local $n
+                  i32.lt_s
+                  br_if $top
+                end $top
+                get_local $i))
+        "#);
+
+        let call = |inst: &mut Instance, name, args: &[i32]| {
+            let args: Vec<_> = args.iter().map(|a| Value::I32(*a)).collect();
+            inst.invoke(name, &args)
+        };
+        assert_eq!(call(&mut inst, "pick", &[0]), Ok(vec![Value::I32(10)]));
+        assert_eq!(call(&mut inst, "pick", &[1]), Ok(vec![Value::I32(11)]));
+        assert_eq!(call(&mut inst, "pick", &[7]), Ok(vec![Value::I32(12)]));
+        assert_eq!(call(&mut inst, "max", &[3, -4]), Ok(vec![Value::I32(3)]));
+        assert_eq!(call(&mut inst, "max", &[3, 4]), Ok(vec![Value::I32(4)]));
+        assert_eq!(call(&mut inst, "count", &[5]), Ok(vec![Value::I32(5)]));
+    }
+
+    #[test]
+    fn test_abbreviations() {
+        let mut inst = instantiate(r#"
+            (module
+              (global $g (export "g") (mut i32) (i32.const 3))
+              (memory (export "mem") (data "\2a\00\00\00hello"))
+              (table anyfunc (elem $seven $load))
+              (func $seven (result i32) (i32.const 7))
+              (func $load (result i32) (i32.load offset=0 align=4 (i32.const 0)))
+              (func (export "call") (param i32) (result i32)
+                (call_indirect (result i32) (get_local 0)))
+              (func (export "bump")
+                (set_global $g (i32.add (get_global $g) (i32.const 1)))))
+        "#);
+
+        assert_eq!(inst.invoke("call", &[Value::I32(0)]), Ok(vec![Value::I32(7)]));
+        assert_eq!(inst.invoke("call", &[Value::I32(1)]), Ok(vec![Value::I32(42)]));
+        assert_eq!(inst.invoke("bump", &[]), Ok(vec![]));
+        assert_eq!(inst.global("g"), Some(Value::I32(4)));
+        assert_eq!(&inst.memory().data()[4..9], b"hello");
+        assert_eq!(inst.memory().len_pages(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_wat("(module (func (call $f)))"),
+                   Err(WatError::UnknownId("$f".to_string())));
+        assert_eq!(parse_wat("(module (func $f) (func $f))"),
+                   Err(WatError::DuplicateId("$f".to_string())));
+        assert_eq!(parse_wat(r#"(module (func) (import "m" "f" (func)))"#),
+                   Err(WatError::ImportAfterDefinition));
+        assert_eq!(parse_wat("(module (func (i32.frob)))"),
+                   Err(WatError::UnknownOperator("i32.frob".to_string())));
+        assert_eq!(parse_wat("(module (func (i32.const 0x1_0000_0000)))"),
+                   Err(WatError::BadLiteral("0x1_0000_0000".to_string())));
+        assert_eq!(parse_wat("(module (type (func)) (func (type 0) (param i32)))"),
+                   Err(WatError::TypeMismatch));
+        assert!(parse_wat("(module (func block))").is_err());
+        assert!(parse_wat("(module (func").is_err());
+    }
+}